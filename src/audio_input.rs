@@ -218,3 +218,40 @@ impl AudioInput {
         Ok(())
     }
 }
+
+/// Average the channels of interleaved samples into mono
+pub fn downmix(data: &[f32], channels: u16) -> Vec<f32> {
+    if channels <= 1 {
+        return data.to_vec();
+    }
+    data.chunks_exact(channels as usize)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect()
+}
+
+/// Convert samples to 16-bit little-endian PCM, the format both STT providers take
+pub fn to_pcm16(samples: &[f32]) -> Vec<u8> {
+    samples
+        .iter()
+        .flat_map(|&sample| {
+            let pcm_sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            pcm_sample.to_le_bytes()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_downmix() {
+        assert_eq!(downmix(&[0.5, -0.5, 1.0], 1), [0.5, -0.5, 1.0]);
+        assert_eq!(downmix(&[0.5, 0.25, 1.0, 0.0], 2), [0.375, 0.5]);
+    }
+
+    #[test]
+    fn test_to_pcm16() {
+        assert_eq!(to_pcm16(&[0.0, 1.0, -2.0]), [0, 0, 0xff, 0x7f, 0x01, 0x80]);
+    }
+}
@@ -0,0 +1,95 @@
+//! Serves scripted Flux responses on a local port, to try voice-keyboard without network
+//! access:
+//!
+//! ```bash
+//! cargo run --features mock-server --bin mock-flux-server -- --script script.json
+//! sudo -E voice-keyboard --test-stt --stt-url ws://127.0.0.1:8765/v2/listen
+//! ```
+
+use anyhow::{Context, Result};
+use clap::{Arg, Command};
+use std::time::Duration;
+use tracing::{error, info};
+
+// The test helpers of the module are not used here
+#[allow(dead_code)]
+#[path = "../mock_flux.rs"]
+mod mock_flux;
+
+use mock_flux::{MockFluxServer, Step};
+
+// Two turns, the second with an eager end of turn that is taken back
+fn demo_script() -> Vec<Step> {
+    vec![
+        Step::connected(),
+        // One second of 16 kHz audio
+        Step::WaitForAudio(32_000),
+        Step::turn_info("StartOfTurn", 0, "hello"),
+        Step::sleep(Duration::from_millis(300)),
+        Step::turn_info("Update", 0, "hello world"),
+        Step::sleep(Duration::from_millis(300)),
+        Step::turn_info("EndOfTurn", 0, "Hello world."),
+        Step::WaitForAudio(96_000),
+        Step::turn_info("StartOfTurn", 1, "this is"),
+        Step::sleep(Duration::from_millis(300)),
+        Step::turn_info("EagerEndOfTurn", 1, "This is a test."),
+        Step::sleep(Duration::from_millis(300)),
+        Step::turn_info("TurnResumed", 1, "this is a test of"),
+        Step::sleep(Duration::from_millis(300)),
+        Step::turn_info("EndOfTurn", 1, "This is a test of the mock server."),
+        Step::WaitForCloseStream,
+    ]
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let matches = Command::new("mock-flux-server")
+        .about("Local stand-in for the Deepgram Flux API that plays scripted responses")
+        .arg(
+            Arg::new("listen")
+                .long("listen")
+                .value_name("ADDRESS")
+                .default_value("127.0.0.1:8765")
+                .help("Address to listen on"),
+        )
+        .arg(
+            Arg::new("script")
+                .long("script")
+                .value_name("FILE")
+                .help("JSON file with one list of steps per connection, played in turn (default: a short demo)"),
+        )
+        .get_matches();
+
+    let scripts = match matches.get_one::<String>("script") {
+        Some(path) => {
+            let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+            match serde_json::from_str::<Vec<Vec<Step>>>(&text) {
+                Ok(scripts) => scripts,
+                Err(e) => {
+                    error!("Error: invalid script {}: {}", path, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => vec![demo_script()],
+    };
+
+    let listen = matches.get_one::<String>("listen").unwrap();
+    let server = MockFluxServer::bind(listen, scripts, true).await?;
+    info!("Mock Flux server ready, use --stt-url {}", server.url());
+
+    tokio::signal::ctrl_c().await?;
+    for (index, log) in server.connections().iter().enumerate() {
+        info!(
+            "Connection {}: {} bytes of audio, CloseStream {}, {} other messages, failures: {:?}",
+            index,
+            log.audio.len(),
+            if log.close_stream { "received" } else { "not received" },
+            log.messages.len(),
+            log.failures
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,276 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::env;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use tracing::debug;
+
+use crate::input_event::*;
+
+/// Shortcut sent to paste the clipboard into the focused application
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PasteShortcut {
+    #[default]
+    CtrlV,
+    /// Terminal emulators
+    CtrlShiftV,
+    /// Works in most terminals and X11 applications
+    ShiftInsert,
+}
+
+impl PasteShortcut {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "ctrl-v" => Ok(PasteShortcut::CtrlV),
+            "ctrl-shift-v" => Ok(PasteShortcut::CtrlShiftV),
+            "shift-insert" => Ok(PasteShortcut::ShiftInsert),
+            other => Err(anyhow!(
+                "invalid paste shortcut '{}': must be 'ctrl-v', 'ctrl-shift-v' or 'shift-insert'",
+                other
+            )),
+        }
+    }
+
+    /// Modifier keys and the key to press while holding them
+    pub fn keys(&self) -> (&'static [u16], u16) {
+        match self {
+            PasteShortcut::CtrlV => (&[KEY_LEFTCTRL], KEY_V),
+            PasteShortcut::CtrlShiftV => (&[KEY_LEFTCTRL, KEY_LEFTSHIFT], KEY_V),
+            PasteShortcut::ShiftInsert => (&[KEY_LEFTSHIFT], KEY_INSERT),
+        }
+    }
+}
+
+/// What the clipboard held before pasting, to put it back afterwards
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardContents {
+    Empty,
+    Text(Vec<u8>),
+    /// Images, files and other data that can't be saved as text and restored
+    Other,
+}
+
+/// Clipboard access through external copy/paste commands (wl-copy, xclip, ...)
+#[derive(Debug, Clone)]
+pub struct Clipboard {
+    copy_command: String,
+    paste_command: String,
+    /// Prints the offered MIME types or X11 targets, one per line
+    types_command: Option<String>,
+    /// Empties the clipboard (default: copy nothing)
+    clear_command: Option<String>,
+}
+
+// X11 selection targets that hold text or describe the selection itself
+const TEXT_TARGETS: &[&str] = &[
+    "COMPOUND_TEXT",
+    "MULTIPLE",
+    "SAVE_TARGETS",
+    "STRING",
+    "TARGETS",
+    "TEXT",
+    "TIMESTAMP",
+    "UTF8_STRING",
+];
+
+/// Whether a clipboard offering these types holds plain text: some text type and no
+/// images or files (file managers offer those as `text/uri-list` too)
+fn is_text(types: &str) -> bool {
+    let types: Vec<&str> = types
+        .lines()
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .collect();
+    let has_text = types.iter().any(|t| {
+        t.starts_with("text/") && !t.starts_with("text/uri-list") || TEXT_TARGETS.contains(t)
+    });
+    let has_other = types.iter().any(|t| {
+        t.starts_with("image/") || t.starts_with("text/uri-list") || t.starts_with("x-special/")
+    });
+    has_text && !has_other
+}
+
+impl Clipboard {
+    /// Use the given shell commands: `copy_command` reads the new contents on stdin,
+    /// `paste_command` prints the current contents on stdout
+    pub fn with_commands(copy_command: &str, paste_command: &str) -> Self {
+        Self {
+            copy_command: copy_command.to_string(),
+            paste_command: paste_command.to_string(),
+            types_command: None,
+            clear_command: None,
+        }
+    }
+
+    /// Check the offered types before reading, so images and files are recognised
+    pub fn with_types_command(mut self, types_command: &str) -> Self {
+        self.types_command = Some(types_command.to_string());
+        self
+    }
+
+    /// Empty the clipboard with this command instead of copying nothing
+    pub fn with_clear_command(mut self, clear_command: &str) -> Self {
+        self.clear_command = Some(clear_command.to_string());
+        self
+    }
+
+    /// Pick wl-clipboard on Wayland and xclip on X11
+    pub fn detect() -> Option<Self> {
+        if env::var("WAYLAND_DISPLAY").is_ok_and(|v| !v.is_empty()) {
+            Some(
+                Self::with_commands("wl-copy", "wl-paste --no-newline")
+                    .with_types_command("wl-paste --list-types")
+                    .with_clear_command("wl-copy --clear"),
+            )
+        } else if env::var("DISPLAY").is_ok_and(|v| !v.is_empty()) {
+            Some(
+                Self::with_commands(
+                    "xclip -selection clipboard -in",
+                    "xclip -selection clipboard -out",
+                )
+                .with_types_command("xclip -selection clipboard -out -target TARGETS"),
+            )
+        } else {
+            None
+        }
+    }
+
+    /// Read the current clipboard contents
+    pub fn read(&self) -> Result<ClipboardContents> {
+        if let Some(types_command) = &self.types_command {
+            match Self::output(types_command)? {
+                None => return Ok(ClipboardContents::Empty),
+                Some(types) if !is_text(&String::from_utf8_lossy(&types)) => {
+                    debug!("Clipboard offers non-text types: {}", String::from_utf8_lossy(&types).trim());
+                    return Ok(ClipboardContents::Other);
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(match Self::output(&self.paste_command)? {
+            Some(contents) => ClipboardContents::Text(contents),
+            None => ClipboardContents::Empty,
+        })
+    }
+
+    // Output of a clipboard command, None if it failed or printed nothing
+    fn output(command: &str) -> Result<Option<Vec<u8>>> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .context(format!("Failed to run clipboard command '{}'", command))?;
+
+        // wl-paste and xclip exit non-zero when the clipboard is empty
+        if !output.status.success() || output.stdout.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(output.stdout))
+    }
+
+    /// Empty the clipboard
+    pub fn clear(&self) -> Result<()> {
+        let Some(clear_command) = &self.clear_command else {
+            return self.write(b"");
+        };
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(clear_command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .context(format!("Failed to run clipboard command '{}'", clear_command))?;
+        if !status.success() {
+            bail!("Clipboard command '{}' failed with {}", clear_command, status);
+        }
+        Ok(())
+    }
+
+    /// Replace the clipboard contents
+    pub fn write(&self, contents: &[u8]) -> Result<()> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.copy_command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context(format!("Failed to run clipboard command '{}'", self.copy_command))?;
+
+        child
+            .stdin
+            .take()
+            .context("Clipboard command has no stdin")?
+            .write_all(contents)
+            .context("Failed to write clipboard contents")?;
+
+        // wl-copy and xclip fork a background process that serves the selection,
+        // so the command itself returns once the contents are handed over
+        let status = child.wait().context("Failed to wait for clipboard command")?;
+        if !status.success() {
+            bail!("Clipboard command '{}' failed with {}", self.copy_command, status);
+        }
+
+        debug!("Placed {} bytes on the clipboard", contents.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paste_shortcut_names() {
+        assert_eq!(PasteShortcut::from_name("ctrl-v").unwrap(), PasteShortcut::CtrlV);
+        assert_eq!(
+            PasteShortcut::from_name("ctrl-shift-v").unwrap().keys(),
+            (&[KEY_LEFTCTRL, KEY_LEFTSHIFT][..], KEY_V)
+        );
+        assert!(PasteShortcut::from_name("cmd-v").is_err());
+    }
+
+    #[test]
+    fn test_clipboard_commands_roundtrip() {
+        let path = std::env::temp_dir().join(format!("voice-keyboard-clipboard-{}", std::process::id()));
+        let path = path.display();
+        let clipboard =
+            Clipboard::with_commands(&format!("cat > {path}"), &format!("cat {path}"));
+
+        clipboard.write("héllo\nworld".as_bytes()).unwrap();
+        assert_eq!(
+            clipboard.read().unwrap(),
+            ClipboardContents::Text("héllo\nworld".as_bytes().to_vec())
+        );
+
+        clipboard.clear().unwrap();
+        assert_eq!(clipboard.read().unwrap(), ClipboardContents::Empty);
+
+        // The offered types decide whether the contents can be restored
+        let clipboard = clipboard.with_types_command("printf 'image/png\\n'");
+        assert_eq!(clipboard.read().unwrap(), ClipboardContents::Other);
+        let clipboard = clipboard.with_types_command("true");
+        assert_eq!(clipboard.read().unwrap(), ClipboardContents::Empty);
+
+        let _ = std::fs::remove_file(path.to_string());
+    }
+
+    #[test]
+    fn test_clipboard_text_types() {
+        assert!(is_text("text/plain;charset=utf-8\ntext/plain\nUTF8_STRING\n"));
+        assert!(is_text("TARGETS\nTIMESTAMP\nUTF8_STRING\nSTRING\n"));
+        // Office suites offer their own formats next to the text
+        assert!(is_text(
+            "application/x-openoffice-embed-source-xml\ntext/plain;charset=utf-8\n"
+        ));
+
+        assert!(!is_text("image/png\n"));
+        assert!(!is_text("TARGETS\nimage/png\ntext/html\n"));
+        // Copied files
+        assert!(!is_text("x-special/gnome-copied-files\ntext/uri-list\ntext/plain\n"));
+        assert!(!is_text("application/octet-stream\n"));
+        assert!(!is_text(""));
+    }
+}
@@ -0,0 +1,321 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::spoken_punctuation::Spacing::{self, *};
+use crate::text_transform::TextTransformer;
+use crate::voice_commands::VoiceCommands;
+
+/// Whether programmer dictation is on; clones share the same switch, so voice commands,
+/// D-Bus and the text pipeline all see the same state
+#[derive(Debug, Clone, Default)]
+pub struct CodeMode {
+    enabled: Arc<AtomicBool>,
+}
+
+impl CodeMode {
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+}
+
+/// How the words after a case-style command are joined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CaseStyle {
+    Camel,
+    Pascal,
+    Snake,
+    Kebab,
+    Constant,
+    AllCaps,
+    NoSpace,
+}
+
+const STYLES: &[(&str, CaseStyle)] = &[
+    ("camel case", CaseStyle::Camel),
+    ("pascal case", CaseStyle::Pascal),
+    ("snake case", CaseStyle::Snake),
+    ("kebab case", CaseStyle::Kebab),
+    ("constant case", CaseStyle::Constant),
+    ("all caps", CaseStyle::AllCaps),
+    ("no space", CaseStyle::NoSpace),
+];
+
+// Spoken symbol names; a quote symbol opens or closes a string depending on what came before
+const SYMBOLS: &[(&str, &str, Spacing)] = &[
+    ("dot", ".", Both),
+    ("comma", ",", Left),
+    ("colon", ":", Left),
+    ("double colon", "::", Both),
+    ("semicolon", ";", Left),
+    ("semi colon", ";", Left),
+    ("arrow", "->", Spaced),
+    ("fat arrow", "=>", Spaced),
+    ("equals", "=", Spaced),
+    ("equals equals", "==", Spaced),
+    ("double equals", "==", Spaced),
+    ("triple equals", "===", Spaced),
+    ("not equals", "!=", Spaced),
+    ("plus equals", "+=", Spaced),
+    ("minus equals", "-=", Spaced),
+    ("less than", "<", Spaced),
+    ("greater than", ">", Spaced),
+    ("less than or equal", "<=", Spaced),
+    ("greater than or equal", ">=", Spaced),
+    ("plus", "+", Spaced),
+    ("minus", "-", Spaced),
+    ("times", "*", Spaced),
+    ("star", "*", Spaced),
+    ("slash", "/", Spaced),
+    ("percent", "%", Spaced),
+    ("and and", "&&", Spaced),
+    ("or or", "||", Spaced),
+    ("pipe", "|", Spaced),
+    ("ampersand", "&", Right),
+    ("bang", "!", Right),
+    ("question mark", "?", Left),
+    ("open paren", "(", Both),
+    ("close paren", ")", Left),
+    ("open bracket", "[", Both),
+    ("close bracket", "]", Left),
+    ("open brace", "{", Spaced),
+    ("close brace", "}", Spaced),
+    ("open angle", "<", Both),
+    ("close angle", ">", Left),
+    ("underscore", "_", Both),
+    ("dash", "-", Both),
+    ("hash", "#", Right),
+    ("dollar", "$", Right),
+    ("at sign", "@", Right),
+    ("tilde", "~", Right),
+    ("caret", "^", Spaced),
+    ("backtick", "`", Both),
+    ("quote", "\"", Spaced),
+    ("single quote", "'", Spaced),
+];
+
+/// Formats dictated code: "camel case user id equals equals none" -> `userId == none`
+///
+/// A case style applies to the words after it, up to the next symbol, style or voice
+/// command. Other words are lowercased with the provider's punctuation removed, so
+/// only spoken symbols end up in the code.
+#[derive(Debug, Clone, Default)]
+pub struct CodeFormatter {
+    commands: VoiceCommands,
+}
+
+impl CodeFormatter {
+    /// Words starting one of `commands` are left for the keyboard to run
+    pub fn new(commands: VoiceCommands) -> Self {
+        Self { commands }
+    }
+}
+
+impl TextTransformer for CodeFormatter {
+    fn transform(&self, text: &str) -> String {
+        let words: Vec<String> = text
+            .split_whitespace()
+            .map(|w| w.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase())
+            .filter(|w| !w.is_empty())
+            .collect();
+        let words: Vec<&str> = words.iter().map(|w| w.as_str()).collect();
+
+        let mut output = Output::default();
+        let mut style = None;
+        let mut group: Vec<&str> = Vec::new();
+
+        let mut i = 0;
+        while i < words.len() {
+            let rest = &words[i..];
+            let symbol = longest_match(SYMBOLS.iter().map(|&(name, symbol, spacing)| (name, (symbol, spacing))), rest);
+            let new_style = longest_match(STYLES.iter().copied(), rest);
+
+            if symbol.is_none() && new_style.is_none() && !self.commands.starts_command(rest) {
+                match style {
+                    Some(_) => group.push(words[i]),
+                    None => output.push(words[i], Spaced),
+                }
+                i += 1;
+                continue;
+            }
+
+            if let Some(style) = style.take() {
+                output.push(&apply_style(style, &group), Spaced);
+                group.clear();
+            }
+
+            if let Some(((symbol, spacing), len)) = symbol {
+                output.push_symbol(symbol, spacing);
+                i += len;
+            } else if let Some((next, len)) = new_style {
+                style = Some(next);
+                i += len;
+            } else {
+                // A voice command: keep its words plain so the keyboard still recognizes it
+                output.push(words[i], Spaced);
+                i += 1;
+            }
+        }
+
+        if let Some(style) = style {
+            output.push(&apply_style(style, &group), Spaced);
+        }
+        output.text
+    }
+}
+
+#[derive(Default)]
+struct Output {
+    text: String,
+    // Whether the next token attaches to what came before
+    glue_next: bool,
+    open_quotes: Vec<&'static str>,
+}
+
+impl Output {
+    fn push(&mut self, token: &str, spacing: Spacing) {
+        if token.is_empty() {
+            return;
+        }
+        let glue = self.glue_next || matches!(spacing, Left | Both);
+        if !self.text.is_empty() && !glue {
+            self.text.push(' ');
+        }
+        self.text.push_str(token);
+        self.glue_next = matches!(spacing, Right | Both);
+    }
+
+    fn push_symbol(&mut self, symbol: &'static str, spacing: Spacing) {
+        if symbol != "\"" && symbol != "'" {
+            return self.push(symbol, spacing);
+        }
+        // The first quote opens a string, the matching one closes it
+        if self.open_quotes.last() == Some(&symbol) {
+            self.open_quotes.pop();
+            self.push(symbol, Left);
+        } else {
+            self.open_quotes.push(symbol);
+            self.push(symbol, Right);
+        }
+    }
+}
+
+// Longest entry whose name starts `words`, and how many words it spans
+fn longest_match<T>(entries: impl Iterator<Item = (&'static str, T)>, words: &[&str]) -> Option<(T, usize)> {
+    entries
+        .filter_map(|(name, value)| {
+            let len = name.split(' ').count();
+            let matches = len <= words.len() && name.split(' ').zip(words).all(|(n, w)| n == *w);
+            matches.then_some((value, len))
+        })
+        .max_by_key(|&(_, len)| len)
+}
+
+fn apply_style(style: CaseStyle, words: &[&str]) -> String {
+    let capitalize = |word: &str| {
+        let mut chars = word.chars();
+        chars
+            .next()
+            .map_or(String::new(), |first| first.to_uppercase().chain(chars).collect())
+    };
+
+    match style {
+        CaseStyle::Camel => words
+            .iter()
+            .enumerate()
+            .map(|(i, w)| if i == 0 { w.to_string() } else { capitalize(w) })
+            .collect(),
+        CaseStyle::Pascal => words.iter().map(|w| capitalize(w)).collect(),
+        CaseStyle::Snake => words.join("_"),
+        CaseStyle::Kebab => words.join("-"),
+        CaseStyle::Constant => words.join("_").to_uppercase(),
+        CaseStyle::AllCaps => words.join(" ").to_uppercase(),
+        CaseStyle::NoSpace => words.concat(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_case_styles() {
+        let formatter = CodeFormatter::default();
+        let test_cases = vec![
+            ("camel case user id", "userId"),
+            ("Pascal case user id.", "UserId"),
+            ("snake case user id", "user_id"),
+            ("kebab case user id", "user-id"),
+            ("constant case max size", "MAX_SIZE"),
+            ("all caps read me", "READ ME"),
+            ("no space foo bar", "foobar"),
+            ("let camel case is valid equals true", "let isValid = true"),
+            ("camel case first snake case second", "first second"),
+            ("camel case", ""),
+        ];
+
+        for (input, expected) in test_cases {
+            assert_eq!(formatter.transform(input), expected, "For: '{}'", input);
+        }
+    }
+
+    #[test]
+    fn test_symbols() {
+        let formatter = CodeFormatter::default();
+        let test_cases = vec![
+            ("self dot name", "self.name"),
+            ("std double colon io", "std::io"),
+            ("x equals equals y", "x == y"),
+            ("a not equals b", "a != b"),
+            ("fn main open paren close paren arrow int", "fn main() -> int"),
+            ("if x open brace", "if x {"),
+            ("items open bracket zero close bracket semicolon", "items[zero];"),
+            ("print open paren quote hello quote close paren", "print(\"hello\")"),
+            ("x equals quote hi quote", "x = \"hi\""),
+            ("bang done and and ready", "!done && ready"),
+            ("camel case user id dot camel case get name", "userId.getName"),
+        ];
+
+        for (input, expected) in test_cases {
+            assert_eq!(formatter.transform(input), expected, "For: '{}'", input);
+        }
+    }
+
+    #[test]
+    fn test_commands_stay_plain() {
+        let formatter = CodeFormatter::new(VoiceCommands::default());
+        assert_eq!(formatter.transform("camel case user id new line"), "userId new line");
+        assert_eq!(formatter.transform("Snake case file name. Press control S."), "file_name press control s");
+        assert_eq!(formatter.transform("Code mode off."), "code mode off");
+    }
+
+    #[test]
+    fn test_incremental() {
+        // Growing transcripts only append, apart from multi-word symbols completing
+        let formatter = CodeFormatter::default();
+        let updates = [
+            ("camel case", ""),
+            ("camel case user", "user"),
+            ("camel case user id", "userId"),
+            ("camel case user id equals", "userId ="),
+            ("camel case user id equals equals", "userId =="),
+            ("camel case user id equals equals none", "userId == none"),
+        ];
+
+        for (input, expected) in updates {
+            assert_eq!(formatter.transform(input), expected, "For: '{}'", input);
+        }
+    }
+
+    #[test]
+    fn test_code_mode_switch_is_shared() {
+        let mode = CodeMode::default();
+        let clone = mode.clone();
+        assert!(!mode.is_enabled());
+        clone.set_enabled(true);
+        assert!(mode.is_enabled());
+    }
+}
@@ -0,0 +1,335 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use tracing::{debug, info};
+
+use crate::speech_recognizer::SttProvider;
+
+/// Settings read from `~/.config/voice-keyboard/config.toml`
+/// Every field is optional; command line flags take precedence
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub keyboard: KeyboardConfig,
+    pub output: OutputConfig,
+    pub commands: CommandsConfig,
+    pub spoken_punctuation: SpokenPunctuationConfig,
+    pub transform: TransformConfig,
+    pub hotkeys: HotkeysConfig,
+    /// The section `[hotkeys]` replaced; still read, `[hotkeys]` wins where both are set
+    pub push_to_talk: PushToTalkConfig,
+    pub session: SessionConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyboardConfig {
+    /// XKB layout name, e.g. "de" or "us(dvorak)"
+    pub layout: Option<String>,
+    /// XKB variant name, e.g. "nodeadkeys"
+    pub variant: Option<String>,
+    pub unicode_input: Option<String>,
+    pub compose_key: Option<String>,
+    pub compose_table: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    /// "type" or "paste"
+    pub mode: Option<String>,
+    /// "ctrl-v", "ctrl-shift-v" or "shift-insert"
+    pub paste_shortcut: Option<String>,
+    /// Shell command that reads new clipboard contents on stdin
+    pub copy_command: Option<String>,
+    /// Shell command that prints the clipboard contents
+    pub paste_command: Option<String>,
+    /// "auto", "space" or "none"
+    pub turn_separator: Option<String>,
+    /// Fit the first letter of a turn to the sentence before it
+    pub sentence_case: Option<bool>,
+    /// Live mode types a word once it has stayed the same for this many updates
+    pub stable_updates: Option<usize>,
+    /// ...or once the provider's confidence in it reaches this level
+    pub stable_confidence: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    /// "flush" or "cancel" the session in progress on Ctrl+C or SIGTERM
+    pub on_shutdown: Option<String>,
+    /// Seconds to wait for the final transcript when listening is toggled off
+    pub stop_timeout: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CommandsConfig {
+    pub mid_turn: Option<bool>,
+    pub delay_mode: Option<bool>,
+    /// Spoken phrase to action, e.g. "save file" = "ctrl+s"; "" disables a built-in
+    pub phrases: HashMap<String, String>,
+}
+
+/// Whether spoken punctuation is converted, keyed by STT provider name, e.g. `rest = true`
+#[derive(Debug, Default, Deserialize)]
+#[serde(transparent)]
+pub struct SpokenPunctuationConfig(HashMap<String, bool>);
+
+impl SpokenPunctuationConfig {
+    pub fn for_provider(&self, provider: SttProvider) -> Option<bool> {
+        self.0.get(provider.name()).copied()
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransformConfig {
+    /// Text post-processing stages in the order they run
+    pub stages: Option<Vec<String>>,
+    /// Words dropped by the "filler_words" stage
+    pub filler_words: Option<Vec<String>>,
+    /// Regex pattern to replacement, applied in the order they are written
+    #[serde(deserialize_with = "ordered_table")]
+    pub dictionary: Vec<(String, String)>,
+    /// Words with a fixed spelling, e.g. "GitHub"
+    pub case: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HotkeysConfig {
+    /// Key to hold while talking, e.g. "f13" or "rightctrl"
+    pub push_to_talk: Option<String>,
+    /// Combo that toggles listening, e.g. "ctrl+alt+v"
+    pub toggle: Option<String>,
+    /// Combo that cancels the recording without transcribing it
+    pub cancel: Option<String>,
+    /// Input device to watch, e.g. "/dev/input/by-id/...-event-kbd" (default: all keyboards)
+    pub device: Option<String>,
+    /// Take the keyboards exclusively so hotkeys never reach applications
+    pub grab: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PushToTalkConfig {
+    pub key: Option<String>,
+    pub device: Option<String>,
+    pub grab: Option<bool>,
+}
+
+// A table of strings as its entries in file order, which a map would lose
+fn ordered_table<'de, D>(deserializer: D) -> std::result::Result<Vec<(String, String)>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    struct EntriesVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for EntriesVisitor {
+        type Value = Vec<(String, String)>;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a table of strings")
+        }
+
+        fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> std::result::Result<Self::Value, A::Error> {
+            let mut entries = Vec::new();
+            while let Some(entry) = map.next_entry()? {
+                entries.push(entry);
+            }
+            Ok(entries)
+        }
+    }
+
+    deserializer.deserialize_map(EntriesVisitor)
+}
+
+impl Config {
+    /// Default config location, following the XDG base directory spec
+    pub fn default_path() -> Option<PathBuf> {
+        let base = match env::var("XDG_CONFIG_HOME") {
+            Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var("HOME").ok()?).join(".config"),
+        };
+        Some(base.join("voice-keyboard").join("config.toml"))
+    }
+
+    /// Load the config from an explicit path (must exist) or the default path (optional)
+    pub fn load(path: Option<&str>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(p) => (PathBuf::from(p), true),
+            None => match Self::default_path() {
+                Some(p) => (p, false),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        if !required && !path.exists() {
+            debug!("No config file at {:?}, using defaults", path);
+            return Ok(Self::default());
+        }
+
+        let contents = std::fs::read_to_string(&path)
+            .context(format!("Failed to read config file {:?}", path))?;
+        let config = Self::parse(&contents).context(format!("Invalid config file {:?}", path))?;
+        info!("Loaded config from {:?}", path);
+        Ok(config)
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        let mut config: Self = toml::from_str(contents)?;
+        let push_to_talk = std::mem::take(&mut config.push_to_talk);
+        let hotkeys = &mut config.hotkeys;
+        hotkeys.push_to_talk = hotkeys.push_to_talk.take().or(push_to_talk.key);
+        hotkeys.device = hotkeys.device.take().or(push_to_talk.device);
+        hotkeys.grab = hotkeys.grab.or(push_to_talk.grab);
+        for name in config.spoken_punctuation.0.keys() {
+            SttProvider::from_name(name).context("Invalid [spoken_punctuation] section")?;
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_keyboard_section() {
+        let config = Config::parse(
+            r#"
+            [keyboard]
+            layout = "de"
+            variant = "nodeadkeys"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.keyboard.layout.as_deref(), Some("de"));
+        assert_eq!(config.keyboard.variant.as_deref(), Some("nodeadkeys"));
+        assert_eq!(config.keyboard.unicode_input, None);
+    }
+
+    #[test]
+    fn test_parse_output_section() {
+        let config = Config::parse(
+            r#"
+            [output]
+            mode = "paste"
+            paste_shortcut = "ctrl-shift-v"
+            turn_separator = "none"
+            sentence_case = false
+            stable_updates = 3
+            stable_confidence = 0.9
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.output.mode.as_deref(), Some("paste"));
+        assert_eq!(config.output.paste_shortcut.as_deref(), Some("ctrl-shift-v"));
+        assert!(config.output.copy_command.is_none());
+        assert_eq!(config.output.turn_separator.as_deref(), Some("none"));
+        assert_eq!(config.output.sentence_case, Some(false));
+        assert_eq!(config.output.stable_updates, Some(3));
+        assert_eq!(config.output.stable_confidence, Some(0.9));
+    }
+
+    #[test]
+    fn test_parse_commands_section() {
+        let config = Config::parse(
+            r#"
+            [commands]
+            mid_turn = true
+
+            [commands.phrases]
+            "save file" = "ctrl+s"
+            "tab" = ""
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.commands.mid_turn, Some(true));
+        assert_eq!(config.commands.delay_mode, None);
+        assert_eq!(config.commands.phrases["save file"], "ctrl+s");
+        assert_eq!(config.commands.phrases["tab"], "");
+    }
+
+    #[test]
+    fn test_parse_spoken_punctuation_section() {
+        let config = Config::parse("[spoken_punctuation]\nrest = false\nwebsocket = true").unwrap();
+        assert_eq!(config.spoken_punctuation.for_provider(SttProvider::WebSocket), Some(true));
+        assert_eq!(config.spoken_punctuation.for_provider(SttProvider::Rest), Some(false));
+
+        let config = Config::parse("[spoken_punctuation]\nrest = true").unwrap();
+        assert_eq!(config.spoken_punctuation.for_provider(SttProvider::WebSocket), None);
+        assert!(Config::parse("[spoken_punctuation]\ngrpc = true").is_err());
+    }
+
+    #[test]
+    fn test_parse_transform_section() {
+        let config = Config::parse(
+            r#"
+            [transform]
+            stages = ["filler_words", "whitespace"]
+            case = ["GitHub"]
+
+            [transform.dictionary]
+            '\bgit hub\b' = "GitHub"
+            'teh' = "the"
+            '(\d+) percent' = "$1%"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.transform.stages, Some(vec!["filler_words".to_string(), "whitespace".to_string()]));
+        assert_eq!(config.transform.filler_words, None);
+        // In file order, not sorted
+        let patterns: Vec<&str> = config.transform.dictionary.iter().map(|(p, _)| p.as_str()).collect();
+        assert_eq!(patterns, [r"\bgit hub\b", "teh", r"(\d+) percent"]);
+        assert_eq!(config.transform.dictionary[0].1, "GitHub");
+        assert_eq!(config.transform.case, vec!["GitHub"]);
+    }
+
+    #[test]
+    fn test_parse_hotkeys_section() {
+        let config = Config::parse(
+            r#"
+            [hotkeys]
+            push_to_talk = "f13"
+            toggle = "super+h"
+            grab = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.hotkeys.push_to_talk.as_deref(), Some("f13"));
+        assert_eq!(config.hotkeys.toggle.as_deref(), Some("super+h"));
+        assert_eq!(config.hotkeys.cancel, None);
+        assert_eq!(config.hotkeys.device, None);
+        assert_eq!(config.hotkeys.grab, Some(true));
+    }
+
+    #[test]
+    fn test_parse_push_to_talk_section() {
+        let config = Config::parse("[push_to_talk]\nkey = \"f13\"\ngrab = true").unwrap();
+        assert_eq!(config.hotkeys.push_to_talk.as_deref(), Some("f13"));
+        assert_eq!(config.hotkeys.device, None);
+        assert_eq!(config.hotkeys.grab, Some(true));
+
+        // [hotkeys] takes precedence
+        let config = Config::parse("[push_to_talk]\nkey = \"f13\"\n[hotkeys]\npush_to_talk = \"pause\"").unwrap();
+        assert_eq!(config.hotkeys.push_to_talk.as_deref(), Some("pause"));
+    }
+
+    #[test]
+    fn test_parse_session_section() {
+        let config = Config::parse("[session]\non_shutdown = \"cancel\"\nstop_timeout = 1.5").unwrap();
+        assert_eq!(config.session.on_shutdown.as_deref(), Some("cancel"));
+        assert_eq!(config.session.stop_timeout, Some(1.5));
+    }
+
+    #[test]
+    fn test_parse_empty_and_unknown() {
+        assert!(Config::parse("").unwrap().keyboard.layout.is_none());
+        assert!(Config::parse("[keyboard]\nlayuot = \"de\"").is_err());
+    }
+}
@@ -4,11 +4,21 @@ use std::sync::Arc;
 use tracing::info;
 use zbus::{interface, ConnectionBuilder};
 
+use crate::code_mode::CodeMode;
+use crate::stt_client::ConnectionStatus;
+
+// Returns whether a turn was undone
+type UndoCallback = Arc<Mutex<Option<Box<dyn Fn() -> bool + Send + Sync>>>>;
+
 /// D-Bus interface for Voice Keyboard control
 pub struct VoiceKeyboardInterface {
     is_active: Arc<Mutex<bool>>,
     toggle_callback: Arc<Mutex<Option<Box<dyn Fn(bool) + Send + Sync>>>>,
     cancel_callback: Arc<Mutex<Option<Box<dyn Fn() + Send + Sync>>>>,
+    undo_callback: UndoCallback,
+    code_mode: CodeMode,
+    connection_status: Arc<Mutex<ConnectionStatus>>,
+    typing_error: Arc<Mutex<Option<String>>>,
 }
 
 #[interface(name = "com.voicekeyboard.Control")]
@@ -71,6 +81,48 @@ impl VoiceKeyboardInterface {
 
         was_active
     }
+
+    /// Backspace the most recently typed turn; returns false if nothing was undone
+    async fn undo_last_turn(&self) -> bool {
+        info!("D-Bus undo last turn");
+
+        // Backspacing takes a while, so keep it off the async executor
+        let callback = self.undo_callback.clone();
+        tokio::task::spawn_blocking(move || callback.lock().as_ref().is_some_and(|cb| cb()))
+            .await
+            .unwrap_or(false)
+    }
+
+    /// Switch programmer dictation on or off; returns the new state
+    async fn set_code_mode(&self, enabled: bool) -> bool {
+        info!("D-Bus code mode: {}", if enabled { "on" } else { "off" });
+        self.code_mode.set_enabled(enabled);
+        enabled
+    }
+
+    /// Toggle programmer dictation; returns the new state
+    async fn toggle_code_mode(&self) -> bool {
+        let enabled = !self.code_mode.is_enabled();
+        self.set_code_mode(enabled).await
+    }
+
+    /// Whether programmer dictation is on
+    async fn is_code_mode(&self) -> bool {
+        self.code_mode.is_enabled()
+    }
+
+    /// State of the speech-to-text connection: "connected", "reconnecting" or "disconnected"
+    async fn connection_status(&self) -> String {
+        if !*self.is_active.lock() {
+            return ConnectionStatus::Disconnected.name().to_string();
+        }
+        self.connection_status.lock().name().to_string()
+    }
+
+    /// Why typing failed since listening was last started; empty if it didn't
+    async fn typing_error(&self) -> String {
+        self.typing_error.lock().clone().unwrap_or_default()
+    }
 }
 
 /// D-Bus service manager for Voice Keyboard
@@ -78,6 +130,10 @@ pub struct DbusService {
     is_active: Arc<Mutex<bool>>,
     toggle_callback: Arc<Mutex<Option<Box<dyn Fn(bool) + Send + Sync>>>>,
     cancel_callback: Arc<Mutex<Option<Box<dyn Fn() + Send + Sync>>>>,
+    undo_callback: UndoCallback,
+    code_mode: CodeMode,
+    connection_status: Arc<Mutex<ConnectionStatus>>,
+    typing_error: Arc<Mutex<Option<String>>>,
 }
 
 impl DbusService {
@@ -86,6 +142,10 @@ impl DbusService {
             is_active,
             toggle_callback: Arc::new(Mutex::new(None)),
             cancel_callback: Arc::new(Mutex::new(None)),
+            undo_callback: Arc::new(Mutex::new(None)),
+            code_mode: CodeMode::default(),
+            connection_status: Arc::new(Mutex::new(ConnectionStatus::Disconnected)),
+            typing_error: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -105,12 +165,39 @@ impl DbusService {
         *self.cancel_callback.lock() = Some(Box::new(callback));
     }
 
+    /// Set the callback that will be called when UndoLastTurn is triggered via D-Bus
+    pub fn set_undo_callback<F>(&self, callback: F)
+    where
+        F: Fn() -> bool + Send + Sync + 'static,
+    {
+        *self.undo_callback.lock() = Some(Box::new(callback));
+    }
+
+    /// Share the code mode switch controlled by SetCodeMode/ToggleCodeMode
+    pub fn set_code_mode(&mut self, code_mode: CodeMode) {
+        self.code_mode = code_mode;
+    }
+
+    /// Share the connection state reported by ConnectionStatus
+    pub fn set_connection_status(&mut self, connection_status: Arc<Mutex<ConnectionStatus>>) {
+        self.connection_status = connection_status;
+    }
+
+    /// Share the last typing error reported by TypingError
+    pub fn set_typing_error(&mut self, typing_error: Arc<Mutex<Option<String>>>) {
+        self.typing_error = typing_error;
+    }
+
     /// Start the D-Bus service (runs async)
     pub async fn start(self) -> Result<()> {
         let interface = VoiceKeyboardInterface {
             is_active: self.is_active.clone(),
             toggle_callback: self.toggle_callback.clone(),
             cancel_callback: self.cancel_callback.clone(),
+            undo_callback: self.undo_callback.clone(),
+            code_mode: self.code_mode.clone(),
+            connection_status: self.connection_status.clone(),
+            typing_error: self.typing_error.clone(),
         };
 
         let _connection = ConnectionBuilder::session()?
@@ -124,6 +211,10 @@ impl DbusService {
         info!("Available D-Bus commands:");
         info!("  Toggle: dbus-send --session --type=method_call --dest=com.voicekeyboard.App /com/voicekeyboard/Control com.voicekeyboard.Control.Toggle");
         info!("  Cancel: dbus-send --session --type=method_call --dest=com.voicekeyboard.App /com/voicekeyboard/Control com.voicekeyboard.Control.Cancel");
+        info!("  Undo last turn: dbus-send --session --type=method_call --dest=com.voicekeyboard.App /com/voicekeyboard/Control com.voicekeyboard.Control.UndoLastTurn");
+        info!("  Toggle code mode: dbus-send --session --type=method_call --dest=com.voicekeyboard.App /com/voicekeyboard/Control com.voicekeyboard.Control.ToggleCodeMode");
+        info!("  Connection status: dbus-send --session --print-reply --type=method_call --dest=com.voicekeyboard.App /com/voicekeyboard/Control com.voicekeyboard.Control.ConnectionStatus");
+        info!("  Typing error: dbus-send --session --print-reply --type=method_call --dest=com.voicekeyboard.App /com/voicekeyboard/Control com.voicekeyboard.Control.TypingError");
 
         // Keep the connection alive
         std::future::pending::<()>().await;
@@ -41,6 +41,11 @@ impl InputEvent {
 // Event types
 pub const EV_SYN: u16 = 0x00;
 pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
+pub const EV_ABS: u16 = 0x03;
+pub const EV_MSC: u16 = 0x04;
+pub const EV_LED: u16 = 0x11;
+pub const EV_REP: u16 = 0x14;
 
 // Synchronization events
 pub const SYN_REPORT: u16 = 0;
@@ -103,6 +108,7 @@ pub const KEY_RIGHTSHIFT: u16 = 54;
 pub const KEY_LEFTALT: u16 = 56;
 pub const KEY_SPACE: u16 = 57;
 pub const KEY_CAPSLOCK: u16 = 58;
+pub const KEY_102ND: u16 = 86;
 
 // Function keys
 pub const KEY_F1: u16 = 59;
@@ -117,6 +123,43 @@ pub const KEY_F9: u16 = 67;
 pub const KEY_F10: u16 = 68;
 pub const KEY_F11: u16 = 87;
 pub const KEY_F12: u16 = 88;
+// Not on most keyboards, so they make good dedicated hotkeys
+pub const KEY_F13: u16 = 183;
+pub const KEY_F14: u16 = 184;
+pub const KEY_F15: u16 = 185;
+pub const KEY_F16: u16 = 186;
+pub const KEY_F17: u16 = 187;
+pub const KEY_F18: u16 = 188;
+pub const KEY_F19: u16 = 189;
+pub const KEY_F20: u16 = 190;
+pub const KEY_F21: u16 = 191;
+pub const KEY_F22: u16 = 192;
+pub const KEY_F23: u16 = 193;
+pub const KEY_F24: u16 = 194;
+
+// A key without a meaning of its own
+pub const KEY_UNKNOWN: u16 = 240;
+
+// Navigation and editing keys
+pub const KEY_HOME: u16 = 102;
+pub const KEY_UP: u16 = 103;
+pub const KEY_PAGEUP: u16 = 104;
+pub const KEY_LEFT: u16 = 105;
+pub const KEY_RIGHT: u16 = 106;
+pub const KEY_END: u16 = 107;
+pub const KEY_DOWN: u16 = 108;
+pub const KEY_PAGEDOWN: u16 = 109;
+pub const KEY_INSERT: u16 = 110;
+pub const KEY_DELETE: u16 = 111;
+
+// Modifier and compose keys outside the main block
+pub const KEY_SCROLLLOCK: u16 = 70;
+pub const KEY_RIGHTCTRL: u16 = 97;
+pub const KEY_RIGHTALT: u16 = 100;
+pub const KEY_LEFTMETA: u16 = 125;
+pub const KEY_RIGHTMETA: u16 = 126;
+pub const KEY_COMPOSE: u16 = 127;
+pub const KEY_PAUSE: u16 = 119;
 
 // uinput constants
 pub const UINPUT_MAX_NAME_SIZE: usize = 80;
@@ -168,87 +211,98 @@ pub struct InputId {
     pub version: u16,
 }
 
-// Character to key code mapping
-pub fn char_to_keycode(c: char) -> Option<(u16, bool)> {
-    match c {
-        // Direct letter mappings instead of calculating offsets
-        'a' | 'A' => Some((KEY_A, c.is_uppercase())),
-        'b' | 'B' => Some((KEY_B, c.is_uppercase())),
-        'c' | 'C' => Some((KEY_C, c.is_uppercase())),
-        'd' | 'D' => Some((KEY_D, c.is_uppercase())),
-        'e' | 'E' => Some((KEY_E, c.is_uppercase())),
-        'f' | 'F' => Some((KEY_F, c.is_uppercase())),
-        'g' | 'G' => Some((KEY_G, c.is_uppercase())),
-        'h' | 'H' => Some((KEY_H, c.is_uppercase())),
-        'i' | 'I' => Some((KEY_I, c.is_uppercase())),
-        'j' | 'J' => Some((KEY_J, c.is_uppercase())),
-        'k' | 'K' => Some((KEY_K, c.is_uppercase())),
-        'l' | 'L' => Some((KEY_L, c.is_uppercase())),
-        'm' | 'M' => Some((KEY_M, c.is_uppercase())),
-        'n' | 'N' => Some((KEY_N, c.is_uppercase())),
-        'o' | 'O' => Some((KEY_O, c.is_uppercase())),
-        'p' | 'P' => Some((KEY_P, c.is_uppercase())),
-        'q' | 'Q' => Some((KEY_Q, c.is_uppercase())),
-        'r' | 'R' => Some((KEY_R, c.is_uppercase())),
-        's' | 'S' => Some((KEY_S, c.is_uppercase())),
-        't' | 'T' => Some((KEY_T, c.is_uppercase())),
-        'u' | 'U' => Some((KEY_U, c.is_uppercase())),
-        'v' | 'V' => Some((KEY_V, c.is_uppercase())),
-        'w' | 'W' => Some((KEY_W, c.is_uppercase())),
-        'x' | 'X' => Some((KEY_X, c.is_uppercase())),
-        'y' | 'Y' => Some((KEY_Y, c.is_uppercase())),
-        'z' | 'Z' => Some((KEY_Z, c.is_uppercase())),
-        // Numbers (note: KEY_1-KEY_9 come before KEY_0 in the keycodes)
-        '0' => Some((KEY_0, false)),
-        '1' => Some((KEY_1, false)),
-        '2' => Some((KEY_2, false)),
-        '3' => Some((KEY_3, false)),
-        '4' => Some((KEY_4, false)),
-        '5' => Some((KEY_5, false)),
-        '6' => Some((KEY_6, false)),
-        '7' => Some((KEY_7, false)),
-        '8' => Some((KEY_8, false)),
-        '9' => Some((KEY_9, false)),
-        // Other special characters
-        ' ' => Some((KEY_SPACE, false)),
-        '\n' => Some((KEY_ENTER, false)),
-        '\t' => Some((KEY_TAB, false)),
-        '!' => Some((KEY_1, true)),
-        '@' => Some((KEY_2, true)),
-        '#' => Some((KEY_3, true)),
-        '$' => Some((KEY_4, true)),
-        '%' => Some((KEY_5, true)),
-        '^' => Some((KEY_6, true)),
-        '&' => Some((KEY_7, true)),
-        '*' => Some((KEY_8, true)),
-        '(' => Some((KEY_9, true)),
-        ')' => Some((KEY_0, true)),
-        '-' => Some((KEY_MINUS, false)),
-        '_' => Some((KEY_MINUS, true)),
-        '=' => Some((KEY_EQUAL, false)),
-        '+' => Some((KEY_EQUAL, true)),
-        '[' => Some((KEY_LEFTBRACE, false)),
-        '{' => Some((KEY_LEFTBRACE, true)),
-        ']' => Some((KEY_RIGHTBRACE, false)),
-        '}' => Some((KEY_RIGHTBRACE, true)),
-        '\\' => Some((KEY_BACKSLASH, false)),
-        '|' => Some((KEY_BACKSLASH, true)),
-        ';' => Some((KEY_SEMICOLON, false)),
-        ':' => Some((KEY_SEMICOLON, true)),
-        '\'' => Some((KEY_APOSTROPHE, false)),
-        '"' => Some((KEY_APOSTROPHE, true)),
-        '`' => Some((KEY_GRAVE, false)),
-        '~' => Some((KEY_GRAVE, true)),
-        ',' => Some((KEY_COMMA, false)),
-        '<' => Some((KEY_COMMA, true)),
-        '.' => Some((KEY_DOT, false)),
-        '>' => Some((KEY_DOT, true)),
-        '/' => Some((KEY_SLASH, false)),
-        '?' => Some((KEY_SLASH, true)),
+// Key name to key code mapping (case-insensitive), e.g. "a", "5", "enter", "ctrl", "f4"
+// Letters and digits refer to US key positions
+pub fn key_from_name(name: &str) -> Option<u16> {
+    const LETTERS: [u16; 26] = [
+        KEY_A, KEY_B, KEY_C, KEY_D, KEY_E, KEY_F, KEY_G, KEY_H, KEY_I, KEY_J, KEY_K, KEY_L, KEY_M,
+        KEY_N, KEY_O, KEY_P, KEY_Q, KEY_R, KEY_S, KEY_T, KEY_U, KEY_V, KEY_W, KEY_X, KEY_Y, KEY_Z,
+    ];
+    const DIGITS: [u16; 10] = [
+        KEY_0, KEY_1, KEY_2, KEY_3, KEY_4, KEY_5, KEY_6, KEY_7, KEY_8, KEY_9,
+    ];
+    const FUNCTION_KEYS: [u16; 24] = [
+        KEY_F1, KEY_F2, KEY_F3, KEY_F4, KEY_F5, KEY_F6, KEY_F7, KEY_F8, KEY_F9, KEY_F10, KEY_F11,
+        KEY_F12, KEY_F13, KEY_F14, KEY_F15, KEY_F16, KEY_F17, KEY_F18, KEY_F19, KEY_F20, KEY_F21,
+        KEY_F22, KEY_F23, KEY_F24,
+    ];
+
+    let name = name.to_lowercase();
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.clone().next()) {
+        if c.is_ascii_lowercase() {
+            return Some(LETTERS[(c as u8 - b'a') as usize]);
+        }
+        if c.is_ascii_digit() {
+            return Some(DIGITS[(c as u8 - b'0') as usize]);
+        }
+    }
+    if let Some(n) = name.strip_prefix('f').and_then(|n| n.parse::<usize>().ok()) {
+        return FUNCTION_KEYS.get(n.wrapping_sub(1)).copied();
+    }
+
+    match name.as_str() {
+        // Modifiers
+        "ctrl" | "control" => Some(KEY_LEFTCTRL),
+        "shift" => Some(KEY_LEFTSHIFT),
+        "alt" => Some(KEY_LEFTALT),
+        "altgr" => Some(KEY_RIGHTALT),
+        "super" | "meta" | "win" | "windows" => Some(KEY_LEFTMETA),
+        "rightctrl" => Some(KEY_RIGHTCTRL),
+        "rightshift" => Some(KEY_RIGHTSHIFT),
+        "rightalt" => Some(KEY_RIGHTALT),
+        "rightsuper" | "rightmeta" => Some(KEY_RIGHTMETA),
+        // Editing and navigation
+        "esc" | "escape" => Some(KEY_ESC),
+        "enter" | "return" => Some(KEY_ENTER),
+        "tab" => Some(KEY_TAB),
+        "space" => Some(KEY_SPACE),
+        "backspace" => Some(KEY_BACKSPACE),
+        "delete" | "del" => Some(KEY_DELETE),
+        "insert" => Some(KEY_INSERT),
+        "home" => Some(KEY_HOME),
+        "end" => Some(KEY_END),
+        "pageup" => Some(KEY_PAGEUP),
+        "pagedown" => Some(KEY_PAGEDOWN),
+        "up" => Some(KEY_UP),
+        "down" => Some(KEY_DOWN),
+        "left" => Some(KEY_LEFT),
+        "right" => Some(KEY_RIGHT),
+        "capslock" => Some(KEY_CAPSLOCK),
+        "scrolllock" => Some(KEY_SCROLLLOCK),
+        "pause" => Some(KEY_PAUSE),
+        "menu" | "compose" => Some(KEY_COMPOSE),
+        // Punctuation keys
+        "minus" => Some(KEY_MINUS),
+        "equal" => Some(KEY_EQUAL),
+        "comma" => Some(KEY_COMMA),
+        "dot" | "period" => Some(KEY_DOT),
+        "slash" => Some(KEY_SLASH),
+        "backslash" => Some(KEY_BACKSLASH),
+        "semicolon" => Some(KEY_SEMICOLON),
+        "apostrophe" => Some(KEY_APOSTROPHE),
+        "grave" => Some(KEY_GRAVE),
+        "leftbrace" => Some(KEY_LEFTBRACE),
+        "rightbrace" => Some(KEY_RIGHTBRACE),
         _ => None,
     }
 }
 
+// Whether a key code is a modifier key
+pub fn is_modifier(keycode: u16) -> bool {
+    matches!(
+        keycode,
+        KEY_LEFTCTRL
+            | KEY_RIGHTCTRL
+            | KEY_LEFTSHIFT
+            | KEY_RIGHTSHIFT
+            | KEY_LEFTALT
+            | KEY_RIGHTALT
+            | KEY_LEFTMETA
+            | KEY_RIGHTMETA
+    )
+}
+
 // Helper function to get all required key codes for keyboard setup
 pub fn get_all_keycodes() -> Vec<u16> {
     let mut keys = Vec::new();
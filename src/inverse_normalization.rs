@@ -0,0 +1,600 @@
+use crate::text_transform::TextTransformer;
+
+/// Rules for one language: recognize a number expression at the start of `words` and return
+/// its written form and how many words it spans. Words are lowercase without punctuation.
+trait Grammar: Send + Sync {
+    fn normalize(&self, words: &[&str]) -> Option<(String, usize)>;
+}
+
+/// Inverse text normalization: spoken numbers, dates, times, currency and percentages in
+/// written form ("twenty three point five percent" -> "23.5%")
+///
+/// A number is retyped as it grows ("twenty" -> "20", then "twenty three" -> "23"), so
+/// live mode backspaces a few characters while one is being spoken
+pub struct InverseNormalizer {
+    grammar: Box<dyn Grammar>,
+}
+
+impl InverseNormalizer {
+    /// Rules for a language code such as "en" or "en-US"; None if the language isn't supported
+    pub fn for_language(language: &str) -> Option<Self> {
+        let base = language.split(['-', '_']).next().unwrap_or("").to_lowercase();
+        match base.as_str() {
+            "en" => Some(Self {
+                grammar: Box::new(English),
+            }),
+            _ => None,
+        }
+    }
+}
+
+// A whitespace separated token, split into its word and the punctuation around it
+struct Token {
+    prefix: String,
+    word: String,
+    suffix: String,
+    original: String,
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for original in text.split_whitespace() {
+        let not_word = |c: char| !c.is_alphanumeric();
+        let start = original.len() - original.trim_start_matches(not_word).len();
+        let end = original.trim_end_matches(not_word).len();
+        if start >= end {
+            tokens.push(Token {
+                prefix: String::new(),
+                word: String::new(),
+                suffix: String::new(),
+                original: original.to_string(),
+            });
+            continue;
+        }
+
+        // "a.m." is one word, not "a.m" and a period
+        let abbreviation = original[end..].starts_with('.') && original[start..end].contains('.');
+        let end = if abbreviation { end + 1 } else { end };
+
+        // Decimal numbers keep their point, "a.m." loses its dots
+        let core = &original[start..end];
+        let word = if core.parse::<f64>().is_ok() {
+            core.to_string()
+        } else {
+            core.chars().filter(|c| c.is_alphanumeric() || *c == '\'' || *c == '-').collect::<String>().to_lowercase()
+        };
+
+        // "twenty-three" is two number words
+        let parts: Vec<&str> = word.split('-').collect();
+        if parts.len() > 1 && parts.iter().all(|p| number_word(p).is_some()) {
+            for (i, part) in parts.iter().enumerate() {
+                tokens.push(Token {
+                    prefix: if i == 0 { original[..start].to_string() } else { String::new() },
+                    word: part.to_string(),
+                    suffix: if i == parts.len() - 1 { original[end..].to_string() } else { String::new() },
+                    original: part.to_string(),
+                });
+            }
+            continue;
+        }
+
+        tokens.push(Token {
+            prefix: original[..start].to_string(),
+            word,
+            suffix: original[end..].to_string(),
+            original: original.to_string(),
+        });
+    }
+    tokens
+}
+
+impl TextTransformer for InverseNormalizer {
+    fn transform(&self, text: &str) -> String {
+        let tokens = tokenize(text);
+        let mut output: Vec<String> = Vec::with_capacity(tokens.len());
+
+        let mut i = 0;
+        while i < tokens.len() {
+            // An expression can't span punctuation: "twenty, three" is two numbers
+            let mut end = i;
+            while end + 1 < tokens.len()
+                && tokens[end].suffix.is_empty()
+                && tokens[end + 1].prefix.is_empty()
+                && !tokens[end + 1].word.is_empty()
+            {
+                end += 1;
+            }
+            let words: Vec<&str> = tokens[i..=end].iter().map(|t| t.word.as_str()).collect();
+
+            match self.grammar.normalize(&words) {
+                Some((written, len)) if !tokens[i].word.is_empty() => {
+                    let last = &tokens[i + len - 1];
+                    output.push(format!("{}{}{}", tokens[i].prefix, written, last.suffix));
+                    i += len;
+                }
+                _ => {
+                    output.push(tokens[i].original.clone());
+                    i += 1;
+                }
+            }
+        }
+
+        output.join(" ")
+    }
+}
+
+// English number words by kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Unit,
+    Teen,
+    Tens,
+    Hundred,
+    Scale,
+}
+
+fn number_word(word: &str) -> Option<(Kind, u64)> {
+    const UNITS: [&str; 10] = ["zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine"];
+    const TEENS: [&str; 10] = [
+        "ten", "eleven", "twelve", "thirteen", "fourteen", "fifteen", "sixteen", "seventeen", "eighteen", "nineteen",
+    ];
+    const TENS: [&str; 8] = ["twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety"];
+
+    if let Some(n) = UNITS.iter().position(|&w| w == word) {
+        return Some((Kind::Unit, n as u64));
+    }
+    if let Some(n) = TEENS.iter().position(|&w| w == word) {
+        return Some((Kind::Teen, 10 + n as u64));
+    }
+    if let Some(n) = TENS.iter().position(|&w| w == word) {
+        return Some((Kind::Tens, 20 + 10 * n as u64));
+    }
+    match word {
+        "hundred" => Some((Kind::Hundred, 100)),
+        "thousand" => Some((Kind::Scale, 1_000)),
+        "million" => Some((Kind::Scale, 1_000_000)),
+        "billion" => Some((Kind::Scale, 1_000_000_000)),
+        _ => None,
+    }
+}
+
+// Ordinal words and the cardinal word they stand in for ("twenty first" reads as "twenty one")
+const ORDINALS: &[(&str, &str)] = &[
+    ("first", "one"),
+    ("second", "two"),
+    ("third", "three"),
+    ("fourth", "four"),
+    ("fifth", "five"),
+    ("sixth", "six"),
+    ("seventh", "seven"),
+    ("eighth", "eight"),
+    ("ninth", "nine"),
+    ("tenth", "ten"),
+    ("eleventh", "eleven"),
+    ("twelfth", "twelve"),
+    ("thirteenth", "thirteen"),
+    ("fourteenth", "fourteen"),
+    ("fifteenth", "fifteen"),
+    ("sixteenth", "sixteen"),
+    ("seventeenth", "seventeen"),
+    ("eighteenth", "eighteen"),
+    ("nineteenth", "nineteen"),
+    ("twentieth", "twenty"),
+    ("thirtieth", "thirty"),
+    ("fortieth", "forty"),
+    ("fiftieth", "fifty"),
+    ("sixtieth", "sixty"),
+    ("seventieth", "seventy"),
+    ("eightieth", "eighty"),
+    ("ninetieth", "ninety"),
+    ("hundredth", "hundred"),
+    ("thousandth", "thousand"),
+    ("millionth", "million"),
+];
+
+const MONTHS: [&str; 12] = [
+    "january", "february", "march", "april", "may", "june", "july", "august", "september", "october",
+    "november", "december",
+];
+
+struct English;
+
+impl Grammar for English {
+    fn normalize(&self, words: &[&str]) -> Option<(String, usize)> {
+        date(words)
+            .or_else(|| time(words))
+            .or_else(|| amount(words))
+            .or_else(|| ordinal_of_month(words))
+            .or_else(|| spoken_year(words))
+            .or_else(|| {
+                // Small standalone numbers read better as words ("two cats", "the first one")
+                let (n, len) = ordinal(words)?;
+                (n >= 10).then(|| (format!("{}{}", n, ordinal_suffix(n)), len))
+            })
+            .or_else(|| {
+                let (n, len) = cardinal(words)?;
+                (n >= 10 && !words[0].chars().all(|c| c.is_ascii_digit())).then(|| (group_thousands(n), len))
+            })
+    }
+}
+
+// "twenty three", "one hundred and five", "a thousand", "2" -> (value, words used)
+fn cardinal(words: &[&str]) -> Option<(u64, usize)> {
+    if let Some(n) = words.first().and_then(|w| w.parse::<u64>().ok()) {
+        return Some((n, 1));
+    }
+
+    let mut total = 0u64;
+    let mut group = 0u64;
+    let mut last: Option<Kind> = None;
+    let mut last_scale = u64::MAX;
+    let mut used = 0;
+
+    let mut i = 0;
+    while i < words.len() {
+        let word = words[i];
+        let next = words.get(i + 1).and_then(|w| number_word(w));
+
+        // "a hundred", "one hundred and five"
+        if word == "a" && last.is_none() && matches!(next, Some((Kind::Hundred | Kind::Scale, _))) {
+            group = 1;
+            last = Some(Kind::Unit);
+            i += 1;
+            continue;
+        }
+        if word == "and"
+            && matches!(last, Some(Kind::Hundred | Kind::Scale))
+            && matches!(next, Some((Kind::Unit | Kind::Teen | Kind::Tens, n)) if n > 0)
+        {
+            i += 1;
+            continue;
+        }
+
+        let Some((kind, n)) = number_word(word) else {
+            break;
+        };
+        let fits = match kind {
+            // "zero" only stands alone
+            Kind::Unit if n == 0 => last.is_none(),
+            Kind::Unit => matches!(last, None | Some(Kind::Hundred | Kind::Scale))
+                || (last == Some(Kind::Tens) && group.is_multiple_of(10)),
+            Kind::Teen | Kind::Tens => matches!(last, None | Some(Kind::Hundred | Kind::Scale)),
+            Kind::Hundred => matches!(last, Some(Kind::Unit | Kind::Teen)) && group < 100,
+            Kind::Scale => last.is_some() && last != Some(Kind::Scale) && n < last_scale,
+        };
+        if !fits {
+            break;
+        }
+
+        match kind {
+            Kind::Hundred => group *= 100,
+            Kind::Scale => {
+                total += group * n;
+                group = 0;
+                last_scale = n;
+            }
+            _ => group += n,
+        }
+        last = Some(kind);
+        i += 1;
+        used = i;
+
+        if kind == Kind::Unit && n == 0 {
+            break;
+        }
+    }
+
+    (used > 0).then_some((total + group, used))
+}
+
+// "twenty first", "third", "one hundredth" -> (value, words used)
+fn ordinal(words: &[&str]) -> Option<(u64, usize)> {
+    let end = words.iter().position(|w| ORDINALS.iter().any(|&(o, _)| o == *w))?;
+    let (_, as_cardinal) = ORDINALS.iter().find(|&&(o, _)| o == words[end])?;
+
+    // "hundredth" is "one hundredth"
+    let mut cardinal_words = words[..end].to_vec();
+    if cardinal_words.is_empty() && matches!(number_word(as_cardinal), Some((Kind::Hundred | Kind::Scale, _))) {
+        cardinal_words.push("one");
+    }
+    cardinal_words.push(as_cardinal);
+    let (n, used) = cardinal(&cardinal_words)?;
+    (used == cardinal_words.len()).then_some((n, end + 1))
+}
+
+// A decimal or whole number: "twenty three point five", "point five", "2.5"
+fn number(words: &[&str]) -> Option<(String, usize)> {
+    if let Some(word) = words.first().filter(|w| w.contains('.') && w.parse::<f64>().is_ok()) {
+        return Some((word.to_string(), 1));
+    }
+
+    let (whole, mut used) = match cardinal(words) {
+        Some((n, used)) => (n.to_string(), used),
+        None if words.first() == Some(&"point") => ("0".to_string(), 0),
+        None => return None,
+    };
+    if words.get(used) != Some(&"point") {
+        return Some((whole, used));
+    }
+
+    let digits: String = words[used + 1..]
+        .iter()
+        .map_while(|w| match *w {
+            "oh" => Some('0'),
+            w => match number_word(w) {
+                Some((Kind::Unit, n)) => char::from_digit(n as u32, 10),
+                _ => None,
+            },
+        })
+        .collect();
+    if digits.is_empty() {
+        return (used > 0).then_some((whole, used));
+    }
+    used += 1 + digits.len();
+    Some((format!("{}.{}", whole, digits), used))
+}
+
+// Percentages and currency: "five percent", "twenty dollars and fifty cents", "three point five euros"
+fn amount(words: &[&str]) -> Option<(String, usize)> {
+    let (value, used) = number(words)?;
+
+    match words.get(used).copied() {
+        Some("percent") => Some((format!("{}%", value), used + 1)),
+        Some(unit @ ("dollar" | "dollars" | "euro" | "euros")) => {
+            let symbol = if unit.starts_with("dollar") { "$" } else { "€" };
+            let cents = (words.get(used + 1) == Some(&"and"))
+                .then(|| cardinal(&words[used + 2..]))
+                .flatten()
+                .filter(|&(n, len)| n < 100 && matches!(words.get(used + 2 + len), Some(&"cents" | &"cent")));
+            match cents {
+                Some((n, len)) if !value.contains('.') => {
+                    Some((format!("{}{}.{:02}", symbol, value, n), used + 3 + len))
+                }
+                _ => Some((format!("{}{}", symbol, value), used + 1)),
+            }
+        }
+        // Only decimals read as numbers on their own; whole numbers go through `cardinal`
+        _ if value.contains('.') && !words[0].contains('.') => Some((value, used)),
+        _ => None,
+    }
+}
+
+// "three thirty pm", "ten o'clock", "seven oh five a.m."
+fn time(words: &[&str]) -> Option<(String, usize)> {
+    let (hour, mut used) = cardinal(words)?;
+    if !(1..=12).contains(&hour) || used != 1 {
+        return None;
+    }
+
+    let minutes = match words.get(used).copied() {
+        Some("oh") => match words.get(used + 1).and_then(|w| number_word(w)) {
+            Some((Kind::Unit, n)) if n > 0 => {
+                used += 2;
+                Some(n)
+            }
+            _ => None,
+        },
+        Some(word) if matches!(number_word(word), Some((Kind::Teen | Kind::Tens, _))) => {
+            let (n, len) = cardinal(&words[used..])?;
+            used += len;
+            (n < 60).then_some(n)
+        }
+        _ => None,
+    };
+
+    let marker = match words.get(used).copied() {
+        Some("am") => " AM",
+        Some("pm") => " PM",
+        Some("o'clock") if minutes.is_none() => {
+            return Some((format!("{}:00", hour), used + 1));
+        }
+        _ => return None,
+    };
+    let written = match minutes {
+        Some(m) => format!("{}:{:02}{}", hour, m, marker),
+        None => format!("{}{}", hour, marker),
+    };
+    Some((written, used + 1))
+}
+
+// Months that are everyday words too: "I may second that", "we march first"
+const AMBIGUOUS_MONTHS: &[&str] = &["march", "may"];
+
+// "june third", "march third twenty twenty six", "december twenty fifth two thousand ten"
+fn date(words: &[&str]) -> Option<(String, usize)> {
+    let first = words.first()?;
+    let month = MONTHS.iter().position(|m| m == first)?;
+    // A spoken cardinal day needs a year after it: "may one day" isn't a date; so does any
+    // day after "may" or "march"
+    let (day, len, needs_year) = match ordinal(&words[1..]) {
+        Some((day, len)) => (day, len, AMBIGUOUS_MONTHS.contains(first)),
+        None => {
+            let (day, len) = cardinal(&words[1..]).filter(|&(_, len)| len <= 2)?;
+            let spoken = !words[1].chars().all(|c| c.is_ascii_digit());
+            (day, len, spoken || AMBIGUOUS_MONTHS.contains(first))
+        }
+    };
+    if !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let mut name = MONTHS[month].to_string();
+    name[..1].make_ascii_uppercase();
+    let used = 1 + len;
+    match year(&words[used..]) {
+        Some((year, len)) => Some((format!("{} {}, {}", name, day, year), used + len)),
+        None if !needs_year => Some((format!("{} {}", name, day), used)),
+        None => None,
+    }
+}
+
+// "the third of march" keeps the words but writes the ordinal
+fn ordinal_of_month(words: &[&str]) -> Option<(String, usize)> {
+    let (n, len) = ordinal(words)?;
+    let of_month = words.get(len) == Some(&"of") && words.get(len + 1).is_some_and(|w| MONTHS.contains(w));
+    (of_month && (1..=31).contains(&n)).then(|| (format!("{}{}", n, ordinal_suffix(n)), len))
+}
+
+// "twenty twenty six", "nineteen ninety nine", "twenty oh five", "two thousand ten", "2026"
+fn year(words: &[&str]) -> Option<(u64, usize)> {
+    // Read as two pairs of digits
+    let century = match words.first().and_then(|w| number_word(w)) {
+        Some((Kind::Teen | Kind::Tens, n)) if words.len() > 1 => Some(n),
+        _ => None,
+    };
+    if let Some(century) = century {
+        let rest = &words[1..];
+        let pair = match rest[0] {
+            "oh" => match rest.get(1).and_then(|w| number_word(w)) {
+                Some((Kind::Unit, n)) if n > 0 => Some((n, 2)),
+                _ => None,
+            },
+            "hundred" => Some((0, 1)),
+            _ => cardinal(rest).filter(|&(n, _)| (10..100).contains(&n)),
+        };
+        if let Some((n, len)) = pair {
+            return Some((century * 100 + n, 1 + len));
+        }
+    }
+
+    cardinal(words).filter(|&(n, _)| (1000..=2999).contains(&n))
+}
+
+// A year spoken as two pairs of digits outside a date, which `cardinal` would split:
+// "in twenty twenty six" -> "in 2026"
+fn spoken_year(words: &[&str]) -> Option<(String, usize)> {
+    if !matches!(number_word(words.first()?), Some((Kind::Teen | Kind::Tens, _))) {
+        return None;
+    }
+    let (year, len) = year(words).filter(|&(year, len)| len > 1 && (1100..=2099).contains(&year))?;
+    Some((year.to_string(), len))
+}
+
+fn ordinal_suffix(n: u64) -> &'static str {
+    match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    }
+}
+
+// 1234 -> "1234", 12345 -> "12,345"
+fn group_thousands(n: u64) -> String {
+    let digits = n.to_string();
+    if n < 10_000 {
+        return digits;
+    }
+    let mut output = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            output.push(',');
+        }
+        output.push(c);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn english() -> InverseNormalizer {
+        InverseNormalizer::for_language("en-US").unwrap()
+    }
+
+    #[test]
+    fn test_cardinals_and_decimals() {
+        let test_cases = vec![
+            ("I have two cats", "I have two cats"),
+            ("twenty three people", "23 people"),
+            ("Twenty-three people.", "23 people."),
+            ("one hundred and five", "105"),
+            ("a thousand times", "1000 times"),
+            ("two hundred fifty thousand", "250,000"),
+            ("three million four hundred", "3,000,400"),
+            ("twenty three point five", "23.5"),
+            ("two point zero five", "2.05"),
+            ("point five", "0.5"),
+            ("twenty, three", "20, three"),
+            ("one two three", "one two three"),
+            ("we did it 10 times", "we did it 10 times"),
+        ];
+
+        for (input, expected) in test_cases {
+            assert_eq!(english().transform(input), expected, "For: '{}'", input);
+        }
+    }
+
+    #[test]
+    fn test_ordinals() {
+        let test_cases = vec![
+            ("the first one", "the first one"),
+            ("the twenty first century", "the 21st century"),
+            ("his hundredth game", "his 100th game"),
+            ("the twelfth", "the 12th"),
+            ("the third of March", "the 3rd of March"),
+        ];
+
+        for (input, expected) in test_cases {
+            assert_eq!(english().transform(input), expected, "For: '{}'", input);
+        }
+    }
+
+    #[test]
+    fn test_percent_and_currency() {
+        let test_cases = vec![
+            ("twenty three point five percent", "23.5%"),
+            ("up five percent.", "up 5%."),
+            ("it costs twenty dollars", "it costs $20"),
+            ("one dollar and fifty cents", "$1.50"),
+            ("three point five euros", "€3.5"),
+            ("5 percent", "5%"),
+        ];
+
+        for (input, expected) in test_cases {
+            assert_eq!(english().transform(input), expected, "For: '{}'", input);
+        }
+    }
+
+    #[test]
+    fn test_times_and_dates() {
+        let test_cases = vec![
+            ("meet at three thirty pm", "meet at 3:30 PM"),
+            ("seven oh five a.m.", "7:05 AM"),
+            ("at ten o'clock", "at 10:00"),
+            ("at five PM", "at 5 PM"),
+            ("march third twenty twenty six", "March 3, 2026"),
+            ("on December twenty fifth", "on December 25"),
+            ("June 1 nineteen ninety nine", "June 1, 1999"),
+            ("may fifth two thousand ten", "May 5, 2010"),
+            ("twenty oh five", "2005"),
+            ("in twenty twenty six", "in 2026"),
+            ("nineteen ninety nine", "1999"),
+            ("back in nineteen hundred", "back in 1900"),
+            ("twenty three cats", "23 cats"),
+            ("twenty thousand", "20,000"),
+            ("you may go", "you may go"),
+            ("I may one day", "I may one day"),
+            ("I may second that", "I may second that"),
+            ("we may first check", "we may first check"),
+            ("we march first", "we march first"),
+            ("march first twenty twenty", "March 1, 2020"),
+            ("the first of may", "the 1st of may"),
+            ("june third", "June 3"),
+            ("april four twenty twenty", "April 4, 2020"),
+        ];
+
+        for (input, expected) in test_cases {
+            assert_eq!(english().transform(input), expected, "For: '{}'", input);
+        }
+    }
+
+    #[test]
+    fn test_languages() {
+        assert!(InverseNormalizer::for_language("en").is_some());
+        assert!(InverseNormalizer::for_language("EN_gb").is_some());
+        assert!(InverseNormalizer::for_language("de").is_none());
+    }
+}
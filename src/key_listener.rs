@@ -0,0 +1,512 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use tracing::{debug, error, info, warn};
+
+use crate::input_event::*;
+use crate::virtual_keyboard::RealKeyboardHardware;
+use crate::voice_commands::KeyChord;
+
+// evdev ioctls, see linux/input.h
+nix::ioctl_read_buf!(eviocgname, b'E', 0x06, u8);
+nix::ioctl_read_buf!(eviocgbit_ev, b'E', 0x20, u8); // EVIOCGBIT(0, len): event types
+nix::ioctl_read_buf!(eviocgbit_key, b'E', 0x21, u8); // EVIOCGBIT(EV_KEY, len)
+nix::ioctl_read_buf!(eviocgkey, b'E', 0x18, u8);
+nix::ioctl_write_int!(eviocgrab, b'E', 0x90);
+
+// Highest keycode (KEY_MAX) and the bitmap size needed to hold it
+const KEY_MAX: usize = 0x2ff;
+const KEY_BITS_LEN: usize = KEY_MAX / 8 + 1;
+// Same for event types (EV_MAX)
+const EV_MAX: usize = 0x1f;
+const EV_BITS_LEN: usize = EV_MAX / 8 + 1;
+
+// Event types a keyboard reports that the passthrough keyboard can do without: LEDs and
+// autorepeat settings go from the host to the device, scan codes are informational
+const KEYBOARD_EVENT_TYPES: &[u16] = &[EV_SYN, EV_KEY, EV_MSC, EV_LED, EV_REP];
+
+// Our own uinput devices are named "Voice Keyboard ..." and never listened to
+const OWN_DEVICE_PREFIX: &str = "Voice Keyboard";
+
+/// A physical input device under /dev/input, opened for reading key events
+pub struct InputDevice {
+    file: File,
+    path: PathBuf,
+    name: String,
+    ev_bits: [u8; EV_BITS_LEN],
+    key_bits: [u8; KEY_BITS_LEN],
+    grabbed: bool,
+}
+
+impl InputDevice {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).context(format!("Failed to open input device {:?}", path))?;
+        let fd = file.as_raw_fd();
+
+        let mut name = [0u8; 256];
+        unsafe { eviocgname(fd, &mut name) }
+            .context(format!("{:?} is not an evdev device", path))?;
+        let name_len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+        let name = String::from_utf8_lossy(&name[..name_len]).into_owned();
+
+        let mut ev_bits = [0u8; EV_BITS_LEN];
+        unsafe { eviocgbit_ev(fd, &mut ev_bits) }
+            .context(format!("Failed to query event types of {:?}", path))?;
+        let mut key_bits = [0u8; KEY_BITS_LEN];
+        unsafe { eviocgbit_key(fd, &mut key_bits) }
+            .context(format!("Failed to query keys of {:?}", path))?;
+
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+            name,
+            ev_bits,
+            key_bits,
+            grabbed: false,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the device can report this key
+    pub fn supports_key(&self, keycode: u16) -> bool {
+        let keycode = keycode as usize;
+        keycode <= KEY_MAX && self.key_bits[keycode / 8] & (1 << (keycode % 8)) != 0
+    }
+
+    /// Whether everything the device reports can go through the passthrough keyboard,
+    /// which only has keys 1-255: not true of a keyboard with a touchpad or of some
+    /// multimedia keys, whose other events would be lost while grabbed
+    pub fn is_plain_keyboard(&self) -> bool {
+        only_passthrough_events(&self.ev_bits, &self.key_bits)
+    }
+
+    /// Take exclusive access: other readers (X11, Wayland, the console) stop seeing its events
+    /// until the device is closed
+    pub fn grab(&mut self) -> Result<()> {
+        // A key still held now (like the Enter that started us) would have its release
+        // go to the passthrough keyboard and stay stuck down, so wait for it to be let go
+        for _ in 0..300 {
+            if !self.any_key_down()? {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        unsafe { eviocgrab(self.file.as_raw_fd(), 1) }
+            .context(format!("Failed to grab {:?} (is another program grabbing it?)", self.path))?;
+        self.grabbed = true;
+        Ok(())
+    }
+
+    fn any_key_down(&self) -> Result<bool> {
+        let mut state = [0u8; KEY_BITS_LEN];
+        unsafe { eviocgkey(self.file.as_raw_fd(), &mut state) }
+            .context(format!("Failed to query key state of {:?}", self.path))?;
+        Ok(state.iter().any(|&b| b != 0))
+    }
+
+    /// Block until the device reports events
+    pub fn read_events(&mut self) -> Result<Vec<InputEvent>> {
+        const EVENT_SIZE: usize = std::mem::size_of::<InputEvent>();
+        let mut buffer = [0u8; EVENT_SIZE * 64];
+
+        let len = self
+            .file
+            .read(&mut buffer)
+            .context(format!("Failed to read from {:?}", self.path))?;
+        if len == 0 {
+            bail!("{:?} was closed", self.path);
+        }
+
+        Ok(buffer[..len - len % EVENT_SIZE]
+            .chunks_exact(EVENT_SIZE)
+            .map(|chunk| unsafe { std::ptr::read_unaligned(chunk.as_ptr() as *const InputEvent) })
+            .collect())
+    }
+}
+
+fn only_passthrough_events(ev_bits: &[u8; EV_BITS_LEN], key_bits: &[u8; KEY_BITS_LEN]) -> bool {
+    let set = |bits: &[u8], n: usize| bits[n / 8] & (1 << (n % 8)) != 0;
+    let passthrough_keys = get_all_keycodes();
+    (0..=EV_MAX).all(|ev| !set(ev_bits, ev) || KEYBOARD_EVENT_TYPES.contains(&(ev as u16)))
+        && (0..=KEY_MAX).all(|key| !set(key_bits, key) || passthrough_keys.contains(&(key as u16)))
+}
+
+/// Open the keyboard at `path`, or every device under /dev/input that has any of `keys`
+///
+/// Needs read access to /dev/input, so this runs before root privileges are dropped
+pub fn open_keyboards(path: Option<&str>, keys: &[u16]) -> Result<Vec<InputDevice>> {
+    if let Some(path) = path {
+        return Ok(vec![InputDevice::open(Path::new(path))?]);
+    }
+
+    let mut paths: Vec<PathBuf> = std::fs::read_dir("/dev/input")
+        .context("Failed to list /dev/input")?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with("event")))
+        .collect();
+    paths.sort();
+
+    let mut devices = Vec::new();
+    for path in paths {
+        match InputDevice::open(&path) {
+            Ok(device) if device.name().starts_with(OWN_DEVICE_PREFIX) => {}
+            Ok(device) if keys.iter().any(|&k| device.supports_key(k)) => devices.push(device),
+            Ok(device) => debug!("Skipping {:?} ({}): none of the keys", path, device.name()),
+            Err(e) => debug!("Skipping {:?}: {:#}", path, e),
+        }
+    }
+
+    if devices.is_empty() {
+        bail!("No input device under /dev/input has the configured hotkeys");
+    }
+    Ok(devices)
+}
+
+/// Parse a key name for a hotkey, e.g. "f13", "rightctrl" or "pause"
+pub fn parse_key(name: &str) -> Result<u16> {
+    key_from_name(name).ok_or_else(|| anyhow!("unknown key name '{}'", name))
+}
+
+/// Parse a hotkey combo, e.g. "super+h" or "ctrl+alt+space"
+pub fn parse_combo(combo: &str) -> Result<KeyChord> {
+    let chord = KeyChord::parse(combo)?;
+    if is_modifier(chord.key) {
+        bail!("'{}' has no key besides modifiers", combo);
+    }
+    Ok(chord)
+}
+
+/// Something the user did on a physical keyboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAction {
+    PushToTalkPressed,
+    PushToTalkReleased,
+    Toggle,
+    Cancel,
+}
+
+/// What an event passes on to applications
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Forward {
+    Nothing,
+    Event,
+    /// A tap of `MASK_KEY`, then the event
+    MaskedEvent,
+}
+
+// Tapped before a modifier of a combo is released: on its own, a Super or Alt tap opens
+// the desktop's overview or an application's menu, and any key in between cancels that
+const MASK_KEY: u16 = KEY_UNKNOWN;
+
+/// The keys being listened for and what they trigger
+///
+/// Each device gets its own copy, which tracks the modifiers held on that device
+#[derive(Debug, Clone, Default)]
+pub struct KeyBindings {
+    push_to_talk: Option<u16>,
+    combos: Vec<(KeyChord, KeyAction)>,
+    held_modifiers: HashSet<u16>,
+    // Combo keys whose press was swallowed, so their repeats and release are too
+    swallowed: HashSet<u16>,
+    // Modifiers held for a swallowed combo, masked when released
+    masked: HashSet<u16>,
+}
+
+impl KeyBindings {
+    pub fn set_push_to_talk(&mut self, keycode: u16) {
+        self.push_to_talk = Some(keycode);
+    }
+
+    /// Trigger `action` when `combo` is pressed; the held modifiers must match exactly
+    pub fn add_combo(&mut self, combo: KeyChord, action: KeyAction) {
+        self.combos.push((combo, action));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.push_to_talk.is_none() && self.combos.is_empty()
+    }
+
+    fn keys(&self) -> Vec<u16> {
+        self.push_to_talk
+            .into_iter()
+            .chain(self.combos.iter().map(|(combo, _)| combo.key))
+            .collect()
+    }
+
+    /// What an event triggers, and whether it should still reach applications
+    fn handle(&mut self, event: &InputEvent) -> (Option<KeyAction>, Forward) {
+        if event.type_ != EV_KEY {
+            return (None, Forward::Event);
+        }
+
+        // Key repeat (value 2) neither starts nor stops anything
+        if Some(event.code) == self.push_to_talk {
+            let action = match event.value {
+                1 => Some(KeyAction::PushToTalkPressed),
+                0 => Some(KeyAction::PushToTalkReleased),
+                _ => None,
+            };
+            return (action, Forward::Nothing);
+        }
+
+        if is_modifier(event.code) {
+            let kind = modifier_kind(event.code);
+            match event.value {
+                1 => {
+                    self.held_modifiers.insert(kind);
+                }
+                0 => {
+                    self.held_modifiers.remove(&kind);
+                    if self.masked.remove(&kind) {
+                        return (None, Forward::MaskedEvent);
+                    }
+                }
+                _ => {}
+            }
+            return (None, Forward::Event);
+        }
+
+        if self.swallowed.contains(&event.code) {
+            if event.value == 0 {
+                self.swallowed.remove(&event.code);
+            }
+            return (None, Forward::Nothing);
+        }
+
+        if event.value == 1 {
+            let action = self.combos.iter().find_map(|(combo, action)| {
+                let modifiers: HashSet<u16> = combo.modifiers.iter().map(|&m| modifier_kind(m)).collect();
+                (combo.key == event.code && modifiers == self.held_modifiers).then_some(*action)
+            });
+            if let Some(action) = action {
+                self.swallowed.insert(event.code);
+                self.masked.extend(self.held_modifiers.iter().copied());
+                return (Some(action), Forward::Nothing);
+            }
+        }
+
+        (None, Forward::Event)
+    }
+}
+
+// Left and right Ctrl, Shift and Super count as the same modifier; AltGr stays apart from Alt
+fn modifier_kind(keycode: u16) -> u16 {
+    match keycode {
+        KEY_RIGHTCTRL => KEY_LEFTCTRL,
+        KEY_RIGHTSHIFT => KEY_LEFTSHIFT,
+        KEY_RIGHTMETA => KEY_LEFTMETA,
+        other => other,
+    }
+}
+
+/// Watches physical keyboards for the configured keys
+///
+/// With `grab`, the devices are taken exclusively and everything except the bound keys is
+/// passed on through a separate virtual keyboard, so the keys never reach applications.
+/// Without it, pick keys nothing else uses (F13-F24 are a good choice).
+pub struct KeyListener {
+    devices: Vec<InputDevice>,
+    bindings: KeyBindings,
+    forwarder: Option<Arc<RealKeyboardHardware>>,
+}
+
+impl KeyListener {
+    /// Open the devices; needs root, like creating the virtual keyboard
+    pub fn open(device: Option<&str>, bindings: KeyBindings, grab: bool) -> Result<Self> {
+        let mut devices = open_keyboards(device, &bindings.keys())?;
+
+        // Only keyboards whose events can all be passed on are grabbed; the others are
+        // listened to like without grab
+        let mut forwarder = None;
+        if grab {
+            for device in devices.iter().filter(|device| !device.is_plain_keyboard()) {
+                warn!(
+                    "Not grabbing {:?} ({}): it reports events the passthrough keyboard can't pass on, so its hotkeys reach applications too",
+                    device.path(),
+                    device.name()
+                );
+            }
+            if devices.iter().any(InputDevice::is_plain_keyboard) {
+                forwarder = Some(Arc::new(
+                    RealKeyboardHardware::new(&format!("{} passthrough", OWN_DEVICE_PREFIX))
+                        .context("Failed to create passthrough keyboard")?,
+                ));
+                for device in devices.iter_mut().filter(|device| device.is_plain_keyboard()) {
+                    device.grab()?;
+                }
+            }
+        }
+
+        for device in &devices {
+            info!(
+                "Listening for hotkeys on {:?} ({}){}",
+                device.path(),
+                device.name(),
+                if device.grabbed { ", grabbed" } else { "" }
+            );
+        }
+
+        Ok(Self {
+            devices,
+            bindings,
+            forwarder,
+        })
+    }
+
+    /// Read every device on its own thread and report actions to `on_action`
+    pub fn spawn<F>(self, on_action: F)
+    where
+        F: Fn(KeyAction) + Send + Sync + 'static,
+    {
+        let on_action = Arc::new(on_action);
+
+        for mut device in self.devices {
+            let mut bindings = self.bindings.clone();
+            // Only what a grabbed device reports is missing elsewhere
+            let forwarder = self.forwarder.clone().filter(|_| device.grabbed);
+            let on_action = on_action.clone();
+
+            thread::spawn(move || loop {
+                let events = match device.read_events() {
+                    Ok(events) => events,
+                    Err(e) => {
+                        warn!("Stopped listening for hotkeys on {:?}: {:#}", device.path(), e);
+                        return;
+                    }
+                };
+
+                for event in events {
+                    let (action, forward) = bindings.handle(&event);
+                    if let Some(action) = action {
+                        debug!("Hotkey action: {:?}", action);
+                        on_action(action);
+                    }
+                    let Some(forwarder) = &forwarder else {
+                        continue;
+                    };
+                    let events = match forward {
+                        Forward::Nothing => vec![],
+                        Forward::Event => vec![event],
+                        Forward::MaskedEvent => vec![
+                            InputEvent::key_event(MASK_KEY, true),
+                            InputEvent::syn_event(),
+                            InputEvent::key_event(MASK_KEY, false),
+                            InputEvent::syn_event(),
+                            event,
+                        ],
+                    };
+                    if let Err(e) = events.into_iter().try_for_each(|event| forwarder.forward_event(event)) {
+                        error!("Failed to pass on key event: {}", e);
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_to_talk_bindings() {
+        let mut bindings = KeyBindings::default();
+        bindings.set_push_to_talk(KEY_F13);
+
+        let key = |code, value| InputEvent::new(EV_KEY, code, value);
+        assert_eq!(bindings.handle(&key(KEY_F13, 1)), (Some(KeyAction::PushToTalkPressed), Forward::Nothing));
+        assert_eq!(bindings.handle(&key(KEY_F13, 2)), (None, Forward::Nothing));
+        assert_eq!(bindings.handle(&key(KEY_F13, 0)), (Some(KeyAction::PushToTalkReleased), Forward::Nothing));
+
+        // Other keys and non-key events are passed on untouched
+        assert_eq!(bindings.handle(&key(KEY_A, 1)), (None, Forward::Event));
+        assert_eq!(bindings.handle(&InputEvent::syn_event()), (None, Forward::Event));
+    }
+
+    #[test]
+    fn test_only_plain_keyboards_are_passed_through() {
+        let mut ev_bits = [0u8; EV_BITS_LEN];
+        let mut key_bits = [0u8; KEY_BITS_LEN];
+        let set = |bits: &mut [u8], n: u16| bits[n as usize / 8] |= 1 << (n % 8);
+        for ev in [EV_SYN, EV_KEY, EV_MSC, EV_LED, EV_REP] {
+            set(&mut ev_bits, ev);
+        }
+        set(&mut key_bits, KEY_A);
+        set(&mut key_bits, KEY_F13);
+        assert!(only_passthrough_events(&ev_bits, &key_bits));
+
+        // A keyboard with a touchpad
+        let mut with_pointer = ev_bits;
+        set(&mut with_pointer, EV_REL);
+        assert!(!only_passthrough_events(&with_pointer, &key_bits));
+        let mut with_abs = ev_bits;
+        set(&mut with_abs, EV_ABS);
+        assert!(!only_passthrough_events(&with_abs, &key_bits));
+
+        // A multimedia key past the passthrough keyboard's range (KEY_BRIGHTNESS_CYCLE)
+        let mut high_key = key_bits;
+        set(&mut high_key, 0x243);
+        assert!(!only_passthrough_events(&ev_bits, &high_key));
+    }
+
+    #[test]
+    fn test_combo_bindings() {
+        let mut bindings = KeyBindings::default();
+        bindings.add_combo(parse_combo("super+h").unwrap(), KeyAction::Toggle);
+        bindings.add_combo(parse_combo("super+shift+h").unwrap(), KeyAction::Cancel);
+
+        let key = |code, value| InputEvent::new(EV_KEY, code, value);
+        assert_eq!(bindings.handle(&key(KEY_H, 1)), (None, Forward::Event));
+        assert_eq!(bindings.handle(&key(KEY_H, 0)), (None, Forward::Event));
+
+        // Right Super works like left Super; the combo key is swallowed until released
+        assert_eq!(bindings.handle(&key(KEY_RIGHTMETA, 1)), (None, Forward::Event));
+        assert_eq!(bindings.handle(&key(KEY_H, 1)), (Some(KeyAction::Toggle), Forward::Nothing));
+        assert_eq!(bindings.handle(&key(KEY_H, 2)), (None, Forward::Nothing));
+        // Super is released after a neutral key, so the desktop doesn't see a lone tap
+        assert_eq!(bindings.handle(&key(KEY_RIGHTMETA, 0)), (None, Forward::MaskedEvent));
+        assert_eq!(bindings.handle(&key(KEY_H, 0)), (None, Forward::Nothing));
+        bindings.handle(&key(KEY_LEFTMETA, 1));
+        assert_eq!(bindings.handle(&key(KEY_LEFTMETA, 0)), (None, Forward::Event));
+
+        // Extra modifiers pick the combo that matches exactly
+        bindings.handle(&key(KEY_LEFTMETA, 1));
+        bindings.handle(&key(KEY_LEFTSHIFT, 1));
+        assert_eq!(bindings.handle(&key(KEY_H, 1)), (Some(KeyAction::Cancel), Forward::Nothing));
+        bindings.handle(&key(KEY_H, 0));
+        bindings.handle(&key(KEY_LEFTCTRL, 1));
+        assert_eq!(bindings.handle(&key(KEY_H, 1)), (None, Forward::Event));
+    }
+
+    #[test]
+    fn test_parse_combo() {
+        let combo = parse_combo("Super+Shift+H").unwrap();
+        assert_eq!(combo.modifiers, vec![KEY_LEFTMETA, KEY_LEFTSHIFT]);
+        assert_eq!(combo.key, KEY_H);
+        assert_eq!(parse_combo("f13").unwrap().key, KEY_F13);
+        assert!(parse_combo("ctrl+shift").is_err());
+        assert!(parse_combo("super+hh").is_err());
+    }
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(parse_key("F13").unwrap(), KEY_F13);
+        assert_eq!(parse_key("rightctrl").unwrap(), KEY_RIGHTCTRL);
+        assert_eq!(parse_key("pause").unwrap(), KEY_PAUSE);
+        assert!(parse_key("f25").is_err());
+        assert!(parse_key("hyper").is_err());
+    }
+}
@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+use std::fs;
+use tracing::{debug, warn};
+
+use crate::input_event::*;
+
+/// Key press (plus modifiers) that produces a character on a given layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyStroke {
+    pub keycode: u16,
+    pub shift: bool,
+    pub altgr: bool,
+}
+
+impl KeyStroke {
+    fn new(keycode: u16, shift: bool, altgr: bool) -> Self {
+        Self {
+            keycode,
+            shift,
+            altgr,
+        }
+    }
+}
+
+/// Character to keystroke table for one XKB layout/variant
+#[derive(Debug, Clone)]
+pub struct KeyboardLayout {
+    name: String,
+    map: HashMap<char, KeyStroke>,
+}
+
+// Placeholder for positions that hold a dead key (or nothing); those characters
+// are left to the unicode input method instead
+const NONE: char = '\0';
+
+// Key rows of the main block, in XKB order
+const ROW_E: &[u16] = &[
+    KEY_GRAVE, KEY_1, KEY_2, KEY_3, KEY_4, KEY_5, KEY_6, KEY_7, KEY_8, KEY_9, KEY_0, KEY_MINUS,
+    KEY_EQUAL,
+];
+const ROW_D: &[u16] = &[
+    KEY_Q, KEY_W, KEY_E, KEY_R, KEY_T, KEY_Y, KEY_U, KEY_I, KEY_O, KEY_P, KEY_LEFTBRACE,
+    KEY_RIGHTBRACE, KEY_BACKSLASH,
+];
+const ROW_C: &[u16] = &[
+    KEY_A, KEY_S, KEY_D, KEY_F, KEY_G, KEY_H, KEY_J, KEY_K, KEY_L, KEY_SEMICOLON, KEY_APOSTROPHE,
+];
+const ROW_B: &[u16] = &[
+    KEY_102ND, KEY_Z, KEY_X, KEY_C, KEY_V, KEY_B, KEY_N, KEY_M, KEY_COMMA, KEY_DOT, KEY_SLASH,
+];
+
+/// Shipped layout table: (row keys, base level, shift level) plus AltGr entries
+struct LayoutDef {
+    layout: &'static str,
+    variant: &'static str,
+    rows: &'static [(&'static [u16], &'static str, &'static str)],
+    altgr: &'static [(u16, char, char)],
+}
+
+const US: LayoutDef = LayoutDef {
+    layout: "us",
+    variant: "",
+    rows: &[
+        (ROW_E, "`1234567890-=", "~!@#$%^&*()_+"),
+        (ROW_D, "qwertyuiop[]\\", "QWERTYUIOP{}|"),
+        (ROW_C, "asdfghjkl;'", "ASDFGHJKL:\""),
+        (ROW_B, "\0zxcvbnm,./", "\0ZXCVBNM<>?"),
+    ],
+    altgr: &[],
+};
+
+const US_DVORAK: LayoutDef = LayoutDef {
+    layout: "us",
+    variant: "dvorak",
+    rows: &[
+        (ROW_E, "`1234567890[]", "~!@#$%^&*(){}"),
+        (ROW_D, "',.pyfgcrl/=\\", "\"<>PYFGCRL?+|"),
+        (ROW_C, "aoeuidhtns-", "AOEUIDHTNS_"),
+        (ROW_B, "\0;qjkxbmwvz", "\0:QJKXBMWVZ"),
+    ],
+    altgr: &[],
+};
+
+const US_COLEMAK: LayoutDef = LayoutDef {
+    layout: "us",
+    variant: "colemak",
+    rows: &[
+        (ROW_E, "`1234567890-=", "~!@#$%^&*()_+"),
+        (ROW_D, "qwfpgjluy;[]\\", "QWFPGJLUY:{}|"),
+        (ROW_C, "arstdhneio'", "ARSTDHNEIO\""),
+        (ROW_B, "\0zxcvbkm,./", "\0ZXCVBKM<>?"),
+    ],
+    altgr: &[],
+};
+
+const DE: LayoutDef = LayoutDef {
+    layout: "de",
+    variant: "",
+    rows: &[
+        (ROW_E, "\x001234567890ß\0", "°!\"§$%&/()=?\0"),
+        (ROW_D, "qwertzuiopü+#", "QWERTZUIOPÜ*'"),
+        (ROW_C, "asdfghjklöä", "ASDFGHJKLÖÄ"),
+        (ROW_B, "<yxcvbnm,.-", ">YXCVBNM;:_"),
+    ],
+    altgr: &[
+        (KEY_2, '²', NONE),
+        (KEY_3, '³', NONE),
+        (KEY_7, '{', NONE),
+        (KEY_8, '[', NONE),
+        (KEY_9, ']', NONE),
+        (KEY_0, '}', NONE),
+        (KEY_MINUS, '\\', NONE),
+        (KEY_Q, '@', NONE),
+        (KEY_E, '€', NONE),
+        (KEY_M, 'µ', NONE),
+        (KEY_102ND, '|', NONE),
+    ],
+};
+
+const DE_NODEADKEYS: LayoutDef = LayoutDef {
+    layout: "de",
+    variant: "nodeadkeys",
+    rows: &[
+        (ROW_E, "^1234567890ß´", "°!\"§$%&/()=?`"),
+        (ROW_D, "qwertzuiopü+#", "QWERTZUIOPÜ*'"),
+        (ROW_C, "asdfghjklöä", "ASDFGHJKLÖÄ"),
+        (ROW_B, "<yxcvbnm,.-", ">YXCVBNM;:_"),
+    ],
+    altgr: &[
+        (KEY_2, '²', NONE),
+        (KEY_3, '³', NONE),
+        (KEY_7, '{', NONE),
+        (KEY_8, '[', NONE),
+        (KEY_9, ']', NONE),
+        (KEY_0, '}', NONE),
+        (KEY_MINUS, '\\', NONE),
+        (KEY_Q, '@', NONE),
+        (KEY_E, '€', NONE),
+        (KEY_RIGHTBRACE, '~', NONE),
+        (KEY_M, 'µ', NONE),
+        (KEY_102ND, '|', NONE),
+    ],
+};
+
+const FR: LayoutDef = LayoutDef {
+    layout: "fr",
+    variant: "",
+    rows: &[
+        (ROW_E, "²&é\"'(-è_çà)=", "\x001234567890°+"),
+        (ROW_D, "azertyuiop\0$*", "AZERTYUIOP\0£µ"),
+        (ROW_C, "qsdfghjklmù", "QSDFGHJKLM%"),
+        (ROW_B, "<wxcvbn,;:!", ">WXCVBN?./§"),
+    ],
+    altgr: &[
+        (KEY_2, '~', NONE),
+        (KEY_3, '#', NONE),
+        (KEY_4, '{', NONE),
+        (KEY_5, '[', NONE),
+        (KEY_6, '|', NONE),
+        (KEY_7, '`', NONE),
+        (KEY_8, '\\', NONE),
+        (KEY_9, '^', NONE),
+        (KEY_0, '@', NONE),
+        (KEY_MINUS, ']', NONE),
+        (KEY_EQUAL, '}', NONE),
+        (KEY_E, '€', NONE),
+        (KEY_RIGHTBRACE, '¤', NONE),
+    ],
+};
+
+const LAYOUTS: &[LayoutDef] = &[US, US_DVORAK, US_COLEMAK, DE, DE_NODEADKEYS, FR];
+
+impl Default for KeyboardLayout {
+    fn default() -> Self {
+        Self::from_def(&US)
+    }
+}
+
+impl KeyboardLayout {
+    /// Look up a shipped layout by XKB layout and variant names
+    /// Unknown variants fall back to the base layout, unknown layouts to US
+    pub fn new(layout: &str, variant: Option<&str>) -> Self {
+        // Accept "us(dvorak)" and "us:dvorak" as well as separate names
+        let (layout, inline_variant) = match layout.find(['(', ':']) {
+            Some(pos) => (
+                &layout[..pos],
+                Some(layout[pos + 1..].trim_end_matches(')')),
+            ),
+            None => (layout, None),
+        };
+        let layout = layout.trim();
+        let variant = variant
+            .or(inline_variant)
+            .map(str::trim)
+            .filter(|v| !v.is_empty() && *v != "basic");
+
+        // Allow the common variant names on their own
+        let (layout, variant) = match (layout, variant) {
+            ("dvorak", None) | ("colemak", None) => ("us", Some(layout)),
+            other => other,
+        };
+
+        if let Some(variant) = variant {
+            if let Some(def) = LAYOUTS
+                .iter()
+                .find(|d| d.layout == layout && d.variant == variant)
+            {
+                return Self::from_def(def);
+            }
+            warn!(
+                "Keyboard variant '{}({})' is not supported, using '{}'",
+                layout, variant, layout
+            );
+        }
+
+        match LAYOUTS
+            .iter()
+            .find(|d| d.layout == layout && d.variant.is_empty())
+        {
+            Some(def) => Self::from_def(def),
+            None => {
+                warn!(
+                    "Keyboard layout '{}' is not supported (available: {}), falling back to 'us'",
+                    layout,
+                    Self::available().join(", ")
+                );
+                Self::default()
+            }
+        }
+    }
+
+    /// Detect the system layout from XKB_DEFAULT_* or /etc/default/keyboard
+    pub fn detect() -> Option<(String, Option<String>)> {
+        if let Ok(layout) = std::env::var("XKB_DEFAULT_LAYOUT") {
+            let variant = std::env::var("XKB_DEFAULT_VARIANT").ok();
+            return Some(first_of_list(&layout, variant.as_deref()));
+        }
+
+        let contents = fs::read_to_string("/etc/default/keyboard").ok()?;
+        let mut layout = None;
+        let mut variant = None;
+        for line in contents.lines() {
+            if let Some((key, value)) = line.trim().split_once('=') {
+                let value = value.trim().trim_matches('"').to_string();
+                match key.trim() {
+                    "XKBLAYOUT" => layout = Some(value),
+                    "XKBVARIANT" => variant = Some(value),
+                    _ => {}
+                }
+            }
+        }
+        let layout = layout.filter(|l| !l.is_empty())?;
+        debug!("Detected keyboard layout from /etc/default/keyboard: {}", layout);
+        Some(first_of_list(&layout, variant.as_deref()))
+    }
+
+    /// Names of the shipped layouts, e.g. "us(dvorak)"
+    pub fn available() -> Vec<String> {
+        LAYOUTS
+            .iter()
+            .map(|d| {
+                if d.variant.is_empty() {
+                    d.layout.to_string()
+                } else {
+                    format!("{}({})", d.layout, d.variant)
+                }
+            })
+            .collect()
+    }
+
+    fn from_def(def: &LayoutDef) -> Self {
+        let mut map = HashMap::new();
+
+        // Keys that are the same on every layout
+        map.insert(' ', KeyStroke::new(KEY_SPACE, false, false));
+        map.insert('\n', KeyStroke::new(KEY_ENTER, false, false));
+        map.insert('\t', KeyStroke::new(KEY_TAB, false, false));
+
+        for &(keys, base, shifted) in def.rows {
+            for (&keycode, c) in keys.iter().zip(base.chars()) {
+                if c != NONE {
+                    map.entry(c).or_insert(KeyStroke::new(keycode, false, false));
+                }
+            }
+            for (&keycode, c) in keys.iter().zip(shifted.chars()) {
+                if c != NONE {
+                    map.entry(c).or_insert(KeyStroke::new(keycode, true, false));
+                }
+            }
+        }
+
+        for &(keycode, c, shifted) in def.altgr {
+            if c != NONE {
+                map.entry(c).or_insert(KeyStroke::new(keycode, false, true));
+            }
+            if shifted != NONE {
+                map.entry(shifted).or_insert(KeyStroke::new(keycode, true, true));
+            }
+        }
+
+        let name = if def.variant.is_empty() {
+            def.layout.to_string()
+        } else {
+            format!("{}({})", def.layout, def.variant)
+        };
+
+        Self { name, map }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Keystroke that types `c`, if the layout has one
+    pub fn lookup(&self, c: char) -> Option<KeyStroke> {
+        self.map.get(&c).copied()
+    }
+
+    /// Whether any character on this layout needs AltGr
+    pub fn uses_altgr(&self) -> bool {
+        self.map.values().any(|k| k.altgr)
+    }
+
+    /// Keycode for a shortcut given by its US key position (e.g. KEY_Z for Ctrl+Z)
+    /// Applications match shortcut letters by keysym, so letters move with the layout
+    pub fn shortcut_keycode(&self, us_keycode: u16) -> u16 {
+        let letter = US
+            .rows
+            .iter()
+            .flat_map(|(keycodes, base, _)| keycodes.iter().zip(base.chars()))
+            .find(|&(&keycode, _)| keycode == us_keycode)
+            .map(|(_, c)| c)
+            .filter(char::is_ascii_lowercase);
+        letter
+            .and_then(|c| self.lookup(c))
+            .filter(|k| !k.shift && !k.altgr)
+            .map_or(us_keycode, |k| k.keycode)
+    }
+}
+
+// XKB settings may list several layouts ("us,de"); the first one is active by default
+fn first_of_list(layout: &str, variant: Option<&str>) -> (String, Option<String>) {
+    let layout = layout.split(',').next().unwrap_or_default().trim().to_string();
+    let variant = variant
+        .and_then(|v| v.split(',').next())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty());
+    (layout, variant)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_rows_match_key_counts() {
+        for def in LAYOUTS {
+            for (keys, base, shifted) in def.rows {
+                assert_eq!(keys.len(), base.chars().count(), "{} {}: {}", def.layout, def.variant, base);
+                assert_eq!(keys.len(), shifted.chars().count(), "{} {}: {}", def.layout, def.variant, shifted);
+            }
+        }
+    }
+
+    #[test]
+    fn test_us_layout() {
+        let layout = KeyboardLayout::new("us", None);
+        assert_eq!(layout.name(), "us");
+        assert_eq!(layout.lookup('a'), Some(KeyStroke::new(KEY_A, false, false)));
+        assert_eq!(layout.lookup('Z'), Some(KeyStroke::new(KEY_Z, true, false)));
+        assert_eq!(layout.lookup('?'), Some(KeyStroke::new(KEY_SLASH, true, false)));
+        assert_eq!(layout.lookup('\n'), Some(KeyStroke::new(KEY_ENTER, false, false)));
+        assert_eq!(layout.lookup('é'), None);
+        assert!(!layout.uses_altgr());
+    }
+
+    #[test]
+    fn test_german_layout() {
+        let layout = KeyboardLayout::new("de", None);
+        assert_eq!(layout.lookup('z'), Some(KeyStroke::new(KEY_Y, false, false)));
+        assert_eq!(layout.lookup('y'), Some(KeyStroke::new(KEY_Z, false, false)));
+        assert_eq!(layout.lookup('ß'), Some(KeyStroke::new(KEY_MINUS, false, false)));
+        assert_eq!(layout.lookup('?'), Some(KeyStroke::new(KEY_MINUS, true, false)));
+        assert_eq!(layout.lookup('@'), Some(KeyStroke::new(KEY_Q, false, true)));
+        assert_eq!(layout.lookup('>'), Some(KeyStroke::new(KEY_102ND, true, false)));
+        // Dead keys are left to the unicode input method
+        assert_eq!(layout.lookup('^'), None);
+        assert!(layout.uses_altgr());
+
+        let nodead = KeyboardLayout::new("de", Some("nodeadkeys"));
+        assert_eq!(nodead.lookup('^'), Some(KeyStroke::new(KEY_GRAVE, false, false)));
+        assert_eq!(nodead.lookup('~'), Some(KeyStroke::new(KEY_RIGHTBRACE, false, true)));
+    }
+
+    #[test]
+    fn test_french_layout() {
+        let layout = KeyboardLayout::new("fr", None);
+        assert_eq!(layout.lookup('a'), Some(KeyStroke::new(KEY_Q, false, false)));
+        assert_eq!(layout.lookup('1'), Some(KeyStroke::new(KEY_1, true, false)));
+        assert_eq!(layout.lookup('m'), Some(KeyStroke::new(KEY_SEMICOLON, false, false)));
+        assert_eq!(layout.lookup('é'), Some(KeyStroke::new(KEY_2, false, false)));
+        assert_eq!(layout.lookup('@'), Some(KeyStroke::new(KEY_0, false, true)));
+    }
+
+    #[test]
+    fn test_variant_name_forms() {
+        assert_eq!(KeyboardLayout::new("us(dvorak)", None).name(), "us(dvorak)");
+        assert_eq!(KeyboardLayout::new("us:colemak", None).name(), "us(colemak)");
+        assert_eq!(KeyboardLayout::new("dvorak", None).name(), "us(dvorak)");
+        assert_eq!(KeyboardLayout::new("us", Some("colemak")).name(), "us(colemak)");
+
+        let dvorak = KeyboardLayout::new("us", Some("dvorak"));
+        assert_eq!(dvorak.lookup('s'), Some(KeyStroke::new(KEY_SEMICOLON, false, false)));
+    }
+
+    #[test]
+    fn test_unknown_layout_falls_back() {
+        assert_eq!(KeyboardLayout::new("xx", None).name(), "us");
+        assert_eq!(KeyboardLayout::new("de", Some("neo")).name(), "de");
+    }
+
+    #[test]
+    fn test_shortcut_keycode_follows_letters() {
+        assert_eq!(KeyboardLayout::default().shortcut_keycode(KEY_Z), KEY_Z);
+        assert_eq!(KeyboardLayout::new("de", None).shortcut_keycode(KEY_Z), KEY_Y);
+        assert_eq!(KeyboardLayout::new("us", Some("dvorak")).shortcut_keycode(KEY_V), KEY_DOT);
+        // Non-letter keys stay where they are
+        assert_eq!(KeyboardLayout::new("fr", None).shortcut_keycode(KEY_1), KEY_1);
+        assert_eq!(KeyboardLayout::new("de", None).shortcut_keycode(KEY_ENTER), KEY_ENTER);
+    }
+
+    #[test]
+    fn test_first_of_list() {
+        assert_eq!(
+            first_of_list("de,us", Some("nodeadkeys,")),
+            ("de".to_string(), Some("nodeadkeys".to_string()))
+        );
+        assert_eq!(first_of_list("fr", Some("")), ("fr".to_string(), None));
+    }
+}
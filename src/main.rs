@@ -4,31 +4,65 @@ use nix::unistd::{getgid, getuid, setgid, setuid, Gid, Uid};
 use parking_lot::Mutex;
 use std::env;
 use std::sync::mpsc;
-use tokio::sync::mpsc as tokio_mpsc;
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 mod audio_control;
 mod audio_input;
+mod clipboard;
+mod code_mode;
+mod config;
 mod dbus_service;
 mod input_event;
+mod inverse_normalization;
+mod key_listener;
+mod keyboard_layout;
+#[cfg(test)]
+mod mock_flux;
+mod output_worker;
+mod shutdown;
+mod speech_recognizer;
+mod spoken_punctuation;
 mod stt_client;
+mod text_transform;
 mod tray_icon;
+mod unicode_input;
 mod virtual_keyboard;
+mod voice_commands;
 mod whisper_client;
 
 use audio_control::AudioControl;
 use audio_input::AudioInput;
-use stt_client::{AudioBuffer, SttClient};
-use virtual_keyboard::{RealKeyboardHardware, VirtualKeyboard};
-use whisper_client::WhisperClient;
-
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum SttProvider {
-    WebSocket,  // Deepgram or similar WebSocket-based STT
-    Rest,       // OpenAI Whisper or similar REST-based STT
+use clipboard::{Clipboard, PasteShortcut};
+use code_mode::{CodeFormatter, CodeMode};
+use config::Config;
+use inverse_normalization::InverseNormalizer;
+use key_listener::{KeyAction, KeyBindings, KeyListener};
+use keyboard_layout::KeyboardLayout;
+use output_worker::{OutputEvent, OutputWorker};
+use shutdown::ShutdownMode;
+use spoken_punctuation::SpokenPunctuation;
+use speech_recognizer::{EventSink, RecognizerSession, RecognizerSettings, SttProvider, StatusSink};
+use stt_client::{ConnectionStatus, SttEvent};
+use text_transform::{CaseFixes, Dictionary, FillerWords, TextPipeline, Whitespace};
+use unicode_input::{ComposeTable, UnicodeInput};
+use virtual_keyboard::{OutputMode, RealKeyboardHardware, StabilityPolicy, TurnEvent, TurnSeparator, VirtualKeyboard};
+use voice_commands::VoiceCommands;
+
+/// Speech-to-text connection settings from the command line
+#[derive(Debug, Clone, Copy)]
+struct SttSettings<'a> {
+    provider: SttProvider,
+    url: Option<&'a String>,
+    eager_eot_threshold: Option<f64>,
+    eot_threshold: Option<f64>,
+    inactivity_timeout: u64,
+    language: &'a str,
+    model: &'a str,
+    on_shutdown: ShutdownMode,
+    stop_timeout: Duration,
 }
 
 #[derive(Debug)]
@@ -149,9 +183,9 @@ async fn main() -> Result<()> {
         .arg(
             Arg::new("stt-provider")
                 .long("stt-provider")
-                .help("STT provider type: 'websocket' (Deepgram) or 'rest' (OpenAI Whisper)")
+                .help(format!("STT provider type: {}", SttProvider::help_choices()))
                 .value_name("PROVIDER")
-                .default_value("websocket"),
+                .default_value(SttProvider::default().name()),
         )
         .arg(
             Arg::new("stt-url")
@@ -191,10 +225,22 @@ async fn main() -> Result<()> {
                 .value_name("SECONDS")
                 .default_value("30"),
         )
+        .arg(
+            Arg::new("on-shutdown")
+                .long("on-shutdown")
+                .help("What happens to the session in progress on Ctrl+C or SIGTERM: 'flush' (transcribe and type it) or 'cancel' (default: flush)")
+                .value_name("MODE"),
+        )
+        .arg(
+            Arg::new("stop-timeout")
+                .long("stop-timeout")
+                .help("On toggle off, wait this long for the final transcript before typing what is pending (default: 3)")
+                .value_name("SECONDS"),
+        )
         .arg(
             Arg::new("language")
                 .long("language")
-                .help("Language code for speech recognition (default: en)")
+                .help("Language code for speech recognition and number formatting (default: en)")
                 .value_name("LANGUAGE")
                 .default_value("en"),
         )
@@ -205,6 +251,152 @@ async fn main() -> Result<()> {
                 .value_name("MODEL")
                 .default_value("whisper-1"),
         )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .help("Config file path (default: ~/.config/voice-keyboard/config.toml)")
+                .value_name("FILE_PATH"),
+        )
+        .arg(
+            Arg::new("keyboard-layout")
+                .long("keyboard-layout")
+                .help("XKB keyboard layout, e.g. 'us', 'de', 'fr', 'us(dvorak)' (default: system layout, else us)")
+                .value_name("LAYOUT"),
+        )
+        .arg(
+            Arg::new("keyboard-variant")
+                .long("keyboard-variant")
+                .help("XKB keyboard variant, e.g. 'nodeadkeys', 'dvorak', 'colemak'")
+                .value_name("VARIANT"),
+        )
+        .arg(
+            Arg::new("unicode-input")
+                .long("unicode-input")
+                .help("How to type characters without a direct key: 'ctrl-shift-u' (GTK/IBus), 'compose' or 'none' (default: ctrl-shift-u)")
+                .value_name("METHOD"),
+        )
+        .arg(
+            Arg::new("compose-key")
+                .long("compose-key")
+                .help("Compose key used by --unicode-input compose: ralt, rctrl, menu, caps, lwin, rwin, scrolllock (default: ralt)")
+                .value_name("KEY"),
+        )
+        .arg(
+            Arg::new("mid-turn-commands")
+                .long("mid-turn-commands")
+                .help("Run voice commands as soon as speech continues after them, not only at the end of a turn")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("delay-mode-commands")
+                .long("delay-mode-commands")
+                .help("Also run voice commands without --live-mode (default: turns are typed verbatim, except a turn that is just \"scratch that\" or a code mode switch)")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("spoken-punctuation")
+                .long("spoken-punctuation")
+                .help("Turn spoken punctuation (\"comma\", \"open paren\", ...) into symbols: 'on' or 'off' (default: off, or per provider from the config file)")
+                .value_name("ON_OFF"),
+        )
+        .arg(
+            Arg::new("transform")
+                .long("transform")
+                .help("Comma-separated text post-processing stages in order: spoken_punctuation, numbers, filler_words, dictionary, case, whitespace (default: spoken_punctuation,numbers,dictionary,case,whitespace)")
+                .value_name("STAGES"),
+        )
+        .arg(
+            Arg::new("code-mode")
+                .long("code-mode")
+                .help("Start in programmer dictation mode (switch with \"code mode on/off\" or D-Bus)")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("push-to-talk")
+                .long("push-to-talk")
+                .help("Record only while this key is held, e.g. 'f13', 'rightctrl', 'pause'")
+                .value_name("KEY"),
+        )
+        .arg(
+            Arg::new("toggle-hotkey")
+                .long("toggle-hotkey")
+                .help("Key combo that toggles listening, e.g. 'ctrl+alt+v'")
+                .value_name("COMBO"),
+        )
+        .arg(
+            Arg::new("cancel-hotkey")
+                .long("cancel-hotkey")
+                .help("Key combo that cancels the recording without transcribing, e.g. 'ctrl+alt+shift+v'")
+                .value_name("COMBO"),
+        )
+        .arg(
+            Arg::new("hotkey-device")
+                .long("hotkey-device")
+                .alias("push-to-talk-device")
+                .help("Input device to watch for hotkeys (default: every keyboard that has the keys)")
+                .value_name("DEVICE_PATH"),
+        )
+        .arg(
+            Arg::new("hotkey-grab")
+                .long("hotkey-grab")
+                .alias("push-to-talk-grab")
+                .help("Grab the keyboards so hotkeys never reach applications; other keys are passed on")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("output-mode")
+                .long("output-mode")
+                .help("How finished turns are entered in delay mode: 'type' or 'paste' via the clipboard (default: type)")
+                .value_name("MODE"),
+        )
+        .arg(
+            Arg::new("turn-separator")
+                .long("turn-separator")
+                .help("Typed after each turn: 'auto' (a space unless the turn ended with a newline), 'space' or 'none' (default: auto)")
+                .value_name("SEPARATOR"),
+        )
+        .arg(
+            Arg::new("sentence-case")
+                .long("sentence-case")
+                .help("Capitalize a turn after a sentence end and lowercase common words mid-sentence: 'on' or 'off' (default: on)")
+                .value_name("ON_OFF"),
+        )
+        .arg(
+            Arg::new("stable-updates")
+                .long("stable-updates")
+                .help("In live mode, only type a word once it has stayed the same for this many updates (default: 1)")
+                .value_name("N"),
+        )
+        .arg(
+            Arg::new("stable-confidence")
+                .long("stable-confidence")
+                .help("In live mode, type words at or above this confidence (0.0-1.0) right away, even if not yet stable")
+                .value_name("LEVEL"),
+        )
+        .arg(
+            Arg::new("paste-shortcut")
+                .long("paste-shortcut")
+                .help("Shortcut used in paste mode: 'ctrl-v', 'ctrl-shift-v' (terminals) or 'shift-insert' (default: ctrl-v)")
+                .value_name("SHORTCUT"),
+        )
+        .arg(
+            Arg::new("clipboard-copy-command")
+                .long("clipboard-copy-command")
+                .help("Shell command that sets the clipboard from stdin (default: wl-copy or xclip)")
+                .value_name("COMMAND"),
+        )
+        .arg(
+            Arg::new("clipboard-paste-command")
+                .long("clipboard-paste-command")
+                .help("Shell command that prints the clipboard (default: wl-paste or xclip)")
+                .value_name("COMMAND"),
+        )
+        .arg(
+            Arg::new("compose-table")
+                .long("compose-table")
+                .help("File with extra compose sequences, one '<char> <keys>' per line")
+                .value_name("FILE_PATH"),
+        )
         .get_matches();
 
     // Parse and validate thresholds from command line BEFORE creating keyboard
@@ -258,45 +450,404 @@ async fn main() -> Result<()> {
         .unwrap_or("whisper-1");
 
     // Parse STT provider
-    let stt_provider = match matches.get_one::<String>("stt-provider").map(|s| s.as_str()) {
-        Some("websocket") => SttProvider::WebSocket,
-        Some("rest") => SttProvider::Rest,
-        Some(provider) => {
-            error!("Invalid STT provider: {}. Must be 'websocket' or 'rest'", provider);
+    let stt_provider = match matches.get_one::<String>("stt-provider").map(|s| SttProvider::from_name(s)) {
+        Some(Ok(provider)) => provider,
+        Some(Err(e)) => {
+            error!("Error: {}", e);
             std::process::exit(1);
         }
-        None => SttProvider::WebSocket, // Default
+        None => SttProvider::default(),
+    };
+
+    // Load the config file; command line flags override its values
+    let config = Config::load(matches.get_one::<String>("config").map(|s| s.as_str()))
+        .context("Failed to load config")?;
+
+    // Parse keyboard layout: command line, config file, system setting, then US
+    let keyboard_layout = match matches
+        .get_one::<String>("keyboard-layout")
+        .cloned()
+        .or_else(|| config.keyboard.layout.clone())
+    {
+        Some(layout) => {
+            let variant = matches
+                .get_one::<String>("keyboard-variant")
+                .cloned()
+                .or_else(|| config.keyboard.variant.clone());
+            KeyboardLayout::new(&layout, variant.as_deref())
+        }
+        None => match KeyboardLayout::detect() {
+            Some((layout, variant)) => KeyboardLayout::new(&layout, variant.as_deref()),
+            None => KeyboardLayout::default(),
+        },
+    };
+
+    // Parse unicode input method
+    let mut compose_table = ComposeTable::default();
+    if let Some(key) = matches
+        .get_one::<String>("compose-key")
+        .or(config.keyboard.compose_key.as_ref())
+    {
+        match ComposeTable::key_from_name(key) {
+            Ok(keycode) => compose_table.set_compose_key(keycode),
+            Err(e) => {
+                error!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = matches
+        .get_one::<String>("compose-table")
+        .or(config.keyboard.compose_table.as_ref())
+    {
+        compose_table
+            .load_file(path)
+            .context("Failed to load compose table")?;
+    }
+    let unicode_input = match UnicodeInput::from_name(
+        matches
+            .get_one::<String>("unicode-input")
+            .or(config.keyboard.unicode_input.as_ref())
+            .map(|s| s.as_str())
+            .unwrap_or("ctrl-shift-u"),
+        compose_table,
+    ) {
+        Ok(method) => method,
+        Err(e) => {
+            error!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let UnicodeInput::Compose(table) = &unicode_input {
+        if keyboard_layout.uses_altgr() && table.compose_key() == input_event::KEY_RIGHTALT {
+            warn!("Right Alt is AltGr on layout '{}', so it can't be the compose key; set --compose-key", keyboard_layout.name());
+        }
+    }
+
+    // Parse output mode
+    let output_mode = match OutputMode::from_name(
+        matches
+            .get_one::<String>("output-mode")
+            .or(config.output.mode.as_ref())
+            .map(|s| s.as_str())
+            .unwrap_or("type"),
+    ) {
+        Ok(mode) => mode,
+        Err(e) => {
+            error!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let turn_separator = match TurnSeparator::from_name(
+        matches
+            .get_one::<String>("turn-separator")
+            .or(config.output.turn_separator.as_ref())
+            .map(|s| s.as_str())
+            .unwrap_or("auto"),
+    ) {
+        Ok(separator) => separator,
+        Err(e) => {
+            error!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let sentence_case = match matches.get_one::<String>("sentence-case").map(|s| s.as_str()) {
+        Some("on") => true,
+        Some("off") => false,
+        Some(other) => {
+            error!("Error: invalid sentence case setting '{}': must be 'on' or 'off'", other);
+            std::process::exit(1);
+        }
+        None => config.output.sentence_case.unwrap_or(true),
+    };
+    let stable_updates = match matches.get_one::<String>("stable-updates") {
+        Some(s) => match s.parse::<usize>() {
+            Ok(n) => Some(n),
+            Err(_) => {
+                error!("Error: invalid stable-updates '{}': must be a whole number", s);
+                std::process::exit(1);
+            }
+        },
+        None => config.output.stable_updates,
+    };
+    let stable_confidence = match matches.get_one::<String>("stable-confidence") {
+        Some(s) => match s.parse::<f64>() {
+            Ok(level) => Some(level),
+            Err(_) => {
+                error!("Error: invalid stable-confidence '{}': must be a number", s);
+                std::process::exit(1);
+            }
+        },
+        None => config.output.stable_confidence,
+    };
+    if stable_updates == Some(0) {
+        error!("Error: stable-updates must be at least 1");
+        std::process::exit(1);
+    }
+    if let Some(level) = stable_confidence {
+        if !(0.0..=1.0).contains(&level) {
+            error!("Error: stable-confidence must be between 0.0 and 1.0 (got {})", level);
+            std::process::exit(1);
+        }
+    }
+    let stability = StabilityPolicy {
+        min_updates: stable_updates.unwrap_or(1),
+        min_confidence: stable_confidence,
+    };
+    let on_shutdown = match ShutdownMode::from_name(
+        matches
+            .get_one::<String>("on-shutdown")
+            .or(config.session.on_shutdown.as_ref())
+            .map(|s| s.as_str())
+            .unwrap_or("flush"),
+    ) {
+        Ok(mode) => mode,
+        Err(e) => {
+            error!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let stop_timeout = match matches.get_one::<String>("stop-timeout") {
+        Some(s) => match s.parse::<f64>() {
+            Ok(secs) => secs,
+            Err(_) => {
+                error!("Error: invalid stop-timeout '{}': must be a number", s);
+                std::process::exit(1);
+            }
+        },
+        None => config.session.stop_timeout.unwrap_or(3.0),
+    };
+    if !(0.0..=60.0).contains(&stop_timeout) {
+        error!("Error: stop-timeout must be between 0 and 60 seconds (got {})", stop_timeout);
+        std::process::exit(1);
+    }
+    let paste_shortcut = match PasteShortcut::from_name(
+        matches
+            .get_one::<String>("paste-shortcut")
+            .or(config.output.paste_shortcut.as_ref())
+            .map(|s| s.as_str())
+            .unwrap_or("ctrl-v"),
+    ) {
+        Ok(shortcut) => shortcut,
+        Err(e) => {
+            error!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let clipboard = match (
+        matches
+            .get_one::<String>("clipboard-copy-command")
+            .or(config.output.copy_command.as_ref()),
+        matches
+            .get_one::<String>("clipboard-paste-command")
+            .or(config.output.paste_command.as_ref()),
+    ) {
+        (Some(copy), Some(paste)) => Some(Clipboard::with_commands(copy, paste)),
+        (None, None) => Clipboard::detect(),
+        _ => {
+            error!("Error: --clipboard-copy-command and --clipboard-paste-command must be set together");
+            std::process::exit(1);
+        }
+    };
+
+    // Voice commands: built-ins plus phrases from the config file
+    let mut commands = VoiceCommands::default();
+    commands.set_mid_turn(
+        matches.get_flag("mid-turn-commands") || config.commands.mid_turn.unwrap_or(false),
+    );
+    commands.set_delay_mode(
+        matches.get_flag("delay-mode-commands") || config.commands.delay_mode.unwrap_or(false),
+    );
+    for (phrase, action) in &config.commands.phrases {
+        if let Err(e) = commands.set(phrase, action) {
+            error!("Error: invalid voice command in config: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // Spoken punctuation is only useful for providers that don't punctuate themselves
+    let spoken_punctuation = match matches.get_one::<String>("spoken-punctuation").map(|s| s.as_str()) {
+        Some("on") => true,
+        Some("off") => false,
+        Some(other) => {
+            error!("Error: invalid spoken punctuation setting '{}': must be 'on' or 'off'", other);
+            std::process::exit(1);
+        }
+        None => config.spoken_punctuation.for_provider(stt_provider).unwrap_or(false),
+    };
+
+    // Text post-processing pipeline, run on every transcript before it is typed
+    let stages: Vec<String> = match matches.get_one::<String>("transform") {
+        Some(list) => list.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect(),
+        None => config
+            .transform
+            .stages
+            .clone()
+            .unwrap_or_else(|| text_transform::DEFAULT_STAGES.iter().map(|s| s.to_string()).collect()),
     };
+    let mut text_pipeline = TextPipeline::default();
+    for stage in &stages {
+        match stage.as_str() {
+            "spoken_punctuation" => {
+                if spoken_punctuation {
+                    text_pipeline.push(SpokenPunctuation);
+                }
+            }
+            "numbers" => match InverseNormalizer::for_language(language) {
+                Some(numbers) => text_pipeline.push(numbers),
+                None => info!("Number formatting is not available for language '{}', leaving numbers as words", language),
+            },
+            "filler_words" => match &config.transform.filler_words {
+                Some(words) => text_pipeline.push(FillerWords::new(words)),
+                None => text_pipeline.push(FillerWords::default()),
+            },
+            "dictionary" => {
+                if !config.transform.dictionary.is_empty() {
+                    let entries = config.transform.dictionary.iter().map(|(p, r)| (p.as_str(), r.as_str()));
+                    match Dictionary::new(entries) {
+                        Ok(dictionary) => text_pipeline.push(dictionary),
+                        Err(e) => {
+                            error!("Error: {:#}", e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
+            "case" => {
+                let mut case = CaseFixes::default();
+                for word in &config.transform.case {
+                    case.set(word);
+                }
+                text_pipeline.push(case);
+            }
+            "whitespace" => text_pipeline.push(Whitespace),
+            other => {
+                error!(
+                    "Error: unknown text transform stage '{}': must be one of {}",
+                    other,
+                    text_transform::STAGES.join(", ")
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+    debug!("Text post-processing stages: {}", stages.join(", "));
+
+    // Code mode formats dictated code instead; the switch is shared with voice commands and D-Bus
+    let code_mode = CodeMode::default();
+    code_mode.set_enabled(matches.get_flag("code-mode"));
+    text_pipeline.set_code_mode(code_mode.clone(), CodeFormatter::new(commands.clone()));
+
+    // Hotkeys read straight from the keyboard
+    let mut key_bindings = KeyBindings::default();
+    if let Some(key) = matches
+        .get_one::<String>("push-to-talk")
+        .or(config.hotkeys.push_to_talk.as_ref())
+    {
+        match key_listener::parse_key(key) {
+            Ok(keycode) => key_bindings.set_push_to_talk(keycode),
+            Err(e) => {
+                error!("Error: invalid push-to-talk key: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    for (arg, configured, action) in [
+        ("toggle-hotkey", &config.hotkeys.toggle, KeyAction::Toggle),
+        ("cancel-hotkey", &config.hotkeys.cancel, KeyAction::Cancel),
+    ] {
+        if let Some(combo) = matches.get_one::<String>(arg).or(configured.as_ref()) {
+            match key_listener::parse_combo(combo) {
+                Ok(combo) => key_bindings.add_combo(combo, action),
+                Err(e) => {
+                    error!("Error: invalid --{}: {}", arg, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
 
     let device_name = "Voice Keyboard";
     let delay_input = !matches.get_flag("live-mode");
 
+    if output_mode == OutputMode::Paste {
+        if !delay_input {
+            info!("Paste output mode only applies without --live-mode; live typing is unchanged");
+        }
+        if clipboard.is_none() {
+            error!("Paste output mode needs a clipboard: no WAYLAND_DISPLAY or DISPLAY, set --clipboard-copy-command/--clipboard-paste-command");
+            std::process::exit(1);
+        }
+    }
+
     // Step 1: Create virtual keyboard while we have root privileges
     debug!("Creating virtual keyboard device (requires root privileges)...");
-    let hardware =
+    let mut hardware =
         RealKeyboardHardware::new(device_name).context("Failed to create keyboard hardware")?;
-    let keyboard = VirtualKeyboard::new(hardware, delay_input);
+    virtual_keyboard::release_keys_on_exit();
+    shutdown::install();
+    hardware.set_layout(keyboard_layout);
+    hardware.set_unicode_input(unicode_input);
+    if output_mode == OutputMode::Paste {
+        if let Some(clipboard) = clipboard {
+            hardware.set_clipboard(clipboard, paste_shortcut);
+        }
+    }
+    let mut keyboard = VirtualKeyboard::new(hardware, delay_input);
+    keyboard.set_output_mode(output_mode);
+    keyboard.set_turn_separator(turn_separator);
+    keyboard.set_sentence_case(sentence_case);
+    keyboard.set_stability(stability);
+    keyboard.set_commands(commands);
+    keyboard.set_code_mode(code_mode.clone());
     debug!("Virtual keyboard created successfully");
 
+    // Input devices are only readable by root, so open them now too
+    let key_listener = if key_bindings.is_empty() || matches.get_flag("test-audio") {
+        None
+    } else {
+        let device = matches
+            .get_one::<String>("hotkey-device")
+            .or(config.hotkeys.device.as_ref());
+        let grab = matches.get_flag("hotkey-grab") || config.hotkeys.grab.unwrap_or(false);
+        Some(
+            KeyListener::open(device.map(|s| s.as_str()), key_bindings, grab)
+                .context("Failed to open keyboard for hotkeys")?,
+        )
+    };
+
     // Step 2: Drop root privileges before initializing audio
     original_user
         .drop_privileges()
         .context("Failed to drop root privileges")?;
 
+    let stt_settings = SttSettings {
+        provider: stt_provider,
+        url: matches.get_one::<String>("stt-url"),
+        eager_eot_threshold,
+        eot_threshold,
+        inactivity_timeout,
+        language,
+        model: stt_model,
+        on_shutdown,
+        stop_timeout: Duration::from_secs_f64(stop_timeout),
+    };
+
+    // Ctrl+C and SIGTERM now end the session cleanly instead of killing the process
+    shutdown::handle_gracefully();
+
     if matches.get_flag("test-audio") {
         let save_audio_path = matches.get_one::<String>("save-audio").map(|s| s.as_str());
         test_audio(save_audio_path).await?;
     } else if matches.get_flag("test-stt") {
-        let stt_url = matches.get_one::<String>("stt-url");
-        test_stt(keyboard, stt_provider, stt_url, eager_eot_threshold, eot_threshold, inactivity_timeout, language, stt_model).await?;
+        test_stt(keyboard, text_pipeline, code_mode, stt_settings, key_listener).await?;
     } else {
         let debug_mode = matches.get_flag("debug-stt");
-        let stt_url = matches.get_one::<String>("stt-url");
 
         if debug_mode {
-            debug_stt(stt_provider, stt_url, eager_eot_threshold, eot_threshold, inactivity_timeout, language, stt_model).await?;
+            debug_stt(stt_settings, key_listener).await?;
         } else {
-            test_stt(keyboard, stt_provider, stt_url, eager_eot_threshold, eot_threshold, inactivity_timeout, language, stt_model).await?;
+            test_stt(keyboard, text_pipeline, code_mode, stt_settings, key_listener).await?;
         }
     }
 
@@ -337,7 +888,7 @@ async fn test_audio(save_audio_path: Option<&str>) -> Result<()> {
     info!("Recording for 5 seconds...");
     let start = std::time::Instant::now();
 
-    while start.elapsed() < Duration::from_secs(5) {
+    while start.elapsed() < Duration::from_secs(5) && !shutdown::requested() {
         if let Ok(level) = rx.try_recv() {
             let bar_length = (level * 50.0) as usize;
             let bar: String = "#".repeat(bar_length);
@@ -355,65 +906,59 @@ async fn test_audio(save_audio_path: Option<&str>) -> Result<()> {
     Ok(())
 }
 
-async fn test_stt(keyboard: VirtualKeyboard<RealKeyboardHardware>, stt_provider: SttProvider, stt_url: Option<&String>, eager_eot_threshold: Option<f64>, eot_threshold: Option<f64>, inactivity_timeout: u64, language: &str, stt_model: &str) -> Result<()> {
+async fn test_stt(keyboard: VirtualKeyboard<RealKeyboardHardware>, text_pipeline: TextPipeline, code_mode: CodeMode, stt_settings: SttSettings<'_>, key_listener: Option<KeyListener>) -> Result<()> {
+    let min_confidence = keyboard.stability().min_confidence;
     info!("Testing speech-to-text functionality...");
 
-    // Wrap keyboard in a mutex to allow mutable access from the closure
-    let keyboard = std::sync::Arc::new(std::sync::Mutex::new(keyboard));
-    let keyboard_clone = keyboard.clone();
-
-    run_stt(stt_provider, stt_url, eager_eot_threshold, eot_threshold, inactivity_timeout, language, stt_model, move |result| {
-        if !result.transcript.is_empty() {
-            info!("Transcription [{}]: {}", result.event, result.transcript);
+    // Typing runs on its own thread so it never holds up the STT connection; keyboard
+    // errors are logged and shown by the tray and D-Bus, and typing carries on with the
+    // next turn
+    let typing_error = Arc::new(Mutex::new(None));
+    let typing_error_output = typing_error.clone();
+    let output = OutputWorker::spawn(keyboard, move |e| {
+        error!("{:#}", e);
+        *typing_error_output.lock() = Some(format!("{:#}", e));
+    });
+    let output_undo = output.clone();
+    let output_cancel = output.clone();
+    let output_shutdown = output.clone();
+    let flush = stt_settings.on_shutdown == ShutdownMode::Flush;
+
+    run_stt(stt_settings, key_listener, code_mode, typing_error, move |event| {
+        if let Some(result) = event.result().filter(|result| !result.transcript.is_empty()) {
+            info!("Transcription [{}]: {}", event.name(), result.transcript);
         }
 
-        let mut kb = keyboard_clone.lock().unwrap();
-
-        // Handle different event types
-        match result.event.as_str() {
-            "EndOfTurn" => {
-                // Finalize the transcript; treat failure as fatal
-                if let Err(e) = kb.finalize_transcript() {
-                    error!("Failed to finalize transcript: {}", e);
-                    std::process::exit(1);
-                }
-            }
-            "EagerEndOfTurn" => {
-                // Eager end of turn detected - finalize transcript
-                info!("Eager end of turn detected, finalizing transcript");
-                if let Err(e) = kb.finalize_transcript() {
-                    error!("Failed to finalize transcript on eager EOT: {}", e);
-                    std::process::exit(1);
-                }
-                // Mark that we've finalized so we don't do it again on EndOfTurn
-                kb.mark_eager_eot_finalized();
-            }
-            "TurnResumed" => {
-                // Turn resumed after eager EOT - reset the flag so we can finalize again
-                info!("Turn resumed, continuing transcription");
-                kb.reset_eager_eot_flag();
-            }
-            _ => {
-                // Handle incremental updates; treat failure as fatal
-                if let Err(e) = kb.update_transcript(&result.transcript) {
-                    error!("Failed to update transcript: {}", e);
-                    std::process::exit(1);
-                }
-            }
-        }
-    })
-    .await
+        // Post-process the text (spoken punctuation, dictionary, ...) before typing it
+        output.send_stt_event(&event, |result| TurnEvent::Update {
+            transcript: text_pipeline.apply(&result.transcript),
+            // The leading words the provider is sure of go through the same stages,
+            // so the keyboard can compare them with the transcript
+            confident: min_confidence
+                .map(|level| text_pipeline.apply(result.confident_prefix(level)))
+                .unwrap_or_default(),
+        });
+    }, move || output_undo.undo_last_turn(), move || output_cancel.send(OutputEvent::SessionCancelled))
+    .await?;
+
+    // Type what the session left open, then destroy the virtual keyboard
+    output_shutdown.shutdown(flush);
+    Ok(())
 }
 
-async fn debug_stt(stt_provider: SttProvider, stt_url: Option<&String>, eager_eot_threshold: Option<f64>, eot_threshold: Option<f64>, inactivity_timeout: u64, language: &str, stt_model: &str) -> Result<()> {
+async fn debug_stt(stt_settings: SttSettings<'_>, key_listener: Option<KeyListener>) -> Result<()> {
     info!("Debugging speech-to-text functionality...");
 
-    run_stt(stt_provider, stt_url, eager_eot_threshold, eot_threshold, inactivity_timeout, language, stt_model, |result| {
+    run_stt(stt_settings, key_listener, CodeMode::default(), Arc::new(Mutex::new(None)), |event| match &event {
+        SttEvent::Connected { request_id } => info!("Connected [{}]", request_id),
+        SttEvent::Error { code, description } => info!("Error [{}]: {}", code, description),
         // Only show non-empty transcriptions
-        if !result.transcript.is_empty() {
-            info!("Transcription [{}]: {}", result.event, result.transcript);
+        _ => {
+            if let Some(result) = event.result().filter(|result| !result.transcript.is_empty()) {
+                info!("Transcription [{}]: {}", event.name(), result.transcript);
+            }
         }
-    })
+    }, || false, || {})
     .await
 }
 
@@ -421,19 +966,52 @@ enum SttCommand {
     Start,
     Stop,
     Cancel, // Stop recording and discard audio without transcription
+    Shutdown(ShutdownMode, mpsc::Sender<()>), // Stop or cancel the session, then end the thread
 }
 
 struct ActiveSttSession {
-    audio_tx: Option<tokio_mpsc::Sender<Vec<u8>>>, // For WebSocket mode
-    _handle: Option<tokio::task::JoinHandle<Result<()>>>, // Kept alive to maintain the async task (WebSocket only)
-    _audio_input: AudioInput, // Kept alive to maintain audio stream
-    audio_buffer: Option<Arc<Mutex<Vec<u8>>>>, // For REST mode - buffer all audio data
+    recognizer_session: Arc<Mutex<Option<Box<dyn RecognizerSession>>>>, // Shared with the audio callback
+    audio_input: AudioInput, // Kept alive to maintain audio stream
+}
+
+impl ActiveSttSession {
+    // Stop recording first, so no audio arrives after the session is over
+    fn take_recognizer_session(self) -> Option<Box<dyn RecognizerSession>> {
+        drop(self.audio_input);
+        self.recognizer_session.lock().take()
+    }
+
+    fn finish(self, timeout: Duration) {
+        if let Some(session) = self.take_recognizer_session() {
+            session.finish(timeout);
+        }
+    }
+
+    fn cancel(self) {
+        if let Some(session) = self.take_recognizer_session() {
+            session.cancel();
+        }
+    }
 }
 
-async fn run_stt<F>(stt_provider: SttProvider, stt_url: Option<&String>, eager_eot_threshold: Option<f64>, eot_threshold: Option<f64>, inactivity_timeout: u64, language: &str, stt_model: &str, on_transcription: F) -> Result<()>
+async fn run_stt<F, U, C>(stt_settings: SttSettings<'_>, key_listener: Option<KeyListener>, code_mode: CodeMode, typing_error: Arc<Mutex<Option<String>>>, on_transcription: F, on_undo: U, on_cancel: C) -> Result<()>
 where
-    F: Fn(stt_client::TranscriptionResult) + Send + 'static + Clone,
+    F: Fn(SttEvent) + Send + Sync + 'static,
+    U: Fn() -> bool + Send + Sync + 'static,
+    C: Fn() + Send + 'static,
 {
+    let SttSettings {
+        provider: stt_provider,
+        url: stt_url,
+        eager_eot_threshold,
+        eot_threshold,
+        inactivity_timeout,
+        language,
+        model: stt_model,
+        on_shutdown,
+        stop_timeout,
+    } = stt_settings;
+
     // Initialize GTK for tray icon
     gtk::init().context("Failed to initialize GTK")?;
     
@@ -449,33 +1027,34 @@ where
     drop(temp_audio);
 
     info!("Voice Keyboard is ready!");
-    info!("STT Provider: {}", match stt_provider {
-        SttProvider::WebSocket => "WebSocket (Deepgram)",
-        SttProvider::Rest => "REST (OpenAI Whisper)",
+    let mut recognizer = stt_provider.recognizer(&RecognizerSettings {
+        url: stt_url.map(|s| s.as_str()),
+        sample_rate,
+        eager_eot_threshold,
+        eot_threshold,
+        language,
+        model: stt_model,
     });
+    info!("STT Provider: {}", recognizer.name());
     if let Some(url) = stt_url {
         info!("STT URL: {}", url);
     }
     info!("Use the tray icon or D-Bus to toggle listening.");
     info!("Press Ctrl+C to quit.");
-    
-    // Only show EOT thresholds for WebSocket mode
-    if stt_provider == SttProvider::WebSocket {
-        if let Some(threshold) = eager_eot_threshold {
-            info!("Eager end-of-turn threshold: {}", threshold);
-        } else {
-            info!("Eager end-of-turn: disabled");
-        }
-        if let Some(threshold) = eot_threshold {
-            info!("Standard end-of-turn threshold: {}", threshold);
-        }
+
+    // Only streaming providers report speech as it happens, which the inactivity timer needs
+    let streams = recognizer.streams();
+    if streams {
         info!("Auto-toggle off after {} seconds of inactivity", inactivity_timeout);
     } else {
-        info!("REST mode: Manually toggle off when done (10 minute maximum to prevent memory overflow)");
+        info!("Batch mode: Manually toggle off when done (10 minute maximum to prevent memory overflow)");
     }
 
     // Shared state for STT active/inactive
     let is_active = Arc::new(Mutex::new(false));
+
+    // State of the streaming connection, shown by the tray and D-Bus
+    let connection_status = Arc::new(Mutex::new(ConnectionStatus::Disconnected));
     
     // Track last voice activity timestamp
     let last_activity = Arc::new(Mutex::new(std::time::Instant::now()));
@@ -488,7 +1067,10 @@ where
     let (cmd_tx, cmd_rx) = mpsc::channel::<SttCommand>();
     
     // Set up D-Bus service
-    let dbus_service = dbus_service::DbusService::new(is_active.clone());
+    let mut dbus_service = dbus_service::DbusService::new(is_active.clone());
+    dbus_service.set_code_mode(code_mode);
+    dbus_service.set_connection_status(connection_status.clone());
+    dbus_service.set_typing_error(typing_error.clone());
     let cmd_tx_dbus = cmd_tx.clone();
     dbus_service.set_toggle_callback(move |new_state| {
         info!("D-Bus toggle: {}", if new_state { "active" } else { "inactive" });
@@ -505,6 +1087,51 @@ where
         // Send cancel command to STT thread
         let _ = cmd_tx_cancel.send(SttCommand::Cancel);
     });
+
+    dbus_service.set_undo_callback(on_undo);
+
+    // Hotkeys feed the same commands as the tray and D-Bus. Push-to-talk records while
+    // the key is held, unless already listening hands-free
+    if let Some(key_listener) = key_listener {
+        let cmd_tx_keys = cmd_tx.clone();
+        let is_active_keys = is_active.clone();
+        let talking = Mutex::new(false);
+        key_listener.spawn(move |action| match action {
+            KeyAction::PushToTalkPressed => {
+                let mut active = is_active_keys.lock();
+                if !*active {
+                    info!("Push-to-talk: active");
+                    *active = true;
+                    *talking.lock() = true;
+                    let _ = cmd_tx_keys.send(SttCommand::Start);
+                }
+            }
+            KeyAction::PushToTalkReleased => {
+                let mut active = is_active_keys.lock();
+                // Skip if the session already ended, e.g. on the inactivity timeout
+                if std::mem::take(&mut *talking.lock()) && *active {
+                    info!("Push-to-talk: inactive");
+                    *active = false;
+                    let _ = cmd_tx_keys.send(SttCommand::Stop);
+                }
+            }
+            KeyAction::Toggle => {
+                let mut active = is_active_keys.lock();
+                *active = !*active;
+                info!("Hotkey toggle: {}", if *active { "active" } else { "inactive" });
+                let cmd = if *active { SttCommand::Start } else { SttCommand::Stop };
+                let _ = cmd_tx_keys.send(cmd);
+            }
+            KeyAction::Cancel => {
+                let mut active = is_active_keys.lock();
+                if *active {
+                    info!("Hotkey cancel: cancelling recording without transcription");
+                    *active = false;
+                    let _ = cmd_tx_keys.send(SttCommand::Cancel);
+                }
+            }
+        });
+    }
     
     // Spawn timeout monitor thread
     let cmd_tx_timeout = cmd_tx.clone();
@@ -517,26 +1144,23 @@ where
             if *is_active_monitor.lock() {
                 let elapsed = last_activity_monitor.lock().elapsed();
                 
-                match stt_provider {
-                    SttProvider::WebSocket => {
-                        // Streaming mode: auto-toggle based on inactivity
-                        if elapsed >= Duration::from_secs(inactivity_timeout) {
-                            info!("Inactivity timeout reached ({} seconds), auto-toggling off", inactivity_timeout);
-                            // Update is_active state first to prevent repeated logs and update tray icon
-                            *is_active_monitor.lock() = false;
-                            // Send stop command to STT thread
-                            let _ = cmd_tx_timeout.send(SttCommand::Stop);
-                        }
+                if streams {
+                    // Streaming mode: auto-toggle based on inactivity
+                    if elapsed >= Duration::from_secs(inactivity_timeout) {
+                        info!("Inactivity timeout reached ({} seconds), auto-toggling off", inactivity_timeout);
+                        // Update is_active state first to prevent repeated logs and update tray icon
+                        *is_active_monitor.lock() = false;
+                        // Send stop command to STT thread
+                        let _ = cmd_tx_timeout.send(SttCommand::Stop);
                     }
-                    SttProvider::Rest => {
-                        // REST mode: maximum recording time to prevent memory overflow
-                        const MAX_RECORDING_TIME_SECS: u64 = 600; // 10 minutes
-                        if elapsed >= Duration::from_secs(MAX_RECORDING_TIME_SECS) {
-                            info!("Maximum recording time reached ({} minutes), auto-toggling off to prevent memory overflow", MAX_RECORDING_TIME_SECS / 60);
-                            // Update is_active state first to prevent repeated logs and update tray icon
-                            *is_active_monitor.lock() = false;
-                            let _ = cmd_tx_timeout.send(SttCommand::Cancel);
-                        }
+                } else {
+                    // Batch mode: maximum recording time to prevent memory overflow
+                    const MAX_RECORDING_TIME_SECS: u64 = 600; // 10 minutes
+                    if elapsed >= Duration::from_secs(MAX_RECORDING_TIME_SECS) {
+                        info!("Maximum recording time reached ({} minutes), auto-toggling off to prevent memory overflow", MAX_RECORDING_TIME_SECS / 60);
+                        // Update is_active state first to prevent repeated logs and update tray icon
+                        *is_active_monitor.lock() = false;
+                        let _ = cmd_tx_timeout.send(SttCommand::Cancel);
                     }
                 }
             }
@@ -551,22 +1175,37 @@ where
     });
     
     // Clone necessary values for the STT thread
-    let stt_url_owned = stt_url.map(|s| s.clone());
-    let language_owned = language.to_string();
-    let stt_model_owned = stt_model.to_string();
     let last_activity_clone = last_activity.clone();
     let last_activity_reset = last_activity.clone();
-    
+    let connection_status_stt = connection_status.clone();
+    let typing_error_stt = typing_error.clone();
+    let is_active_status = is_active.clone();
+    let cmd_tx_status = cmd_tx.clone();
+
     // Wrap the transcription callback to update last activity time
-    let wrapped_on_transcription = move |result: stt_client::TranscriptionResult| {
+    let on_event: EventSink = Arc::new(move |event: SttEvent| {
         // Update last activity time whenever we receive a non-empty transcript
-        if !result.transcript.is_empty() {
+        if event.result().is_some_and(|result| !result.transcript.is_empty()) {
             *last_activity_clone.lock() = std::time::Instant::now();
         }
         // Call the original callback
-        on_transcription(result);
-    };
-    
+        on_transcription(event);
+    });
+
+    // A connection that drops is restored by the provider; if that gives up, end the session
+    let connection_status_callback = connection_status.clone();
+    let on_status: StatusSink = Arc::new(move |status| {
+        *connection_status_callback.lock() = status;
+        if status == ConnectionStatus::Disconnected {
+            let mut active = is_active_status.lock();
+            if *active {
+                error!("Lost connection to speech-to-text service, stopping");
+                *active = false;
+                let _ = cmd_tx_status.send(SttCommand::Cancel);
+            }
+        }
+    });
+
     // Spawn dedicated STT management thread
     thread::spawn(move || {
         // Create a new tokio runtime for this thread
@@ -578,7 +1217,29 @@ where
         // Create audio control instance to manage system audio pause/resume
         let mut audio_control = AudioControl::new();
         
-        for command in cmd_rx {
+        let mut commands = cmd_rx.iter();
+        let mut shutdown_done: Option<mpsc::Sender<()>> = None;
+        loop {
+            // A shutdown ends the session like a stop or cancel, then the thread
+            if let Some(done) = shutdown_done.take() {
+                info!("STT thread stopped");
+                let _ = done.send(());
+                break;
+            }
+            let Some(command) = commands.next() else {
+                break;
+            };
+            let command = match command {
+                SttCommand::Shutdown(mode, done) => {
+                    shutdown_done = Some(done);
+                    match mode {
+                        ShutdownMode::Flush => SttCommand::Stop,
+                        ShutdownMode::Cancel => SttCommand::Cancel,
+                    }
+                }
+                command => command,
+            };
+
             match command {
                 SttCommand::Start => {
                     // Pause system audio if playing
@@ -586,18 +1247,16 @@ where
                         error!("Failed to control system audio: {}", e);
                     }
                     
-                    // If there's an existing session, close it first
+                    // If there's an existing session, finish it first
                     if let Some(session) = active_session.take() {
                         info!("Closing existing STT session...");
-                        if let Some(tx) = session.audio_tx {
-                            drop(tx); // This will trigger WebSocket cleanup
-                        }
-                        drop(session._audio_input); // Stop audio recording
-                        // Don't wait for handle to finish, just move on
+                        session.finish(stop_timeout);
                     }
                     
                     // Reset inactivity timer when starting a new session
                     *last_activity_reset.lock() = std::time::Instant::now();
+                    // A typing error is shown until listening starts again
+                    *typing_error_stt.lock() = None;
                     
                     // Create audio input on this thread
                     let mut audio_input = match AudioInput::new() {
@@ -607,114 +1266,39 @@ where
                             continue;
                         }
                     };
-                    
-                    match stt_provider {
-                        SttProvider::WebSocket => {
-                            // WebSocket mode: stream audio chunks continuously
-                            info!("Creating new WebSocket STT connection...");
-                            let url = stt_url_owned.as_ref().map(|s| s.as_str()).unwrap_or(stt_client::STT_URL);
-                            let stt_client = SttClient::with_eot_thresholds(url, sample_rate, eager_eot_threshold, eot_threshold);
-                            let on_transcription_clone = wrapped_on_transcription.clone();
-                            
-                            match rt.block_on(stt_client.connect_and_transcribe(on_transcription_clone)) {
-                                Ok((audio_tx, handle)) => {
-                                    info!("STT connection established");
-                                    
-                                    // Start recording
-                                    info!("Starting audio recording...");
-                                    let audio_tx_clone = audio_tx.clone();
-                                    let audio_buffer = Arc::new(Mutex::new(AudioBuffer::new(sample_rate, 160)));
-                                    
-                                    if let Err(e) = audio_input.start_recording(move |data| {
-                                        debug!("Received audio data: {} samples", data.len());
-
-                                        // Average stereo channels to mono
-                                        let mono_data: Vec<f32> = if channels == 2 {
-                                            let mut mono = Vec::with_capacity(data.len() / 2);
-                                            for chunk in data.chunks_exact(2) {
-                                                mono.push((chunk[0] + chunk[1]) / 2.0);
-                                            }
-                                            debug!("Averaged samples: {}", mono.len());
-                                            mono
-                                        } else {
-                                            data.to_vec()
-                                        };
-
-                                        // Create audio chunks and send them
-                                        let mut buffer = audio_buffer.lock();
-                                        let chunks = buffer.add_samples(&mono_data);
-                                        for chunk in chunks {
-                                            debug!("Sending audio chunk: {} bytes", chunk.len());
-                                            if let Err(e) = audio_tx_clone.blocking_send(chunk) {
-                                                error!("Failed to send audio chunk: {}", e);
-                                            }
-                                        }
-                                    }) {
-                                        error!("Failed to start recording: {}", e);
-                                        continue;
-                                    }
-                                    
-                                    // Store the complete session (connection + audio input)
-                                    active_session = Some(ActiveSttSession {
-                                        audio_tx: Some(audio_tx),
-                                        _handle: Some(handle),
-                                        _audio_input: audio_input,
-                                        audio_buffer: None,
-                                    });
-                                }
-                                Err(e) => {
-                                    error!("Failed to create STT connection: {}", e);
-                                }
-                            }
+
+                    let recognizer_session = match recognizer.start_session(rt.handle(), on_event.clone(), on_status.clone()) {
+                        Ok(session) => Arc::new(Mutex::new(Some(session))),
+                        Err(e) => {
+                            error!("Failed to start STT session: {}", e);
+                            continue;
+                        }
+                    };
+
+                    // Start recording
+                    info!("Starting audio recording...");
+                    let session_audio = recognizer_session.clone();
+                    if let Err(e) = audio_input.start_recording(move |data| {
+                        debug!("Received audio data: {} samples", data.len());
+
+                        // Average the channels to mono
+                        let mono_data = audio_input::downmix(data, channels);
+                        if let Some(session) = session_audio.lock().as_mut() {
+                            session.push_audio(&mono_data);
                         }
-                        SttProvider::Rest => {
-                            // REST mode: buffer all audio data
-                            info!("Starting REST mode audio recording...");
-                            let buffer = Arc::new(Mutex::new(Vec::new()));
-                            let buffer_clone = buffer.clone();
-                            
-                            if let Err(e) = audio_input.start_recording(move |data| {
-                                debug!("Received audio data: {} samples", data.len());
-
-                                // Average stereo channels to mono
-                                let mono_data: Vec<f32> = if channels == 2 {
-                                    let mut mono = Vec::with_capacity(data.len() / 2);
-                                    for chunk in data.chunks_exact(2) {
-                                        mono.push((chunk[0] + chunk[1]) / 2.0);
-                                    }
-                                    debug!("Averaged samples: {}", mono.len());
-                                    mono
-                                } else {
-                                    data.to_vec()
-                                };
-
-                                // Convert to PCM 16-bit and buffer
-                                let pcm_data: Vec<u8> = mono_data
-                                    .iter()
-                                    .flat_map(|&sample| {
-                                        let pcm_sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
-                                        pcm_sample.to_le_bytes()
-                                    })
-                                    .collect();
-
-                                // Append to buffer
-                                buffer_clone.lock().extend_from_slice(&pcm_data);
-                            }) {
-                                error!("Failed to start recording: {}", e);
-                                continue;
-                            }
-                            
-                            info!("Audio recording started (REST mode - buffering)");
-                            
-                            // Store the session with buffer
-                            active_session = Some(ActiveSttSession {
-                                audio_tx: None,
-                                _handle: None,
-                                _audio_input: audio_input,
-                                audio_buffer: Some(buffer),
-                            });
+                    }) {
+                        error!("Failed to start recording: {}", e);
+                        if let Some(session) = recognizer_session.lock().take() {
+                            session.cancel();
                         }
+                        continue;
                     }
+
+                    // Store the complete session (recognizer session + audio input)
+                    active_session = Some(ActiveSttSession {
+                        recognizer_session,
+                        audio_input,
+                    });
                 }
                 SttCommand::Stop => {
                     // Resume system audio if we paused it
@@ -722,76 +1306,11 @@ where
                         error!("Failed to control system audio: {}", e);
                     }
                     
-                    // Close session: this will drop the WebSocket connection and stop audio recording
+                    // Stop recording and wait for the final results
+                    *connection_status_stt.lock() = ConnectionStatus::Disconnected;
                     if let Some(session) = active_session.take() {
                         info!("Stopping STT session...");
-                        
-                        match stt_provider {
-                            SttProvider::WebSocket => {
-                                // WebSocket mode: just drop the session to clean up
-                                drop(session);
-                            }
-                            SttProvider::Rest => {
-                                // REST mode: send buffered audio to Whisper API
-                                if let Some(buffer) = session.audio_buffer {
-                                    // Stop recording first
-                                    drop(session._audio_input);
-                                    
-                                    let audio_data = buffer.lock().clone();
-                                    info!("Sending {} bytes of audio to Whisper API...", audio_data.len());
-                                    
-                                    if audio_data.is_empty() {
-                                        info!("No audio data recorded, skipping transcription");
-                                        continue;
-                                    }
-                                    
-                                    // Create Whisper client and send audio
-                                    let url = stt_url_owned.as_ref().map(|s| s.as_str());
-                                    let whisper_client = WhisperClient::new(url, &language_owned, &stt_model_owned);
-                                    let on_transcription_clone = wrapped_on_transcription.clone();
-                                    
-                                    match rt.block_on(whisper_client.transcribe(&audio_data, sample_rate)) {
-                                        Ok(text) => {
-                                            info!("Received transcription: {}", text);
-                                            
-                                            // Only send transcription events if the text is not empty
-                                            if !text.is_empty() {
-                                                // First, send an Update event with the transcript
-                                                let update_result = stt_client::TranscriptionResult {
-                                                    event: "Update".to_string(),
-                                                    turn_index: 0,
-                                                    start: 0.0,
-                                                    timestamp: 0.0,
-                                                    transcript: text.clone(),
-                                                    words: Vec::new(),
-                                                    end_of_turn_confidence: 1.0,
-                                                };
-                                                on_transcription_clone(update_result);
-                                                
-                                                // Then, send an EndOfTurn event to finalize
-                                                let eot_result = stt_client::TranscriptionResult {
-                                                    event: "EndOfTurn".to_string(),
-                                                    turn_index: 0,
-                                                    start: 0.0,
-                                                    timestamp: 0.0,
-                                                    transcript: String::new(),
-                                                    words: Vec::new(),
-                                                    end_of_turn_confidence: 1.0,
-                                                };
-                                                on_transcription_clone(eot_result);
-                                            } else {
-                                                info!("Transcription is empty, skipping keyboard input");
-                                            }
-                                        }
-                                        Err(e) => {
-                                            error!("Failed to transcribe audio: {}", e);
-                                        }
-                                    }
-                                } else {
-                                    drop(session);
-                                }
-                            }
-                        }
+                        session.finish(stop_timeout);
                     }
                 }
                 SttCommand::Cancel => {
@@ -801,19 +1320,24 @@ where
                     }
                     
                     // Cancel recording: drop the session without transcription
+                    *connection_status_stt.lock() = ConnectionStatus::Disconnected;
                     if let Some(session) = active_session.take() {
                         info!("Cancelling STT session without transcription...");
-                        // Just drop everything - no transcription will occur
-                        drop(session);
+                        session.cancel();
                     }
+                    // The session reports nothing more, so drop the turns it left open
+                    on_cancel();
                 }
+                SttCommand::Shutdown(..) => unreachable!("shutdown is turned into a stop or cancel above"),
             }
         }
     });
 
     // Event loop on main thread for tray events
     let mut last_state = false;
-    loop {
+    let mut last_status = ConnectionStatus::Disconnected;
+    let mut last_typing_error = None;
+    while !shutdown::requested() {
         // Process GTK events (required for tray icon to work)
         while gtk::events_pending() {
             gtk::main_iteration_do(false);
@@ -841,6 +1365,32 @@ where
             last_state = current_state;
         }
 
+        // Show reconnects of the streaming connection
+        let current_status = *connection_status.lock();
+        if current_status != last_status {
+            if let Err(e) = tray_manager.set_connection_status(current_status) {
+                error!("Failed to update tray icon: {}", e);
+            }
+            last_status = current_status;
+        }
+
+        // Show typing errors from the output thread
+        let current_typing_error = typing_error.lock().clone();
+        if current_typing_error != last_typing_error {
+            if let Err(e) = tray_manager.set_typing_error(current_typing_error.clone()) {
+                error!("Failed to update tray icon: {}", e);
+            }
+            last_typing_error = current_typing_error;
+        }
+
         thread::sleep(Duration::from_millis(100));
     }
+
+    info!("Shutting down (press Ctrl+C again to quit immediately)...");
+    *is_active.lock() = false;
+    let (done_tx, done_rx) = mpsc::channel();
+    let _ = cmd_tx.send(SttCommand::Shutdown(on_shutdown, done_tx));
+    // Waits until the session has been transcribed or dropped and media has resumed
+    let _ = done_rx.recv();
+    Ok(())
 }
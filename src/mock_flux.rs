@@ -0,0 +1,359 @@
+//! A local stand-in for the Deepgram Flux WebSocket API
+//!
+//! Each connection plays a script: server messages to send, pauses between them, and
+//! points where it waits for the client's audio or `CloseStream`. What the client sends
+//! is recorded per connection, so tests can check it. Used by the tests, and by the
+//! `mock-flux-server` binary to try the app without network access.
+
+use anyhow::{Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, warn};
+
+// How long a step waits for the client before the connection is dropped as failed
+const STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One step of the script a connection plays
+///
+/// In a script file each step is written as serde's externally tagged form, e.g.
+/// `{"send": {"type": "Connected"}}`, `{"sleep_ms": 200}`, `{"wait_for_audio": 6400}`,
+/// `"wait_for_close_stream"` or `"drop"`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    /// Send a server message; `request_id` and `sequence_id` are filled in if missing
+    Send(Value),
+    /// Pause before the next step
+    SleepMs(u64),
+    /// Wait until the connection has received this many bytes of audio in total
+    WaitForAudio(usize),
+    /// Wait until the client sends `CloseStream`
+    WaitForCloseStream,
+    /// End the connection without a close frame, as a network failure would
+    Drop,
+}
+
+impl Step {
+    pub fn connected() -> Self {
+        Step::Send(json!({ "type": "Connected" }))
+    }
+
+    /// A `TurnInfo` message of `event` (e.g. "Update", "EagerEndOfTurn") for turn
+    /// `turn_index`, whose words all have confidence 0.9
+    pub fn turn_info(event: &str, turn_index: u32, transcript: &str) -> Self {
+        let words: Vec<Value> = transcript
+            .split_whitespace()
+            .map(|word| json!({ "word": word, "confidence": 0.9 }))
+            .collect();
+        Step::Send(json!({
+            "type": "TurnInfo",
+            "event": event,
+            "turn_index": turn_index,
+            "audio_window_start": 0.0,
+            "audio_window_end": 0.0,
+            "transcript": transcript,
+            "words": words,
+            "end_of_turn_confidence": if event == "EndOfTurn" { 0.9 } else { 0.1 },
+        }))
+    }
+
+    pub fn error(code: &str, description: &str) -> Self {
+        Step::Send(json!({ "type": "Error", "code": code, "description": description }))
+    }
+
+    pub fn sleep(duration: Duration) -> Self {
+        Step::SleepMs(duration.as_millis() as u64)
+    }
+}
+
+/// What the client sent over one connection
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionLog {
+    /// Request path and query, e.g. "/v2/listen?model=flux-general-en&..."
+    pub path: String,
+    /// All binary messages, concatenated
+    pub audio: Vec<u8>,
+    pub close_stream: bool,
+    /// Text messages other than `CloseStream`
+    pub messages: Vec<String>,
+    /// Protocol violations, and steps that timed out waiting for the client
+    pub failures: Vec<String>,
+}
+
+// How far the client has got, for the steps that wait on it
+#[derive(Debug, Clone, Copy, Default)]
+struct Progress {
+    audio: usize,
+    close_stream: bool,
+    disconnected: bool,
+}
+
+/// Serves scripted connections on a local port until dropped
+pub struct MockFluxServer {
+    addr: SocketAddr,
+    connections: Arc<Mutex<Vec<ConnectionLog>>>,
+    task: JoinHandle<()>,
+}
+
+impl MockFluxServer {
+    /// Listen on a free local port and play one script per connection, in order;
+    /// connections beyond the last script are refused
+    pub async fn start(scripts: Vec<Vec<Step>>) -> Result<Self> {
+        Self::bind("127.0.0.1:0", scripts, false).await
+    }
+
+    /// Listen on `addr`; with `repeat` the scripts start over once all have been played
+    pub async fn bind(addr: &str, scripts: Vec<Vec<Step>>, repeat: bool) -> Result<Self> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to listen on {}", addr))?;
+        let addr = listener.local_addr()?;
+        let connections = Arc::new(Mutex::new(Vec::new()));
+        let task = tokio::spawn(serve(listener, scripts, repeat, connections.clone()));
+        debug!("Mock Flux server listening on {}", addr);
+        Ok(Self { addr, connections, task })
+    }
+
+    /// URL to give the client, e.g. "ws://127.0.0.1:40123/v2/listen"
+    pub fn url(&self) -> String {
+        format!("ws://{}/v2/listen", self.addr)
+    }
+
+    /// What each connection so far received, in the order they were made
+    pub fn connections(&self) -> Vec<ConnectionLog> {
+        self.connections.lock().clone()
+    }
+}
+
+impl Drop for MockFluxServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(
+    listener: TcpListener,
+    scripts: Vec<Vec<Step>>,
+    repeat: bool,
+    connections: Arc<Mutex<Vec<ConnectionLog>>>,
+) {
+    let mut next = 0;
+    loop {
+        let (tcp, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Mock Flux server failed to accept a connection: {}", e);
+                continue;
+            }
+        };
+        if next == scripts.len() {
+            if !repeat || scripts.is_empty() {
+                debug!("No script left for the connection from {}, refusing it", peer);
+                continue;
+            }
+            next = 0;
+        }
+        let script = scripts[next].clone();
+        next += 1;
+
+        let index = {
+            let mut connections = connections.lock();
+            connections.push(ConnectionLog::default());
+            connections.len() - 1
+        };
+        info!("Connection {} from {}", index, peer);
+        let connections = connections.clone();
+        tokio::spawn(async move {
+            if let Err(e) = play(tcp, script, index, &connections).await {
+                warn!("Connection {} failed: {:#}", index, e);
+                connections.lock()[index].failures.push(format!("{:#}", e));
+            }
+            let connections = connections.lock();
+            let log = &connections[index];
+            info!(
+                "Connection {} ended: {} bytes of audio, CloseStream {}",
+                index,
+                log.audio.len(),
+                if log.close_stream { "received" } else { "not received" }
+            );
+        });
+    }
+}
+
+// Accept the WebSocket handshake and play the script, recording what the client sends
+async fn play(
+    tcp: TcpStream,
+    script: Vec<Step>,
+    index: usize,
+    connections: &Arc<Mutex<Vec<ConnectionLog>>>,
+) -> Result<()> {
+    let mut path = String::new();
+    // The callback's signature is tungstenite's
+    #[allow(clippy::result_large_err)]
+    let ws_stream = tokio_tungstenite::accept_hdr_async(tcp, |request: &Request, response: Response| {
+        path = request.uri().to_string();
+        Ok(response)
+    })
+    .await
+    .context("WebSocket handshake failed")?;
+    connections.lock()[index].path = path;
+    let (mut sink, mut stream) = ws_stream.split();
+
+    // Record what arrives on a task of its own, so the script can wait on it
+    let (progress_tx, mut progress_rx) = watch::channel(Progress::default());
+    let log = connections.clone();
+    let reader = tokio::spawn(async move {
+        while let Some(Ok(message)) = stream.next().await {
+            let mut log = log.lock();
+            let log = &mut log[index];
+            match message {
+                Message::Binary(data) => {
+                    if log.close_stream {
+                        log.failures.push("audio received after CloseStream".to_string());
+                    }
+                    log.audio.extend_from_slice(&data);
+                    progress_tx.send_modify(|progress| progress.audio += data.len());
+                }
+                Message::Text(text) => {
+                    let is_close_stream = serde_json::from_str::<Value>(&text)
+                        .is_ok_and(|value| value["type"] == "CloseStream");
+                    if is_close_stream {
+                        if log.close_stream {
+                            log.failures.push("CloseStream received twice".to_string());
+                        }
+                        log.close_stream = true;
+                        progress_tx.send_modify(|progress| progress.close_stream = true);
+                    } else {
+                        log.messages.push(text);
+                    }
+                }
+                Message::Close(_) => break,
+                _ => {}
+            }
+        }
+        progress_tx.send_modify(|progress| progress.disconnected = true);
+    });
+
+    let request_id = format!("mock-{}", index);
+    let mut sequence_id = 0;
+    for step in script {
+        match step {
+            Step::Send(mut message) => {
+                if let Some(fields) = message.as_object_mut() {
+                    fields.entry("request_id").or_insert_with(|| json!(request_id));
+                    fields.entry("sequence_id").or_insert_with(|| json!(sequence_id));
+                }
+                sequence_id += 1;
+                debug!("Connection {} sends {}", index, message);
+                sink.send(Message::Text(message.to_string())).await?;
+            }
+            Step::SleepMs(ms) => tokio::time::sleep(Duration::from_millis(ms)).await,
+            Step::WaitForAudio(bytes) => {
+                wait_for(&mut progress_rx, &format!("{} bytes of audio", bytes), |progress| {
+                    progress.audio >= bytes
+                })
+                .await?;
+            }
+            Step::WaitForCloseStream => {
+                wait_for(&mut progress_rx, "CloseStream", |progress| progress.close_stream).await?;
+            }
+            Step::Drop => {
+                debug!("Connection {} dropped by its script", index);
+                reader.abort();
+                return Ok(());
+            }
+        }
+    }
+
+    // Like Flux, close the connection once everything is sent
+    sink.send(Message::Close(None)).await?;
+    let _ = tokio::time::timeout(STEP_TIMEOUT, reader).await;
+    Ok(())
+}
+
+async fn wait_for(
+    progress_rx: &mut watch::Receiver<Progress>,
+    what: &str,
+    done: impl Fn(&Progress) -> bool,
+) -> Result<()> {
+    let waited = tokio::time::timeout(
+        STEP_TIMEOUT,
+        progress_rx.wait_for(|progress| done(progress) || progress.disconnected),
+    )
+    .await;
+    match waited {
+        Ok(Ok(progress)) if done(&progress) => Ok(()),
+        Ok(_) => Err(anyhow::anyhow!("client disconnected while waiting for {}", what)),
+        Err(_) => Err(anyhow::anyhow!("timed out waiting for {}", what)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_tungstenite::connect_async;
+
+    #[tokio::test]
+    async fn test_script_and_log() {
+        let server = MockFluxServer::start(vec![vec![
+            Step::connected(),
+            Step::WaitForAudio(4),
+            Step::turn_info("Update", 0, "hi"),
+            Step::WaitForCloseStream,
+        ]])
+        .await
+        .unwrap();
+
+        let (mut ws, _) = connect_async(format!("{}?sample_rate=16000", server.url())).await.unwrap();
+        let connected: Value = match ws.next().await.unwrap().unwrap() {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!((connected["request_id"].as_str(), connected["sequence_id"].as_u64()), (Some("mock-0"), Some(0)));
+
+        ws.send(Message::Binary(vec![1, 2])).await.unwrap();
+        ws.send(Message::Binary(vec![3, 4])).await.unwrap();
+        let update: Value = match ws.next().await.unwrap().unwrap() {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!((update["transcript"].as_str(), update["sequence_id"].as_u64()), (Some("hi"), Some(1)));
+
+        ws.send(Message::Text(r#"{"type":"CloseStream"}"#.to_string())).await.unwrap();
+        assert!(matches!(ws.next().await, Some(Ok(Message::Close(_)))));
+        drop(ws);
+
+        // The log is complete once the connection task is done with it
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let connections = server.connections();
+        assert_eq!(connections.len(), 1);
+        let log = &connections[0];
+        assert_eq!(log.path, "/v2/listen?sample_rate=16000");
+        assert_eq!(log.audio, [1, 2, 3, 4]);
+        assert!(log.close_stream);
+        assert!(log.failures.is_empty(), "{:?}", log.failures);
+    }
+
+    #[test]
+    fn test_script_file_format() {
+        let script: Vec<Step> = serde_json::from_str(
+            r#"[{"send": {"type": "Connected"}}, {"sleep_ms": 200}, {"wait_for_audio": 6400}, "wait_for_close_stream", "drop"]"#,
+        )
+        .unwrap();
+        assert!(matches!(&script[0], Step::Send(message) if message["type"] == "Connected"));
+        assert!(matches!(script[1], Step::SleepMs(200)));
+        assert!(matches!(script[2], Step::WaitForAudio(6400)));
+        assert!(matches!(script[3], Step::WaitForCloseStream));
+        assert!(matches!(script[4], Step::Drop));
+    }
+}
@@ -0,0 +1,413 @@
+use anyhow::{Context, Error, Result};
+use std::collections::VecDeque;
+use std::sync::mpsc;
+use std::thread;
+use tracing::{debug, warn};
+
+use crate::stt_client::{SttEvent, TranscriptionResult};
+use crate::virtual_keyboard::{KeyboardHardware, TurnEvent, VirtualKeyboard};
+
+/// Keyboard work queued by the transcription callback, in transcript order
+#[derive(Debug)]
+pub enum OutputEvent {
+    /// An event of the provider's turn with this index
+    Turn(u32, TurnEvent),
+    /// A new connection, which numbers its turns from 0 again
+    SessionStarted,
+    /// Listening stopped and the provider sent all it will; type what is still pending
+    SessionEnded,
+    /// The recording was cancelled; drop what is still pending
+    SessionCancelled,
+    /// Backspace the last finalized turn; the reply says whether anything was undone
+    UndoLastTurn(mpsc::Sender<bool>),
+    /// Type what the session left open if `flush` is set, then destroy the keyboard and
+    /// stop; the reply comes once that is done
+    Shutdown { flush: bool, done: mpsc::Sender<()> },
+}
+
+/// Types transcripts on a thread of its own, so slow typing never holds up the STT
+/// connection
+///
+/// Each update carries the whole turn so far, so updates that queue up while typing is
+/// behind are coalesced into the latest one. Keyboard errors are passed to the error
+/// callback and the worker carries on with the next event.
+#[derive(Clone)]
+pub struct OutputWorker {
+    tx: mpsc::Sender<OutputEvent>,
+}
+
+impl OutputWorker {
+    pub fn spawn<H, E>(keyboard: VirtualKeyboard<H>, on_error: E) -> Self
+    where
+        H: KeyboardHardware + Send + 'static,
+        E: Fn(Error) + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || run(keyboard, rx, on_error));
+        Self { tx }
+    }
+
+    /// Queue an event; returns immediately
+    pub fn send(&self, event: OutputEvent) {
+        if self.tx.send(event).is_err() {
+            warn!("Output worker has stopped, dropping keyboard event");
+        }
+    }
+
+    /// Queue what an STT event means for the keyboard; `update` makes the typed update
+    /// from a result, e.g. after post-processing its text
+    pub fn send_stt_event(&self, event: &SttEvent, update: impl Fn(&TranscriptionResult) -> TurnEvent) {
+        let update = |result: &TranscriptionResult| OutputEvent::Turn(result.turn_index, update(result));
+
+        // Flux sends the turn's transcript along with these events, which may correct the
+        // last update; other providers leave it empty
+        match event {
+            SttEvent::Connected { .. } => self.send(OutputEvent::SessionStarted),
+            SttEvent::Closed => self.send(OutputEvent::SessionEnded),
+            // Already logged by the provider; the session carries on or closes
+            SttEvent::Error { .. } => {}
+            SttEvent::EndOfTurn(result) | SttEvent::EagerEndOfTurn(result) => {
+                if !result.transcript.is_empty() {
+                    self.send(update(result));
+                }
+                self.send(OutputEvent::Turn(result.turn_index, if matches!(event, SttEvent::EndOfTurn(_)) {
+                    TurnEvent::EndOfTurn
+                } else {
+                    TurnEvent::EagerEndOfTurn
+                }));
+            }
+            SttEvent::TurnResumed(result) => {
+                self.send(OutputEvent::Turn(result.turn_index, TurnEvent::TurnResumed));
+                if !result.transcript.is_empty() {
+                    self.send(update(result));
+                }
+            }
+            SttEvent::StartOfTurn(result) | SttEvent::Update(result) => self.send(update(result)),
+        }
+    }
+
+    /// Undo the last turn once the events queued before it are typed
+    pub fn undo_last_turn(&self) -> bool {
+        let (reply_tx, reply_rx) = mpsc::channel();
+        self.send(OutputEvent::UndoLastTurn(reply_tx));
+        reply_rx.recv().unwrap_or(false)
+    }
+
+    /// Type or drop what is left of the session, then stop the worker and wait for it
+    pub fn shutdown(&self, flush: bool) {
+        let (done_tx, done_rx) = mpsc::channel();
+        self.send(OutputEvent::Shutdown { flush, done: done_tx });
+        let _ = done_rx.recv();
+    }
+}
+
+fn run<H: KeyboardHardware>(mut keyboard: VirtualKeyboard<H>, rx: mpsc::Receiver<OutputEvent>, on_error: impl Fn(Error)) {
+    let mut pending = VecDeque::new();
+    loop {
+        if pending.is_empty() {
+            // Wake up when a turn that later turns wait for times out, even if no more
+            // events come
+            let event = match keyboard.turn_timeout() {
+                Some(timeout) => rx.recv_timeout(timeout),
+                None => rx.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+            };
+            match event {
+                Ok(event) => pending.push_back(event),
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    if let Err(e) = keyboard.expire_turns().context("Failed to finalize a timed out turn") {
+                        on_error(e);
+                    }
+                    continue;
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        pending.extend(rx.try_iter());
+
+        let Some(event) = next_event(&mut pending) else {
+            continue;
+        };
+        if let OutputEvent::Shutdown { flush, done } = event {
+            if flush {
+                if let Err(e) = keyboard.end_session().context("Failed to finalize the session") {
+                    on_error(e);
+                }
+            }
+            // Dropping the keyboard releases held keys and destroys the device
+            drop(keyboard);
+            let _ = done.send(());
+            debug!("Output worker shut down");
+            return;
+        }
+        if let Err(e) = handle(&mut keyboard, event) {
+            on_error(e);
+        }
+    }
+    debug!("Output worker stopped");
+}
+
+// The next event to handle, skipping updates that a newer update of the same turn replaces
+fn next_event(pending: &mut VecDeque<OutputEvent>) -> Option<OutputEvent> {
+    let mut event = pending.pop_front()?;
+    let mut skipped = 0;
+    while let (OutputEvent::Turn(turn, TurnEvent::Update { .. }), Some(OutputEvent::Turn(next, TurnEvent::Update { .. }))) =
+        (&event, pending.front())
+    {
+        if turn != next {
+            break;
+        }
+        event = pending.pop_front()?;
+        skipped += 1;
+    }
+    if skipped > 0 {
+        debug!("Typing is behind, skipped {} intermediate updates", skipped);
+    }
+    Some(event)
+}
+
+fn handle<H: KeyboardHardware>(keyboard: &mut VirtualKeyboard<H>, event: OutputEvent) -> Result<()> {
+    match event {
+        OutputEvent::Turn(turn_index, event) => keyboard.handle_turn_event(turn_index, event),
+        OutputEvent::SessionStarted => keyboard.start_session().context("Failed to finalize the last session"),
+        OutputEvent::SessionEnded => keyboard.end_session().context("Failed to finalize the session"),
+        OutputEvent::SessionCancelled => {
+            keyboard.discard_session();
+            Ok(())
+        }
+        OutputEvent::UndoLastTurn(reply) => {
+            let result = keyboard.undo_last_turn().context("Failed to undo last turn");
+            let _ = reply.send(*result.as_ref().unwrap_or(&false));
+            result.map(|_| ())
+        }
+        OutputEvent::Shutdown { .. } => unreachable!("shutdown is handled by the worker loop"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_flux::{MockFluxServer, Step};
+    use crate::speech_recognizer::{EventSink, SpeechRecognizer};
+    use crate::stt_client::SttClient;
+    use anyhow::bail;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn update(turn: u32, transcript: &str) -> OutputEvent {
+        OutputEvent::Turn(
+            turn,
+            TurnEvent::Update {
+                transcript: transcript.to_string(),
+                confident: String::new(),
+            },
+        )
+    }
+
+    fn end(turn: u32) -> OutputEvent {
+        OutputEvent::Turn(turn, TurnEvent::EndOfTurn)
+    }
+
+    // Records typed text where the test can see it, and fails on '!'
+    struct SharedHardware {
+        typed: Arc<Mutex<String>>,
+    }
+
+    impl KeyboardHardware for SharedHardware {
+        fn type_text(&mut self, text: &str) -> Result<()> {
+            if text.contains('!') {
+                bail!("cannot type '!'");
+            }
+            self.typed.lock().push_str(text);
+            Ok(())
+        }
+
+        fn press_backspace(&mut self) -> Result<()> {
+            self.typed.lock().pop();
+            Ok(())
+        }
+
+        fn press_enter(&mut self) -> Result<()> {
+            self.typed.lock().push('\n');
+            Ok(())
+        }
+
+        fn press_key(&mut self, _keycode: u16) -> Result<()> {
+            Ok(())
+        }
+
+        fn press_chord(&mut self, _modifiers: &[u16], _keycode: u16) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_updates_are_coalesced() {
+        let mut pending: VecDeque<OutputEvent> = VecDeque::from([
+            update(0, "a"),
+            update(0, "a b"),
+            end(0),
+            update(1, "c"),
+            OutputEvent::Turn(1, TurnEvent::TurnResumed),
+            update(1, "c d"),
+            update(1, "c d e"),
+            update(2, "f"),
+        ]);
+
+        let mut handled = Vec::new();
+        while let Some(event) = next_event(&mut pending) {
+            handled.push(format!("{:?}", event));
+        }
+        assert_eq!(
+            handled,
+            [
+                "Turn(0, Update { transcript: \"a b\", confident: \"\" })",
+                "Turn(0, EndOfTurn)",
+                "Turn(1, Update { transcript: \"c\", confident: \"\" })",
+                "Turn(1, TurnResumed)",
+                "Turn(1, Update { transcript: \"c d e\", confident: \"\" })",
+                "Turn(2, Update { transcript: \"f\", confident: \"\" })"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_worker_types_and_reports_errors() {
+        let typed = Arc::new(Mutex::new(String::new()));
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let keyboard = VirtualKeyboard::new(SharedHardware { typed: typed.clone() }, false);
+        let errors_clone = errors.clone();
+        let worker = OutputWorker::spawn(keyboard, move |e| errors_clone.lock().push(format!("{:#}", e)));
+
+        worker.send(update(0, "hello"));
+        worker.send(update(0, "hello world"));
+        worker.send(end(0));
+
+        // An error is reported and the worker keeps going
+        worker.send(update(1, "oops!"));
+        worker.send(end(1));
+        worker.send(update(2, "again"));
+        worker.send(end(2));
+
+        // Undo waits for the queue, so everything above has been handled
+        assert!(worker.undo_last_turn());
+        assert_eq!(*typed.lock(), "hello world ");
+        assert_eq!(*errors.lock(), ["Failed to update transcript: cannot type '!'"]);
+    }
+
+    #[test]
+    fn test_session_ended_types_pending_text() {
+        let typed = Arc::new(Mutex::new(String::new()));
+        let keyboard = VirtualKeyboard::new(SharedHardware { typed: typed.clone() }, true);
+        let worker = OutputWorker::spawn(keyboard, |e| panic!("{:#}", e));
+
+        // Delay mode types a turn at its end, which the provider never sent
+        worker.send(update(3, "last words"));
+        worker.send(OutputEvent::SessionEnded);
+
+        // The next session counts from 0 again
+        worker.send(update(0, "next"));
+        worker.send(end(0));
+        worker.shutdown(false);
+        assert_eq!(*typed.lock(), "last words next ");
+    }
+
+    #[test]
+    fn test_session_cancelled_drops_pending_text() {
+        let typed = Arc::new(Mutex::new(String::new()));
+        let keyboard = VirtualKeyboard::new(SharedHardware { typed: typed.clone() }, true);
+        let worker = OutputWorker::spawn(keyboard, |e| panic!("{:#}", e));
+
+        worker.send(update(0, "my secret password"));
+        worker.send(update(1, "held back"));
+        worker.send(OutputEvent::SessionCancelled);
+
+        // Neither the next session nor a flushing shutdown types the cancelled turns
+        worker.send(OutputEvent::SessionStarted);
+        worker.send(update(0, "hello"));
+        worker.send(end(0));
+        worker.send(update(1, "open"));
+        worker.send(OutputEvent::SessionCancelled);
+        worker.shutdown(true);
+        assert_eq!(*typed.lock(), "hello ");
+    }
+
+    #[test]
+    fn test_timed_out_turn_is_typed_without_further_events() {
+        let typed = Arc::new(Mutex::new(String::new()));
+        let mut keyboard = VirtualKeyboard::new(SharedHardware { typed: typed.clone() }, false);
+        keyboard.set_turn_timeout(Duration::from_millis(100));
+        let worker = OutputWorker::spawn(keyboard, |e| panic!("{:#}", e));
+
+        // Turn 0 never ends; turn 1 is complete but held back behind it
+        worker.send(update(0, "cut off"));
+        worker.send(update(1, "next"));
+        worker.send(end(1));
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(*typed.lock(), "cut off");
+
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(*typed.lock(), "cut off next ");
+        worker.shutdown(false);
+    }
+
+    #[test]
+    fn test_shutdown_flushes_or_drops_open_turn() {
+        for (flush, expected) in [(true, "still pending "), (false, "")] {
+            let typed = Arc::new(Mutex::new(String::new()));
+            let keyboard = VirtualKeyboard::new(SharedHardware { typed: typed.clone() }, true);
+            let worker = OutputWorker::spawn(keyboard, |e| panic!("{:#}", e));
+
+            worker.send(update(0, "still pending"));
+            worker.shutdown(flush);
+            assert_eq!(*typed.lock(), expected);
+
+            // The worker is gone; later events are dropped
+            worker.send(end(0));
+            assert!(!worker.undo_last_turn());
+        }
+    }
+
+    #[test]
+    fn test_typing_a_mock_flux_session() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let server = runtime
+            .block_on(MockFluxServer::start(vec![vec![
+                Step::connected(),
+                Step::WaitForAudio(3_200),
+                Step::turn_info("StartOfTurn", 0, "hello"),
+                Step::turn_info("Update", 0, "hello world"),
+                Step::turn_info("EndOfTurn", 0, "hello world"),
+                Step::turn_info("StartOfTurn", 1, "this is"),
+                Step::turn_info("EagerEndOfTurn", 1, "this is it"),
+                Step::turn_info("TurnResumed", 1, "this is it and"),
+                Step::WaitForCloseStream,
+                Step::turn_info("EndOfTurn", 1, "this is it and more"),
+            ]]))
+            .unwrap();
+
+        let typed = Arc::new(Mutex::new(String::new()));
+        let keyboard = VirtualKeyboard::new(SharedHardware { typed: typed.clone() }, false);
+        let worker = OutputWorker::spawn(keyboard, |e| panic!("{:#}", e));
+        let output = worker.clone();
+        let on_event: EventSink = Arc::new(move |event| {
+            output.send_stt_event(&event, |result| TurnEvent::Update {
+                transcript: result.transcript.clone(),
+                confident: String::new(),
+            })
+        });
+
+        // The whole path from audio to keys: 200 ms of audio, then the final transcript
+        // that only comes after CloseStream
+        let mut recognizer = SttClient::new(&server.url(), 16_000);
+        let mut session = recognizer.start_session(runtime.handle(), on_event, Arc::new(|_| {})).unwrap();
+        session.push_audio(&[0.0; 3_200]);
+        session.finish(Duration::from_secs(5));
+        worker.shutdown(false);
+
+        assert_eq!(*typed.lock(), "hello world this is it and more ");
+        let log = &server.connections()[0];
+        assert_eq!(log.audio.len(), 6_400);
+        assert!(log.close_stream);
+    }
+}
@@ -0,0 +1,80 @@
+use anyhow::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::warn;
+
+use crate::virtual_keyboard;
+
+/// What happens to the session in progress when the process is asked to stop
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ShutdownMode {
+    /// Transcribe and type what was said so far
+    #[default]
+    Flush,
+    /// Drop the recording and any text that was not typed yet
+    Cancel,
+}
+
+impl ShutdownMode {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "flush" => Ok(ShutdownMode::Flush),
+            "cancel" => Ok(ShutdownMode::Cancel),
+            other => Err(anyhow::anyhow!(
+                "invalid shutdown mode '{}': must be 'flush' or 'cancel'",
+                other
+            )),
+        }
+    }
+}
+
+static GRACEFUL: AtomicBool = AtomicBool::new(false);
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    // Until something polls for shutdown, and on a second signal, stop right away
+    if !GRACEFUL.load(Ordering::SeqCst) || REQUESTED.swap(true, Ordering::SeqCst) {
+        virtual_keyboard::release_held_keys();
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+}
+
+/// Handle SIGINT and SIGTERM: at first they end the process after releasing held keys;
+/// once `handle_gracefully` is called they only set the flag that `requested` returns
+pub fn install() {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        for signal in [libc::SIGINT, libc::SIGTERM] {
+            if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+                warn!("Failed to install handler for signal {}", signal);
+            }
+        }
+    }
+}
+
+/// From now on a signal asks for a graceful shutdown, which the caller polls for
+pub fn handle_gracefully() {
+    GRACEFUL.store(true, Ordering::SeqCst);
+}
+
+/// Whether SIGINT or SIGTERM asked the process to shut down
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shutdown_mode_from_name() {
+        assert_eq!(ShutdownMode::from_name("flush").unwrap(), ShutdownMode::Flush);
+        assert_eq!(ShutdownMode::from_name("cancel").unwrap(), ShutdownMode::Cancel);
+        assert!(ShutdownMode::from_name("wait").is_err());
+    }
+}
@@ -0,0 +1,177 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tracing::info;
+
+use crate::stt_client::{self, ConnectionStatus, SttClient, SttEvent};
+use crate::whisper_client::WhisperClient;
+
+/// Where a session sends what the provider reports
+pub type EventSink = Arc<dyn Fn(SttEvent) + Send + Sync>;
+/// Where a session reports changes of its connection
+pub type StatusSink = Arc<dyn Fn(ConnectionStatus) + Send + Sync>;
+
+/// A speech-to-text backend, which turns the audio of each session into `SttEvent`s
+pub trait SpeechRecognizer: Send {
+    /// Name for the logs, e.g. "WebSocket (Deepgram)"
+    fn name(&self) -> &'static str;
+
+    /// Whether results arrive while audio is still coming in, rather than after `finish`
+    fn streams(&self) -> bool;
+
+    /// Open a session; async work runs on `runtime`
+    fn start_session(
+        &mut self,
+        runtime: &Handle,
+        on_event: EventSink,
+        on_status: StatusSink,
+    ) -> Result<Box<dyn RecognizerSession>>;
+}
+
+/// One recording, from toggling listening on until it is finished or cancelled
+pub trait RecognizerSession: Send {
+    /// Mono samples at the sample rate the recognizer was made for; runs on the audio
+    /// thread, so it must not block for long
+    fn push_audio(&mut self, samples: &[f32]);
+
+    /// No more audio is coming: wait up to `timeout` for the final results. The last
+    /// event of the session is `SttEvent::Closed`, whether the results came or not
+    fn finish(self: Box<Self>, timeout: Duration);
+
+    /// Drop the session; nothing else is reported for it
+    fn cancel(self: Box<Self>);
+}
+
+/// Which speech-to-text backend to use
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SttProvider {
+    /// Deepgram Flux or a similar streaming WebSocket service
+    #[default]
+    WebSocket,
+    /// OpenAI Whisper or a similar REST service that transcribes whole recordings
+    Rest,
+}
+
+/// Every provider with its `--stt-provider` name and the service it is meant for; the
+/// help text and per-provider config keys come from here
+const PROVIDERS: &[(&str, SttProvider, &str)] = &[
+    ("websocket", SttProvider::WebSocket, "Deepgram"),
+    ("rest", SttProvider::Rest, "OpenAI Whisper"),
+];
+
+/// What the backends are configured with
+#[derive(Debug, Clone, Copy)]
+pub struct RecognizerSettings<'a> {
+    pub url: Option<&'a str>,
+    pub sample_rate: u32,
+    pub eager_eot_threshold: Option<f64>,
+    pub eot_threshold: Option<f64>,
+    pub language: &'a str,
+    pub model: &'a str,
+}
+
+impl SttProvider {
+    pub fn from_name(name: &str) -> Result<Self> {
+        PROVIDERS
+            .iter()
+            .find(|&&(n, ..)| n == name)
+            .map(|&(_, provider, _)| provider)
+            .ok_or_else(|| {
+                let names = PROVIDERS.iter().map(|(n, ..)| format!("'{}'", n));
+                anyhow::anyhow!("invalid STT provider '{}': must be {}", name, join_choices(names))
+            })
+    }
+
+    /// Name used by `--stt-provider` and as key in the config file
+    pub fn name(&self) -> &'static str {
+        PROVIDERS
+            .iter()
+            .find(|&&(_, provider, _)| provider == *self)
+            .map(|&(name, ..)| name)
+            .expect("provider missing from PROVIDERS")
+    }
+
+    /// The providers for `--stt-provider --help`, e.g. "'rest' (OpenAI Whisper)"
+    pub fn help_choices() -> String {
+        join_choices(PROVIDERS.iter().map(|(name, _, service)| format!("'{}' ({})", name, service)))
+    }
+
+    /// Create the backend and log how it is set up
+    pub fn recognizer(&self, settings: &RecognizerSettings) -> Box<dyn SpeechRecognizer> {
+        match self {
+            SttProvider::WebSocket => {
+                if let Some(threshold) = settings.eager_eot_threshold {
+                    info!("Eager end-of-turn threshold: {}", threshold);
+                } else {
+                    info!("Eager end-of-turn: disabled");
+                }
+                if let Some(threshold) = settings.eot_threshold {
+                    info!("Standard end-of-turn threshold: {}", threshold);
+                }
+                Box::new(SttClient::with_eot_thresholds(
+                    settings.url.unwrap_or(stt_client::STT_URL),
+                    settings.sample_rate,
+                    settings.eager_eot_threshold,
+                    settings.eot_threshold,
+                ))
+            }
+            SttProvider::Rest => Box::new(WhisperClient::new(
+                settings.url,
+                settings.language,
+                settings.model,
+                settings.sample_rate,
+            )),
+        }
+    }
+}
+
+// "a", "a or b", "a, b or c"
+fn join_choices(choices: impl Iterator<Item = String>) -> String {
+    let mut choices: Vec<String> = choices.collect();
+    let last = choices.pop().unwrap_or_default();
+    if choices.is_empty() {
+        last
+    } else {
+        format!("{} or {}", choices.join(", "), last)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stt_provider_from_name() {
+        assert_eq!(SttProvider::from_name("websocket").unwrap(), SttProvider::WebSocket);
+        assert_eq!(SttProvider::from_name("rest").unwrap(), SttProvider::Rest);
+        assert!(SttProvider::from_name("grpc").is_err());
+
+        for &(name, provider, _) in PROVIDERS {
+            assert_eq!(SttProvider::from_name(provider.name()).unwrap(), provider);
+            assert_eq!(provider.name(), name);
+        }
+        assert_eq!(SttProvider::help_choices(), "'websocket' (Deepgram) or 'rest' (OpenAI Whisper)");
+        assert_eq!(
+            SttProvider::from_name("grpc").unwrap_err().to_string(),
+            "invalid STT provider 'grpc': must be 'websocket' or 'rest'"
+        );
+    }
+
+    #[test]
+    fn test_recognizers_for_providers() {
+        let settings = RecognizerSettings {
+            url: None,
+            sample_rate: 16_000,
+            eager_eot_threshold: None,
+            eot_threshold: Some(0.8),
+            language: "en",
+            model: "whisper-1",
+        };
+        let websocket = SttProvider::WebSocket.recognizer(&settings);
+        assert!(websocket.streams());
+        let rest = SttProvider::Rest.recognizer(&settings);
+        assert!(!rest.streams());
+        assert_ne!(websocket.name(), rest.name());
+    }
+}
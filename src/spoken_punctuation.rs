@@ -0,0 +1,235 @@
+use crate::text_transform::TextTransformer;
+use Punctuation::*;
+use Spacing::*;
+
+/// How a symbol joins the words around it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Spacing {
+    /// Attaches to the previous word: "hello," / "done."
+    Left,
+    /// Attaches to the next word: "(note" / "$5"
+    Right,
+    /// Joins both words: "well-known" / "and/or"
+    Both,
+    /// Stands alone between spaces: "a - b"
+    Spaced,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Punctuation {
+    Symbol(&'static str, Spacing),
+    OpenQuote,
+    CloseQuote,
+    /// "quote" opens a quotation, or closes the open one
+    Quote,
+}
+
+// Spoken names and the punctuation they stand for
+const PHRASES: &[(&str, Punctuation)] = &[
+    ("comma", Symbol(",", Left)),
+    ("period", Symbol(".", Left)),
+    ("full stop", Symbol(".", Left)),
+    ("question mark", Symbol("?", Left)),
+    ("exclamation mark", Symbol("!", Left)),
+    ("exclamation point", Symbol("!", Left)),
+    ("colon", Symbol(":", Left)),
+    ("semicolon", Symbol(";", Left)),
+    ("semi colon", Symbol(";", Left)),
+    ("ellipsis", Symbol("...", Left)),
+    ("dot dot dot", Symbol("...", Left)),
+    ("open paren", Symbol("(", Right)),
+    ("open parenthesis", Symbol("(", Right)),
+    ("left paren", Symbol("(", Right)),
+    ("close paren", Symbol(")", Left)),
+    ("close parenthesis", Symbol(")", Left)),
+    ("right paren", Symbol(")", Left)),
+    ("open bracket", Symbol("[", Right)),
+    ("close bracket", Symbol("]", Left)),
+    ("open brace", Symbol("{", Right)),
+    ("close brace", Symbol("}", Left)),
+    ("hyphen", Symbol("-", Both)),
+    ("dash", Symbol("-", Spaced)),
+    ("em dash", Symbol("—", Spaced)),
+    ("slash", Symbol("/", Both)),
+    ("underscore", Symbol("_", Both)),
+    ("at sign", Symbol("@", Both)),
+    ("ampersand", Symbol("&", Spaced)),
+    ("asterisk", Symbol("*", Spaced)),
+    ("plus sign", Symbol("+", Spaced)),
+    ("equals sign", Symbol("=", Spaced)),
+    ("hash sign", Symbol("#", Right)),
+    ("dollar sign", Symbol("$", Right)),
+    ("percent sign", Symbol("%", Left)),
+    ("quote", Quote),
+    ("open quote", OpenQuote),
+    ("begin quote", OpenQuote),
+    ("end quote", CloseQuote),
+    ("close quote", CloseQuote),
+    ("unquote", CloseQuote),
+];
+
+/// Turns spoken punctuation ("comma", "question mark", "open paren", "quote ... end quote")
+/// into symbols, for providers that do not punctuate on their own
+///
+/// The whole transcript is rewritten on every update, so the output of a growing
+/// transcript stays consistent with incremental typing
+#[derive(Debug, Clone, Default)]
+pub struct SpokenPunctuation;
+
+impl TextTransformer for SpokenPunctuation {
+    fn transform(&self, text: &str) -> String {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let mut output = String::with_capacity(text.len());
+        // Whether the next word attaches to what came before
+        let mut glue_next = false;
+        let mut quote_open = false;
+
+        let mut i = 0;
+        while i < words.len() {
+            let Some((punctuation, len)) = match_phrase(&words[i..]) else {
+                push_spaced(&mut output, words[i], glue_next);
+                glue_next = false;
+                i += 1;
+                continue;
+            };
+            i += len;
+
+            let (symbol, spacing) = match punctuation {
+                Symbol(symbol, spacing) => (symbol, spacing),
+                OpenQuote => {
+                    quote_open = true;
+                    ("\"", Right)
+                }
+                CloseQuote => {
+                    quote_open = false;
+                    ("\"", Left)
+                }
+                Quote => {
+                    quote_open = !quote_open;
+                    ("\"", if quote_open { Right } else { Left })
+                }
+            };
+
+            match spacing {
+                Left | Both => output.push_str(symbol),
+                Right | Spaced => push_spaced(&mut output, symbol, glue_next),
+            }
+            glue_next = matches!(spacing, Right | Both);
+        }
+
+        output
+    }
+}
+
+fn push_spaced(output: &mut String, word: &str, glue: bool) {
+    if !output.is_empty() && !glue {
+        output.push(' ');
+    }
+    output.push_str(word);
+}
+
+// Longest spoken name at the start of `words`, and how many words it spans
+fn match_phrase(words: &[&str]) -> Option<(Punctuation, usize)> {
+    PHRASES
+        .iter()
+        .filter_map(|&(phrase, punctuation)| {
+            let len = phrase.split(' ').count();
+            let matches = len <= words.len()
+                && phrase
+                    .split(' ')
+                    .zip(words)
+                    .all(|(expected, word)| normalize(word) == expected);
+            matches.then_some((punctuation, len))
+        })
+        .max_by_key(|&(_, len)| len)
+}
+
+// Case-insensitive, ignoring punctuation the provider attached ("Comma," -> "comma")
+fn normalize(word: &str) -> String {
+    word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spoken_punctuation_symbols() {
+        let test_cases = vec![
+            ("hello comma world period", "hello, world."),
+            ("is it done question mark", "is it done?"),
+            ("wow exclamation point", "wow!"),
+            ("note colon see open paren below close paren", "note: see (below)"),
+            ("a well hyphen known fact", "a well-known fact"),
+            ("and slash or", "and/or"),
+            ("wait dot dot dot", "wait..."),
+            ("costs dollar sign 5", "costs $5"),
+            ("fifty percent sign", "fifty%"),
+            ("yes dash no", "yes - no"),
+        ];
+
+        for (input, expected) in test_cases {
+            assert_eq!(SpokenPunctuation.transform(input), expected, "For: '{}'", input);
+        }
+    }
+
+    #[test]
+    fn test_spoken_punctuation_quotes() {
+        let test_cases = vec![
+            ("he said quote hello end quote", r#"he said "hello""#),
+            ("quote one unquote and quote two quote", r#""one" and "two""#),
+            ("open quote hi comma there close quote period", r#""hi, there"."#),
+            ("he said quote", r#"he said ""#),
+        ];
+
+        for (input, expected) in test_cases {
+            assert_eq!(SpokenPunctuation.transform(input), expected, "For: '{}'", input);
+        }
+    }
+
+    #[test]
+    fn test_spoken_punctuation_case_and_provider_punctuation() {
+        let test_cases = vec![
+            ("Hello Comma world", "Hello, world"),
+            ("hello COMMA, world", "hello, world"),
+            ("Question mark.", "?"),
+            ("  spaced   out  ", "spaced out"),
+        ];
+
+        for (input, expected) in test_cases {
+            assert_eq!(SpokenPunctuation.transform(input), expected, "For: '{}'", input);
+        }
+    }
+
+    #[test]
+    fn test_spoken_punctuation_negative_cases() {
+        // Words that only contain a spoken name are left alone
+        let test_cases = vec![
+            "commas and periods",
+            "a periodic table",
+            "quoted text",
+            "the dashboard",
+            "",
+        ];
+
+        for input in test_cases {
+            assert_eq!(SpokenPunctuation.transform(input), input, "Should not change: '{}'", input);
+        }
+    }
+
+    #[test]
+    fn test_spoken_punctuation_incremental() {
+        // Each partial transcript is rewritten on its own
+        let updates = [
+            ("open", "open"),
+            ("open paren", "("),
+            ("open paren aside", "(aside"),
+            ("open paren aside close", "(aside close"),
+            ("open paren aside close paren", "(aside)"),
+        ];
+
+        for (input, expected) in updates {
+            assert_eq!(SpokenPunctuation.transform(input), expected, "For: '{}'", input);
+        }
+    }
+}
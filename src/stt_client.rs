@@ -2,24 +2,106 @@ use anyhow::{anyhow, bail, Context, Result};
 use futures_util::{SinkExt, StreamExt};
 use http::{header::AUTHORIZATION, HeaderValue};
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use std::env;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::runtime::Handle;
 use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 use tokio_tungstenite::tungstenite::client::IntoClientRequest;
 use tokio_tungstenite::tungstenite::error::Error as WsError;
-use tokio_tungstenite::{connect_async, tungstenite::Message};
-use tracing::{debug, error, info};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tracing::{debug, error, info, warn};
+
+use crate::audio_input;
+use crate::speech_recognizer::{EventSink, RecognizerSession, SpeechRecognizer, StatusSink};
 
 pub const STT_URL: &str = "wss://api.deepgram.com/v2/listen";
 
+// Reconnecting after the connection drops: the delay doubles from the initial one up to
+// the cap, and audio captured meanwhile is kept up to the buffer length
+const RECONNECT_ATTEMPTS: u32 = 8;
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_millis(250);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(8);
+const RECONNECT_BUFFER_SECS: u32 = 30;
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WordInfo {
     pub word: String,
     pub confidence: f64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// What a speech-to-text provider reports, in the order it happened
+#[derive(Debug, Clone)]
+pub enum SttEvent {
+    /// A connection was made; its turns are numbered from 0
+    Connected { request_id: String },
+    /// Speech started a new turn
+    StartOfTurn(TranscriptionResult),
+    /// The transcript of the open turn so far
+    Update(TranscriptionResult),
+    /// The turn has probably ended, but speech may still resume it
+    EagerEndOfTurn(TranscriptionResult),
+    /// Speech continued a turn that had an EagerEndOfTurn
+    TurnResumed(TranscriptionResult),
+    /// The turn has ended, with its final transcript
+    EndOfTurn(TranscriptionResult),
+    /// The provider reported an error
+    Error { code: String, description: String },
+    /// The session is over; nothing else will come for it
+    Closed,
+}
+
+impl SttEvent {
+    /// Map a Flux `TurnInfo` event; unknown events are taken as updates
+    fn from_turn_info(event: &str, result: TranscriptionResult) -> Self {
+        match event {
+            "StartOfTurn" => SttEvent::StartOfTurn(result),
+            "Update" => SttEvent::Update(result),
+            "EagerEndOfTurn" => SttEvent::EagerEndOfTurn(result),
+            "TurnResumed" => SttEvent::TurnResumed(result),
+            "EndOfTurn" => SttEvent::EndOfTurn(result),
+            other => {
+                warn!("Unknown TurnInfo event '{}', taking it as an update", other);
+                SttEvent::Update(result)
+            }
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SttEvent::Connected { .. } => "Connected",
+            SttEvent::StartOfTurn(_) => "StartOfTurn",
+            SttEvent::Update(_) => "Update",
+            SttEvent::EagerEndOfTurn(_) => "EagerEndOfTurn",
+            SttEvent::TurnResumed(_) => "TurnResumed",
+            SttEvent::EndOfTurn(_) => "EndOfTurn",
+            SttEvent::Error { .. } => "Error",
+            SttEvent::Closed => "Closed",
+        }
+    }
+
+    /// The turn's transcript, for the events that carry one
+    pub fn result(&self) -> Option<&TranscriptionResult> {
+        match self {
+            SttEvent::StartOfTurn(result)
+            | SttEvent::Update(result)
+            | SttEvent::EagerEndOfTurn(result)
+            | SttEvent::TurnResumed(result)
+            | SttEvent::EndOfTurn(result) => Some(result),
+            SttEvent::Connected { .. } | SttEvent::Error { .. } | SttEvent::Closed => None,
+        }
+    }
+}
+
+/// The transcript of a turn as of an event
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TranscriptionResult {
-    pub event: String,
     pub turn_index: u32,
     pub start: f64,
     pub timestamp: f64,
@@ -28,6 +110,21 @@ pub struct TranscriptionResult {
     pub end_of_turn_confidence: f64,
 }
 
+impl TranscriptionResult {
+    /// The start of the transcript up to the first word below `min_confidence`
+    pub fn confident_prefix(&self, min_confidence: f64) -> &str {
+        // `words` lines up with the words of the transcript
+        let count = self.words.iter().take_while(|w| w.confidence >= min_confidence).count();
+        match self.transcript.split_whitespace().take(count).last() {
+            Some(last) => {
+                let end = last.as_ptr() as usize - self.transcript.as_ptr() as usize + last.len();
+                &self.transcript[..end]
+            }
+            None => "",
+        }
+    }
+}
+
 // New server message schema with `type` discriminator
 #[derive(Debug, Clone, Deserialize)]
 #[serde(tag = "type")]
@@ -118,6 +215,7 @@ pub struct SttClient {
     eager_eot_threshold: Option<f64>,
     #[allow(dead_code)]
     eot_threshold: Option<f64>,
+    status_callback: Option<StatusSink>,
 }
 
 impl SttClient {
@@ -132,15 +230,23 @@ impl SttClient {
             sample_rate,
             eager_eot_threshold,
             eot_threshold,
+            status_callback: None,
         }
     }
 
+    /// Connect and start streaming
+    ///
+    /// Fails if the first connection cannot be made. If the connection drops later, the
+    /// client reconnects with exponential backoff, holding on to the audio captured in
+    /// the meantime and replaying it once connected again. The task ends when the audio
+    /// sender is dropped, or with an error once reconnecting gives up; either way its
+    /// last event is `SttEvent::Closed`.
     pub async fn connect_and_transcribe<F>(
         &self,
-        mut on_transcription: F,
-    ) -> Result<(mpsc::Sender<Vec<u8>>, tokio::task::JoinHandle<Result<()>>)>
+        mut on_event: F,
+    ) -> Result<(mpsc::Sender<Vec<u8>>, JoinHandle<Result<()>>)>
     where
-        F: FnMut(TranscriptionResult) + Send + 'static,
+        F: FnMut(SttEvent) + Send + 'static,
     {
         // Build WebSocket URL with query parameters
         // eager_eot_threshold enables eager end-of-turn detection (range 0.3-0.9)
@@ -158,20 +264,14 @@ impl SttClient {
             ws_url.push_str(&format!("&eot_threshold={}", threshold));
         }
 
-        debug!("Connecting to speech-to-text service: {}", ws_url);
-
-        // Build request (allows setting headers)
-        let mut request = ws_url
-            .into_client_request()
-            .context("Failed to build websocket client request")?;
-
         // Optional Authorization from environment
+        let mut authorization = None;
         if let Ok(api_key) = env::var("DEEPGRAM_API_KEY") {
             if !api_key.is_empty() {
                 let value = format!("Token {api_key}");
                 match HeaderValue::from_str(&value) {
                     Ok(hv) => {
-                        request.headers_mut().insert(AUTHORIZATION, hv);
+                        authorization = Some(hv);
                         debug!("Added Authorization header from DEEPGRAM_API_KEY");
                     }
                     Err(_) => {
@@ -186,180 +286,518 @@ impl SttClient {
             debug!("DEEPGRAM_API_KEY not set; connecting without Authorization header");
         }
 
-        // Establish WebSocket connection with the request
-        let (ws_stream, _resp) = connect_async(request).await.map_err(enrich_ws_error)?;
-
-        debug!("Connected to speech-to-text service");
+        let connector = Connector { ws_url, authorization };
+        let ws_stream = connector.connect().await?;
 
-        let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+        let status_callback = self.status_callback.clone();
+        let report = move |status: ConnectionStatus| {
+            debug!("Connection status: {:?}", status);
+            if let Some(callback) = &status_callback {
+                callback(status);
+            }
+        };
+        report(ConnectionStatus::Connected);
 
         // Create channel for sending audio data
         let (audio_tx, mut audio_rx) = mpsc::channel::<Vec<u8>>(32);
+        let mut backlog = AudioBacklog::new(self.sample_rate, RECONNECT_BUFFER_SECS);
 
         // Spawn task to handle WebSocket communication
         let handle = tokio::spawn(async move {
-            // Task to send audio data (fatal on send error)
-            let send_task = tokio::spawn(async move {
-                while let Some(audio_data) = audio_rx.recv().await {
-                    if let Err(e) = ws_sender
-                        .send(Message::Binary(audio_data))
-                        .await
-                        .map_err(enrich_ws_error)
-                    {
-                        error!("Failed to send audio data: {}", e);
-                        return Err(e);
-                    }
-                }
-
-                // Audio channel closed: inform server no more audio is coming
-                let close_msg = String::from("{\"type\":\"CloseStream\"}");
-                debug!("Sending CloseStream control message");
-                ws_sender
-                    .send(Message::Text(close_msg))
-                    .await
-                    .map_err(enrich_ws_error)?;
-
-                // Do not close the socket from client; server will close after sending responses
-                Ok::<(), anyhow::Error>(())
-            });
+            let result: Result<()> = async {
+                let mut ws_stream = ws_stream;
+                loop {
+                    // Each connection starts with a Connected event, which tells the
+                    // consumer that turns are numbered from 0 again
+                    let error = match stream(ws_stream, &mut audio_rx, &mut backlog, &mut on_event).await {
+                        Ok(()) => return Ok(()),
+                        Err(e) => e,
+                    };
+                    warn!("Lost connection to speech-to-text service: {:#}", error);
 
-            // Task to receive messages (fatal on parse/socket error per policy)
-            let receive_task = tokio::spawn(async move {
-                while let Some(msg) = ws_receiver.next().await {
-                    match msg {
-                        Ok(Message::Text(text)) => {
-                            debug!("Received text message: {}", text);
-
-                            // Parse by `type`
-                            let parsed: ServerMessage = match serde_json::from_str(&text) {
-                                Ok(m) => m,
-                                Err(e) => {
-                                    error!("Failed to parse message JSON: {} in {}", e, text);
-                                    return Err(anyhow!("invalid server JSON: {e}"));
-                                }
-                            };
-
-                            match parsed {
-                                ServerMessage::Connected {
-                                    request_id,
-                                    sequence_id,
-                                } => {
-                                    info!(
-                                        "Connected: request_id={}, sequence_id={}",
-                                        request_id, sequence_id
-                                    );
-                                }
-                                ServerMessage::Configuration {
-                                    eot_threshold,
-                                    preflight_threshold,
-                                } => {
-                                    info!("Configuration ack: eot_threshold={:?}, preflight_threshold={:?}", eot_threshold, preflight_threshold);
-                                }
-                                ServerMessage::EagerEndOfTurn {
-                                    request_id: _,
-                                    sequence_id: _,
-                                    turn_index,
-                                } => {
-                                    info!("EagerEndOfTurn detected for turn_index={}", turn_index);
-                                    // Send a special EagerEndOfTurn event to the callback
-                                    let result = TranscriptionResult {
-                                        event: "EagerEndOfTurn".to_string(),
-                                        turn_index,
-                                        start: 0.0,
-                                        timestamp: 0.0,
-                                        transcript: String::new(),
-                                        words: Vec::new(),
-                                        end_of_turn_confidence: 0.0,
-                                    };
-                                    on_transcription(result);
-                                }
-                                ServerMessage::TurnResumed {
-                                    request_id: _,
-                                    sequence_id: _,
-                                    turn_index,
-                                } => {
-                                    info!("TurnResumed for turn_index={}", turn_index);
-                                    // Send a special TurnResumed event to the callback
-                                    let result = TranscriptionResult {
-                                        event: "TurnResumed".to_string(),
-                                        turn_index,
-                                        start: 0.0,
-                                        timestamp: 0.0,
-                                        transcript: String::new(),
-                                        words: Vec::new(),
-                                        end_of_turn_confidence: 0.0,
-                                    };
-                                    on_transcription(result);
-                                }
-                                ServerMessage::Error {
-                                    sequence_id,
-                                    code,
-                                    description,
-                                    websocket_close_code,
-                                } => {
-                                    error!(
-                                        "Server error [{}]: {} (close_code={:?}, seq={:?})",
-                                        code, description, websocket_close_code, sequence_id
-                                    );
-                                    return Err(anyhow!(
-                                        "server error: {} - {}",
-                                        code,
-                                        description
-                                    ));
-                                }
-                                ServerMessage::TurnInfo {
-                                    request_id: _,
-                                    sequence_id: _,
-                                    event,
-                                    turn_index,
-                                    audio_window_start,
-                                    audio_window_end,
-                                    transcript,
-                                    words,
-                                    end_of_turn_confidence,
-                                } => {
-                                    // Map to callback struct
-                                    let result = TranscriptionResult {
-                                        event,
-                                        turn_index,
-                                        start: audio_window_start,
-                                        timestamp: audio_window_end,
-                                        transcript,
-                                        words,
-                                        end_of_turn_confidence,
-                                    };
-                                    on_transcription(result);
-                                }
-                            }
-                        }
-                        Ok(Message::Binary(_data)) => {
-                            return Err(anyhow!("received binary data--this isn't expected"))
-                        }
-                        Ok(Message::Close(_)) => {
-                            debug!("WebSocket closed by server");
-                            break;
-                        }
+                    ws_stream = match reconnect(&connector, &mut audio_rx, &mut backlog, &report).await {
+                        Ok(Some(ws_stream)) => ws_stream,
+                        // The session was stopped while disconnected
+                        Ok(None) => return Err(error),
                         Err(e) => {
-                            let e2 = enrich_ws_error(e);
-                            error!("WebSocket error: {}", e2);
-                            return Err(e2);
+                            report(ConnectionStatus::Disconnected);
+                            return Err(e);
                         }
-                        _ => {}
-                    }
+                    };
+                    report(ConnectionStatus::Connected);
                 }
-                Ok::<(), anyhow::Error>(())
-            });
-
-            // Wait for both tasks to finish
-            let (_sr, _rr) = tokio::try_join!(send_task, receive_task)?;
+            }
+            .await;
 
-            Ok(())
+            // However the session ended, nothing else will come for it
+            on_event(SttEvent::Closed);
+            result
         });
 
         Ok((audio_tx, handle))
     }
 }
 
+impl SpeechRecognizer for SttClient {
+    fn name(&self) -> &'static str {
+        "WebSocket (Deepgram)"
+    }
+
+    fn streams(&self) -> bool {
+        true
+    }
+
+    fn start_session(
+        &mut self,
+        runtime: &Handle,
+        on_event: EventSink,
+        on_status: StatusSink,
+    ) -> Result<Box<dyn RecognizerSession>> {
+        info!("Creating new WebSocket STT connection...");
+        self.status_callback = Some(on_status);
+        let sink = on_event.clone();
+        let (audio_tx, handle) = runtime.block_on(self.connect_and_transcribe(move |event| sink(event)))?;
+        info!("STT connection established");
+        Ok(Box::new(StreamingSession {
+            audio_tx,
+            handle,
+            chunks: AudioBuffer::new(self.sample_rate, 160),
+            runtime: runtime.clone(),
+            on_event,
+        }))
+    }
+}
+
+/// A session of `SttClient`, which streams the audio in chunks as it comes in
+struct StreamingSession {
+    audio_tx: mpsc::Sender<Vec<u8>>,
+    handle: JoinHandle<Result<()>>,
+    chunks: AudioBuffer,
+    runtime: Handle,
+    on_event: EventSink,
+}
+
+impl RecognizerSession for StreamingSession {
+    fn push_audio(&mut self, samples: &[f32]) {
+        for chunk in self.chunks.add_samples(samples) {
+            debug!("Sending audio chunk: {} bytes", chunk.len());
+            if let Err(e) = self.audio_tx.blocking_send(chunk) {
+                error!("Failed to send audio chunk: {}", e);
+            }
+        }
+    }
+
+    fn finish(mut self: Box<Self>, timeout: Duration) {
+        // Send the last samples; dropping the sender makes the task send CloseStream
+        if let Some(rest) = self.chunks.flush() {
+            debug!("Sending last audio chunk: {} bytes", rest.len());
+            if let Err(e) = self.audio_tx.blocking_send(rest) {
+                error!("Failed to send audio chunk: {}", e);
+            }
+        }
+        let StreamingSession { audio_tx, mut handle, runtime, on_event, .. } = *self;
+        drop(audio_tx);
+
+        // Wait for the final results until the server closes the connection; the task
+        // ends with a Closed event unless it has to be cut short
+        let closed = match runtime.block_on(async { tokio::time::timeout(timeout, &mut handle).await }) {
+            Ok(Ok(Ok(()))) => {
+                debug!("STT session finished");
+                true
+            }
+            Ok(Ok(Err(e))) => {
+                warn!("STT session ended with an error: {:#}", e);
+                true
+            }
+            Ok(Err(e)) => {
+                error!("STT task failed: {}", e);
+                false
+            }
+            Err(_) => {
+                info!("No final transcript within {:?}, typing what is pending", timeout);
+                handle.abort();
+                false
+            }
+        };
+        if !closed {
+            on_event(SttEvent::Closed);
+        }
+    }
+
+    fn cancel(self: Box<Self>) {
+        self.handle.abort();
+    }
+}
+
+/// Whether the streaming connection is up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectionStatus {
+    Connected,
+    /// The connection dropped; audio is buffered while reconnect attempt `attempt` is made
+    Reconnecting { attempt: u32 },
+    /// Not connected: no session is running, or reconnecting gave up
+    Disconnected,
+}
+
+impl ConnectionStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConnectionStatus::Connected => "connected",
+            ConnectionStatus::Reconnecting { .. } => "reconnecting",
+            ConnectionStatus::Disconnected => "disconnected",
+        }
+    }
+}
+
+// Everything needed to open the connection again after it dropped
+struct Connector {
+    ws_url: String,
+    authorization: Option<HeaderValue>,
+}
+
+impl Connector {
+    async fn connect(&self) -> Result<WsStream> {
+        debug!("Connecting to speech-to-text service: {}", self.ws_url);
+
+        // Build request (allows setting headers)
+        let mut request = self
+            .ws_url
+            .as_str()
+            .into_client_request()
+            .context("Failed to build websocket client request")?;
+        if let Some(authorization) = &self.authorization {
+            request.headers_mut().insert(AUTHORIZATION, authorization.clone());
+        }
+
+        // Establish WebSocket connection with the request
+        let (ws_stream, _resp) = connect_async(request).await.map_err(enrich_ws_error)?;
+
+        debug!("Connected to speech-to-text service");
+        Ok(ws_stream)
+    }
+}
+
+// Stream audio over one connection until the audio ends and the server has closed it.
+// Audio left in the backlog goes out first; a chunk that fails to send is put back
+async fn stream<F>(
+    ws_stream: WsStream,
+    audio_rx: &mut mpsc::Receiver<Vec<u8>>,
+    backlog: &mut AudioBacklog,
+    on_event: &mut F,
+) -> Result<()>
+where
+    F: FnMut(SttEvent),
+{
+    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+
+    // Set once CloseStream is sent, after which the server closing is expected
+    let closing = AtomicBool::new(false);
+
+    // Send audio data (fatal on send error)
+    let send = async {
+        if !backlog.is_empty() {
+            info!(
+                "Replaying {:.1}s of audio captured while disconnected ({:.1}s dropped)",
+                backlog.duration_secs(),
+                backlog.dropped_secs()
+            );
+        }
+        while let Some(audio_data) = backlog.pop() {
+            if let Err(e) = ws_sender
+                .send(Message::Binary(audio_data.clone()))
+                .await
+                .map_err(enrich_ws_error)
+            {
+                error!("Failed to replay audio data: {}", e);
+                backlog.push_front(audio_data);
+                return Err(e);
+            }
+        }
+
+        while let Some(audio_data) = audio_rx.recv().await {
+            if let Err(e) = ws_sender
+                .send(Message::Binary(audio_data.clone()))
+                .await
+                .map_err(enrich_ws_error)
+            {
+                error!("Failed to send audio data: {}", e);
+                backlog.push(audio_data);
+                return Err(e);
+            }
+        }
+
+        // Audio channel closed: inform server no more audio is coming
+        closing.store(true, Ordering::SeqCst);
+        let close_msg = String::from("{\"type\":\"CloseStream\"}");
+        debug!("Sending CloseStream control message");
+        ws_sender
+            .send(Message::Text(close_msg))
+            .await
+            .map_err(enrich_ws_error)?;
+
+        // Do not close the socket from client; server will close after sending responses
+        Ok::<(), anyhow::Error>(())
+    };
+
+    // Receive messages (fatal on parse/socket error per policy)
+    let receive = async {
+        while let Some(msg) = ws_receiver.next().await {
+            match msg {
+                Ok(Message::Text(text)) => {
+                    debug!("Received text message: {}", text);
+                    handle_text_message(&text, on_event)?;
+                }
+                Ok(Message::Binary(_data)) => {
+                    return Err(anyhow!("received binary data--this isn't expected"))
+                }
+                Ok(Message::Close(_)) => {
+                    debug!("WebSocket closed by server");
+                    break;
+                }
+                Err(e) => {
+                    let e2 = enrich_ws_error(e);
+                    error!("WebSocket error: {}", e2);
+                    return Err(e2);
+                }
+                _ => {}
+            }
+        }
+        if !closing.load(Ordering::SeqCst) {
+            bail!("WebSocket closed before the audio ended");
+        }
+        Ok::<(), anyhow::Error>(())
+    };
+
+    // Wait for both sides to finish
+    match tokio::try_join!(send, receive) {
+        Ok(_) => Ok(()),
+        // All audio went out, so reconnecting could not bring back the trailing results
+        Err(e) if closing.load(Ordering::SeqCst) => {
+            warn!("Connection dropped after the audio ended: {:#}", e);
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn handle_text_message<F>(text: &str, on_event: &mut F) -> Result<()>
+where
+    F: FnMut(SttEvent),
+{
+    // Parse by `type`
+    let parsed: ServerMessage = match serde_json::from_str(text) {
+        Ok(m) => m,
+        Err(e) => {
+            error!("Failed to parse message JSON: {} in {}", e, text);
+            return Err(anyhow!("invalid server JSON: {e}"));
+        }
+    };
+
+    match parsed {
+        ServerMessage::Connected {
+            request_id,
+            sequence_id,
+        } => {
+            info!(
+                "Connected: request_id={}, sequence_id={}",
+                request_id, sequence_id
+            );
+            on_event(SttEvent::Connected { request_id });
+        }
+        ServerMessage::Configuration {
+            eot_threshold,
+            preflight_threshold,
+        } => {
+            info!("Configuration ack: eot_threshold={:?}, preflight_threshold={:?}", eot_threshold, preflight_threshold);
+        }
+        ServerMessage::EagerEndOfTurn {
+            request_id: _,
+            sequence_id: _,
+            turn_index,
+        } => {
+            info!("EagerEndOfTurn detected for turn_index={}", turn_index);
+            on_event(SttEvent::EagerEndOfTurn(TranscriptionResult {
+                turn_index,
+                ..Default::default()
+            }));
+        }
+        ServerMessage::TurnResumed {
+            request_id: _,
+            sequence_id: _,
+            turn_index,
+        } => {
+            info!("TurnResumed for turn_index={}", turn_index);
+            on_event(SttEvent::TurnResumed(TranscriptionResult {
+                turn_index,
+                ..Default::default()
+            }));
+        }
+        ServerMessage::Error {
+            sequence_id,
+            code,
+            description,
+            websocket_close_code,
+        } => {
+            error!(
+                "Server error [{}]: {} (close_code={:?}, seq={:?})",
+                code, description, websocket_close_code, sequence_id
+            );
+            let error = anyhow!("server error: {} - {}", code, description);
+            on_event(SttEvent::Error { code, description });
+            return Err(error);
+        }
+        ServerMessage::TurnInfo {
+            request_id: _,
+            sequence_id: _,
+            event,
+            turn_index,
+            audio_window_start,
+            audio_window_end,
+            transcript,
+            words,
+            end_of_turn_confidence,
+        } => {
+            // Map to callback struct
+            let result = TranscriptionResult {
+                turn_index,
+                start: audio_window_start,
+                timestamp: audio_window_end,
+                transcript,
+                words,
+                end_of_turn_confidence,
+            };
+            on_event(SttEvent::from_turn_info(&event, result));
+        }
+    }
+    Ok(())
+}
+
+// Delay before reconnect attempt `attempt` (from 1): doubles each time, up to a cap
+fn backoff_delay(attempt: u32) -> Duration {
+    let factor = 1u32 << attempt.saturating_sub(1).min(16);
+    (RECONNECT_INITIAL_DELAY * factor).min(RECONNECT_MAX_DELAY)
+}
+
+// Reconnect with exponential backoff, buffering audio all the while. None if the audio
+// ended first; an error once every attempt failed
+async fn reconnect(
+    connector: &Connector,
+    audio_rx: &mut mpsc::Receiver<Vec<u8>>,
+    backlog: &mut AudioBacklog,
+    report: &impl Fn(ConnectionStatus),
+) -> Result<Option<WsStream>> {
+    let mut last_error = None;
+    for attempt in 1..=RECONNECT_ATTEMPTS {
+        report(ConnectionStatus::Reconnecting { attempt });
+        let delay = backoff_delay(attempt);
+        info!("Reconnecting in {:?} (attempt {}/{})", delay, attempt, RECONNECT_ATTEMPTS);
+
+        let connected = match buffer_audio(tokio::time::sleep(delay), audio_rx, backlog).await {
+            Some(()) => buffer_audio(connector.connect(), audio_rx, backlog).await,
+            None => None,
+        };
+        match connected {
+            Some(Ok(ws_stream)) => {
+                info!("Reconnected to speech-to-text service");
+                return Ok(Some(ws_stream));
+            }
+            Some(Err(e)) => {
+                warn!("Reconnect attempt {} failed: {:#}", attempt, e);
+                last_error = Some(e);
+            }
+            None => {
+                info!(
+                    "Audio ended while disconnected, dropping {:.1}s of buffered audio",
+                    backlog.duration_secs()
+                );
+                return Ok(None);
+            }
+        }
+    }
+    let error = last_error.unwrap_or_else(|| anyhow!("no reconnect attempts allowed"));
+    Err(error.context(format!("Gave up reconnecting after {} attempts", RECONNECT_ATTEMPTS)))
+}
+
+// Wait for `future`, moving captured audio into the backlog meanwhile so the audio
+// callback never blocks; None if the audio ended first
+async fn buffer_audio<T>(
+    future: impl Future<Output = T>,
+    audio_rx: &mut mpsc::Receiver<Vec<u8>>,
+    backlog: &mut AudioBacklog,
+) -> Option<T> {
+    tokio::pin!(future);
+    loop {
+        tokio::select! {
+            output = &mut future => return Some(output),
+            audio_data = audio_rx.recv() => backlog.push(audio_data?),
+        }
+    }
+}
+
+/// Audio captured while disconnected, in chunks; past its capacity the oldest audio is
+/// dropped
+struct AudioBacklog {
+    chunks: VecDeque<Vec<u8>>,
+    len: usize,
+    capacity: usize,
+    dropped: usize,
+    bytes_per_sec: usize,
+}
+
+impl AudioBacklog {
+    fn new(sample_rate: u32, max_secs: u32) -> Self {
+        // 16-bit mono PCM
+        let bytes_per_sec = sample_rate as usize * 2;
+        Self {
+            chunks: VecDeque::new(),
+            len: 0,
+            capacity: bytes_per_sec * max_secs as usize,
+            dropped: 0,
+            bytes_per_sec,
+        }
+    }
+
+    fn push(&mut self, chunk: Vec<u8>) {
+        self.len += chunk.len();
+        self.chunks.push_back(chunk);
+        while self.len > self.capacity {
+            let Some(oldest) = self.chunks.pop_front() else {
+                break;
+            };
+            self.len -= oldest.len();
+            self.dropped += oldest.len();
+        }
+    }
+
+    // Put back a chunk taken with `pop` that could not be sent
+    fn push_front(&mut self, chunk: Vec<u8>) {
+        self.len += chunk.len();
+        self.chunks.push_front(chunk);
+    }
+
+    fn pop(&mut self) -> Option<Vec<u8>> {
+        let chunk = self.chunks.pop_front();
+        match &chunk {
+            Some(chunk) => self.len -= chunk.len(),
+            // Everything was replayed; start counting drops afresh
+            None => self.dropped = 0,
+        }
+        chunk
+    }
+
+    fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    fn duration_secs(&self) -> f64 {
+        self.len as f64 / self.bytes_per_sec as f64
+    }
+
+    fn dropped_secs(&self) -> f64 {
+        self.dropped as f64 / self.bytes_per_sec as f64
+    }
+}
+
 #[derive(Clone)]
 pub struct AudioBuffer {
     buffer: Vec<u8>,
@@ -385,13 +823,7 @@ impl AudioBuffer {
 
     pub fn add_samples(&mut self, samples: &[f32]) -> Vec<Vec<u8>> {
         // Convert f32 samples to 16-bit PCM
-        let pcm_data: Vec<u8> = samples
-            .iter()
-            .flat_map(|&sample| {
-                let pcm_sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
-                pcm_sample.to_le_bytes()
-            })
-            .collect();
+        let pcm_data = audio_input::to_pcm16(samples);
 
         self.buffer.extend_from_slice(&pcm_data);
 
@@ -415,7 +847,6 @@ impl AudioBuffer {
         chunks
     }
 
-    #[allow(dead_code)]
     pub fn flush(&mut self) -> Option<Vec<u8>> {
         if !self.buffer.is_empty() {
             let remaining = self.buffer.drain(..).collect();
@@ -429,17 +860,118 @@ impl AudioBuffer {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
-    };
-    use std::time::Duration;
+    use crate::mock_flux::{MockFluxServer, Step};
+    use std::sync::Arc;
 
     fn init_tracing() {
         let _ = tracing_subscriber::fmt::try_init();
     }
 
+    #[test]
+    fn test_confident_prefix() {
+        let word = |word: &str, confidence| WordInfo {
+            word: word.to_string(),
+            confidence,
+        };
+        let result = TranscriptionResult {
+            turn_index: 0,
+            start: 0.0,
+            timestamp: 1.0,
+            transcript: "Hello, big world.".to_string(),
+            words: vec![word("hello", 0.95), word("big", 0.9), word("world", 0.4)],
+            end_of_turn_confidence: 0.1,
+        };
+
+        assert_eq!(result.confident_prefix(0.9), "Hello, big");
+        assert_eq!(result.confident_prefix(0.3), "Hello, big world.");
+        assert_eq!(result.confident_prefix(0.99), "");
+    }
+
+    #[test]
+    fn test_server_messages_to_events() {
+        let mut events = Vec::new();
+        let mut on_event = |event: SttEvent| events.push(event);
+        let turn_info = |event: &str| {
+            format!(
+                r#"{{"type":"TurnInfo","request_id":"r","sequence_id":1,"event":"{}","turn_index":2,"audio_window_start":0.0,"audio_window_end":1.5,"transcript":"hi there","words":[],"end_of_turn_confidence":0.9}}"#,
+                event
+            )
+        };
+
+        handle_text_message(r#"{"type":"Connected","request_id":"abc","sequence_id":0}"#, &mut on_event).unwrap();
+        handle_text_message(&turn_info("StartOfTurn"), &mut on_event).unwrap();
+        handle_text_message(&turn_info("EndOfTurn"), &mut on_event).unwrap();
+        handle_text_message(&turn_info("Whatever"), &mut on_event).unwrap();
+        handle_text_message(r#"{"type":"TurnResumed","request_id":"r","sequence_id":2,"turn_index":3}"#, &mut on_event).unwrap();
+        let error = handle_text_message(r#"{"type":"Error","code":"BAD","description":"oops"}"#, &mut on_event);
+        assert_eq!(error.unwrap_err().to_string(), "server error: BAD - oops");
+
+        let names: Vec<&str> = events.iter().map(SttEvent::name).collect();
+        assert_eq!(names, ["Connected", "StartOfTurn", "EndOfTurn", "Update", "TurnResumed", "Error"]);
+        assert!(matches!(&events[0], SttEvent::Connected { request_id } if request_id == "abc"));
+        let result = events[2].result().unwrap();
+        assert_eq!((result.turn_index, result.transcript.as_str(), result.timestamp), (2, "hi there", 1.5));
+        assert_eq!(events[4].result().unwrap().turn_index, 3);
+        assert!(events[5].result().is_none());
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        assert_eq!(backoff_delay(1), Duration::from_millis(250));
+        assert_eq!(backoff_delay(2), Duration::from_millis(500));
+        assert_eq!(backoff_delay(4), Duration::from_secs(2));
+        assert_eq!(backoff_delay(6), RECONNECT_MAX_DELAY);
+        assert_eq!(backoff_delay(100), RECONNECT_MAX_DELAY);
+    }
+
+    #[test]
+    fn test_audio_backlog_drops_oldest() {
+        // 1 second holds 4 chunks of 250 ms at 8 kHz
+        let mut backlog = AudioBacklog::new(8_000, 1);
+        for i in 0..6u8 {
+            backlog.push(vec![i; 4_000]);
+        }
+        assert_eq!(backlog.duration_secs(), 1.0);
+        assert_eq!(backlog.dropped_secs(), 0.5);
+
+        // A chunk put back goes out first
+        let first = backlog.pop().unwrap();
+        assert_eq!(first[0], 2);
+        backlog.push_front(first);
+        let replayed: Vec<u8> = std::iter::from_fn(|| backlog.pop()).map(|chunk| chunk[0]).collect();
+        assert_eq!(replayed, [2, 3, 4, 5]);
+        assert!(backlog.is_empty());
+        assert_eq!(backlog.dropped_secs(), 0.0);
+    }
+
     #[tokio::test]
+    async fn test_buffer_audio_while_waiting() {
+        let mut backlog = AudioBacklog::new(16_000, 1);
+        let (audio_tx, mut audio_rx) = mpsc::channel(4);
+
+        // Audio keeps flowing while the future is pending
+        let sender = tokio::spawn(async move {
+            for _ in 0..10 {
+                audio_tx.send(vec![0u8; 100]).await.unwrap();
+            }
+            audio_tx
+        });
+        let wait = async { sender.await.unwrap() };
+        let audio_tx = buffer_audio(wait, &mut audio_rx, &mut backlog).await.unwrap();
+        while let Ok(chunk) = audio_rx.try_recv() {
+            backlog.push(chunk);
+        }
+        assert_eq!(backlog.duration_secs(), 1_000.0 / 32_000.0);
+
+        // Once the audio ends there is nothing left to wait for
+        drop(audio_tx);
+        let pending = std::future::pending::<()>();
+        assert!(buffer_audio(pending, &mut audio_rx, &mut backlog).await.is_none());
+    }
+
+    // Needs network access and DEEPGRAM_API_KEY; run with `cargo test -- --ignored`
+    #[tokio::test]
+    #[ignore]
     async fn test_connect_and_receive_turninfo_with_silence() {
         init_tracing();
         // Allow overriding URL via env; default to the new preview endpoint the app uses
@@ -494,52 +1026,165 @@ mod tests {
         );
     }
 
+    // Collects events and statuses of a client session where the test can wait for them
+    fn collect<T: Send + 'static>() -> (impl Fn(T) + Send + Sync + 'static, mpsc::UnboundedReceiver<T>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (move |item| drop(tx.send(item)), rx)
+    }
+
     #[tokio::test]
-    async fn test_stream_silence_until_response() {
+    async fn test_stream_against_mock_server() {
         init_tracing();
-        // Allow overriding URL via env; default to the new preview endpoint the app uses
-        let stt_url = std::env::var("STT_TEST_URL").unwrap_or_else(|_| STT_URL.to_string());
-        let sample_rate: u32 = 16_000;
+        let server = MockFluxServer::start(vec![vec![
+            Step::connected(),
+            Step::WaitForAudio(3_200),
+            Step::turn_info("StartOfTurn", 0, "hello"),
+            Step::turn_info("Update", 0, "hello world"),
+            Step::turn_info("EagerEndOfTurn", 0, "Hello world."),
+            Step::turn_info("TurnResumed", 0, "hello world and"),
+            Step::WaitForCloseStream,
+            // The final transcript comes after the audio ended
+            Step::sleep(Duration::from_millis(50)),
+            Step::turn_info("EndOfTurn", 0, "Hello world and more."),
+        ]])
+        .await
+        .unwrap();
 
-        let client = SttClient::new(&stt_url, sample_rate);
+        let client = SttClient::with_eot_thresholds(&server.url(), 16_000, Some(0.5), Some(0.7));
+        let (on_event, mut events) = collect();
+        let (audio_tx, handle) = client.connect_and_transcribe(on_event).await.unwrap();
 
-        // Flag flipped when we successfully deserialize a TurnInfo and invoke callback
-        let got_result = Arc::new(AtomicBool::new(false));
-        let got_result_clone = got_result.clone();
+        // 100 ms of 16 kHz audio in 20 ms chunks, each a different byte
+        let audio: Vec<Vec<u8>> = (0..5u8).map(|i| vec![i; 640]).collect();
+        for chunk in &audio {
+            audio_tx.send(chunk.clone()).await.unwrap();
+        }
+        drop(audio_tx);
+        handle.await.unwrap().unwrap();
 
-        let (audio_tx, _handle) = client
-            .connect_and_transcribe(move |_result| {
-                got_result_clone.store(true, Ordering::SeqCst);
-            })
-            .await
-            .expect("failed to connect to STT service");
+        let mut received = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            received.push(event);
+        }
+        let names: Vec<&str> = received.iter().map(SttEvent::name).collect();
+        assert_eq!(
+            names,
+            ["Connected", "StartOfTurn", "Update", "EagerEndOfTurn", "TurnResumed", "EndOfTurn", "Closed"]
+        );
+        assert_eq!(received[5].result().unwrap().transcript, "Hello world and more.");
 
-        // Stream silence continuously in 80 ms chunks at real-time rate until we get a result
-        let chunk_ms: u32 = 80;
-        let samples_per_chunk: usize = (sample_rate as usize * chunk_ms as usize) / 1000; // 1280 samples
-        let bytes_per_chunk: usize = samples_per_chunk * 2; // PCM16
-        let zeros = vec![0u8; bytes_per_chunk];
+        let connections = server.connections();
+        assert_eq!(connections.len(), 1);
+        let log = &connections[0];
+        assert!(log.path.contains("sample_rate=16000&encoding=linear16"), "{}", log.path);
+        assert!(log.path.ends_with("&eager_eot_threshold=0.5&eot_threshold=0.7"), "{}", log.path);
+        assert_eq!(log.audio, audio.concat());
+        assert!(log.close_stream);
+        assert!(log.failures.is_empty(), "{:?}", log.failures);
+    }
 
-        // Max stream duration to avoid hanging the test forever
-        let stream_deadline = tokio::time::Instant::now() + Duration::from_secs(10);
-        let audio_tx = Some(audio_tx);
-        loop {
-            if got_result.load(Ordering::SeqCst) {
-                break;
-            }
-            if tokio::time::Instant::now() > stream_deadline {
-                break;
-            }
+    #[tokio::test]
+    async fn test_reconnect_replays_buffered_audio() {
+        init_tracing();
+        let server = MockFluxServer::start(vec![
+            vec![Step::connected(), Step::WaitForAudio(3 * 640), Step::Drop],
+            vec![
+                Step::connected(),
+                Step::WaitForAudio(7 * 640),
+                Step::turn_info("EndOfTurn", 0, "after the drop"),
+                Step::WaitForCloseStream,
+            ],
+        ])
+        .await
+        .unwrap();
 
-            if let Some(tx) = &audio_tx {
-                tx.send(zeros.clone()).await.expect("audio send failed");
-            }
-            tokio::time::sleep(Duration::from_millis(chunk_ms as u64)).await;
+        let mut client = SttClient::new(&server.url(), 16_000);
+        let (on_status, mut statuses) = collect();
+        client.status_callback = Some(Arc::new(on_status));
+        let (on_event, mut events) = collect();
+        let (audio_tx, handle) = client.connect_and_transcribe(on_event).await.unwrap();
+        assert_eq!(statuses.recv().await, Some(ConnectionStatus::Connected));
+
+        let audio: Vec<Vec<u8>> = (0..10u8).map(|i| vec![i; 640]).collect();
+        for chunk in &audio[..3] {
+            audio_tx.send(chunk.clone()).await.unwrap();
         }
+        // The rest is captured while the client waits to reconnect
+        assert_eq!(statuses.recv().await, Some(ConnectionStatus::Reconnecting { attempt: 1 }));
+        for chunk in &audio[3..] {
+            audio_tx.send(chunk.clone()).await.unwrap();
+        }
+        assert_eq!(statuses.recv().await, Some(ConnectionStatus::Connected));
+        drop(audio_tx);
+        handle.await.unwrap().unwrap();
 
-        assert!(
-            got_result.load(Ordering::SeqCst),
-            "did not receive any TurnInfo within the allowed time"
-        );
+        let mut names = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            names.push(event.name());
+        }
+        assert_eq!(names, ["Connected", "Connected", "EndOfTurn", "Closed"]);
+
+        let connections = server.connections();
+        assert_eq!(connections.len(), 2);
+        assert_eq!(connections[0].audio, audio[..3].concat());
+        assert!(!connections[0].close_stream);
+        assert_eq!(connections[1].audio, audio[3..].concat());
+        assert!(connections[1].close_stream);
+        assert!(connections.iter().all(|log| log.failures.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn test_server_error_reconnects() {
+        init_tracing();
+        let server = MockFluxServer::start(vec![
+            vec![Step::connected(), Step::error("SLOW_DOWN", "try again")],
+            vec![Step::connected(), Step::WaitForCloseStream],
+        ])
+        .await
+        .unwrap();
+
+        let client = SttClient::new(&server.url(), 16_000);
+        let (on_event, mut events) = collect();
+        let (audio_tx, handle) = client.connect_and_transcribe(on_event).await.unwrap();
+
+        // Connected, Error, then Connected again once reconnected
+        for _ in 0..3 {
+            events.recv().await.unwrap();
+        }
+        drop(audio_tx);
+        handle.await.unwrap().unwrap();
+        assert!(matches!(events.recv().await, Some(SttEvent::Closed)));
+        assert_eq!(server.connections().len(), 2);
+        assert!(server.connections()[1].close_stream);
+    }
+
+    #[test]
+    fn test_finish_from_a_thread_outside_the_runtime() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let server = runtime
+            .block_on(MockFluxServer::start(vec![vec![
+                Step::connected(),
+                Step::WaitForCloseStream,
+                Step::turn_info("EndOfTurn", 0, "the end"),
+            ]]))
+            .unwrap();
+
+        // Like the STT thread, a plain thread that never entered the runtime
+        let handle = runtime.handle().clone();
+        let url = server.url();
+        let names = std::thread::spawn(move || {
+            let (tx, rx) = std::sync::mpsc::channel();
+            let on_event: EventSink = Arc::new(move |event: SttEvent| {
+                let _ = tx.send(event.name());
+            });
+            let mut client = SttClient::new(&url, 16_000);
+            let mut session = client.start_session(&handle, on_event, Arc::new(|_| {})).unwrap();
+            session.push_audio(&[0.0; 1_600]);
+            session.finish(Duration::from_secs(5));
+            rx.try_iter().collect::<Vec<_>>()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(names, ["Connected", "EndOfTurn", "Closed"]);
     }
 }
@@ -0,0 +1,290 @@
+use anyhow::{Context, Result};
+use regex::{Regex, RegexBuilder};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::code_mode::{CodeFormatter, CodeMode};
+
+/// A step that rewrites transcript text before it reaches the keyboard
+///
+/// Live mode passes the whole transcript of the turn on every update and only retypes what
+/// changed, so `transform` has to be a pure function of the text. Output for the start of a
+/// transcript should not change as more words arrive, or each update causes extra backspacing.
+pub trait TextTransformer: Send + Sync {
+    fn transform(&self, text: &str) -> String;
+}
+
+/// Stage names, in the order they run unless configured otherwise
+pub const DEFAULT_STAGES: &[&str] = &["spoken_punctuation", "numbers", "dictionary", "case", "whitespace"];
+
+/// All stage names, including the ones that are off by default
+pub const STAGES: &[&str] = &["spoken_punctuation", "numbers", "filler_words", "dictionary", "case", "whitespace"];
+
+/// An ordered list of transformers, applied one after the other
+///
+/// While code mode is on, the code formatter runs instead of the stages, which are meant
+/// for prose ("i" -> "I" would break `for i in`)
+#[derive(Clone, Default)]
+pub struct TextPipeline {
+    stages: Vec<Arc<dyn TextTransformer>>,
+    code: Option<(CodeMode, CodeFormatter)>,
+}
+
+impl TextPipeline {
+    pub fn push<T: TextTransformer + 'static>(&mut self, stage: T) {
+        self.stages.push(Arc::new(stage));
+    }
+
+    pub fn set_code_mode(&mut self, mode: CodeMode, formatter: CodeFormatter) {
+        self.code = Some((mode, formatter));
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        if let Some((mode, formatter)) = &self.code {
+            if mode.is_enabled() {
+                return formatter.transform(text);
+            }
+        }
+
+        self.stages
+            .iter()
+            .fold(text.to_string(), |text, stage| stage.transform(&text))
+    }
+}
+
+/// Regex replacements, e.g. `\bgit hub\b` -> "GitHub"; patterns ignore case unless they use `(?-i)`
+pub struct Dictionary {
+    entries: Vec<(Regex, String)>,
+}
+
+impl Dictionary {
+    pub fn new<'a>(entries: impl IntoIterator<Item = (&'a str, &'a str)>) -> Result<Self> {
+        let entries = entries
+            .into_iter()
+            .map(|(pattern, replacement)| {
+                let regex = RegexBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .context(format!("invalid dictionary pattern '{}'", pattern))?;
+                Ok((regex, replacement.to_string()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { entries })
+    }
+}
+
+impl TextTransformer for Dictionary {
+    fn transform(&self, text: &str) -> String {
+        self.entries
+            .iter()
+            .fold(text.to_string(), |text, (regex, replacement)| {
+                regex.replace_all(&text, replacement.as_str()).into_owned()
+            })
+    }
+}
+
+/// Fixed spellings for words the provider gets the case wrong on ("i" -> "I", "github" -> "GitHub")
+pub struct CaseFixes {
+    words: HashMap<String, String>,
+    word: Regex,
+}
+
+impl Default for CaseFixes {
+    fn default() -> Self {
+        let words = ["I", "I'm", "I've", "I'll", "I'd"]
+            .iter()
+            .map(|w| (w.to_lowercase(), w.to_string()))
+            .collect();
+        Self {
+            words,
+            word: Regex::new(r"[\w']+").unwrap(),
+        }
+    }
+}
+
+impl CaseFixes {
+    /// Add or override the spelling of a word; matching ignores case
+    pub fn set(&mut self, spelling: &str) {
+        self.words.insert(spelling.to_lowercase(), spelling.to_string());
+    }
+}
+
+impl TextTransformer for CaseFixes {
+    fn transform(&self, text: &str) -> String {
+        self.word
+            .replace_all(text, |caps: &regex::Captures| {
+                let word = &caps[0];
+                self.words
+                    .get(&word.to_lowercase())
+                    .cloned()
+                    .unwrap_or_else(|| word.to_string())
+            })
+            .into_owned()
+    }
+}
+
+/// Collapses runs of whitespace, trims the ends and removes spaces before closing punctuation
+#[derive(Default)]
+pub struct Whitespace;
+
+impl TextTransformer for Whitespace {
+    fn transform(&self, text: &str) -> String {
+        let mut output = String::with_capacity(text.len());
+        for word in text.split_whitespace() {
+            let attaches = word.starts_with([',', '.', ';', ':', '!', '?', ')', ']', '}']);
+            if !output.is_empty() && !attaches {
+                output.push(' ');
+            }
+            output.push_str(word);
+        }
+        output
+    }
+}
+
+/// Drops hesitation words ("um", "uh") along with a comma after them
+pub struct FillerWords {
+    words: Vec<String>,
+}
+
+impl Default for FillerWords {
+    fn default() -> Self {
+        Self::new(["um", "umm", "uh", "uhh", "uhm", "erm", "er", "hmm"])
+    }
+}
+
+impl FillerWords {
+    pub fn new<S: AsRef<str>>(words: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            words: words.into_iter().map(|w| w.as_ref().to_lowercase()).collect(),
+        }
+    }
+}
+
+impl TextTransformer for FillerWords {
+    fn transform(&self, text: &str) -> String {
+        let mut output = String::with_capacity(text.len());
+        for word in text.split_whitespace() {
+            let core = word.trim_matches(|c: char| !c.is_alphanumeric());
+            if !self.words.contains(&core.to_lowercase()) {
+                if !output.is_empty() {
+                    output.push(' ');
+                }
+                output.push_str(word);
+                continue;
+            }
+
+            // "so um." keeps the period on "so"; "um," just goes
+            let end = word.find(core).map_or(word.len(), |start| start + core.len());
+            let trailing = word[end..].trim_start_matches(',');
+            output.push_str(trailing);
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each word-by-word prefix must transform into a prefix of the full result
+    fn assert_incremental(stage: &dyn TextTransformer, text: &str) {
+        let full = stage.transform(text);
+        let words: Vec<&str> = text.split_whitespace().collect();
+        for n in 1..words.len() {
+            let partial = stage.transform(&words[..n].join(" "));
+            assert!(full.starts_with(&partial), "'{}' is not a prefix of '{}'", partial, full);
+        }
+    }
+
+    #[test]
+    fn test_dictionary() {
+        let dictionary = Dictionary::new([
+            (r"\bgit hub\b", "GitHub"),
+            (r"\bteh\b", "the"),
+            (r"(\d+) percent", "$1%"),
+        ])
+        .unwrap();
+
+        assert_eq!(dictionary.transform("push it to Git Hub"), "push it to GitHub");
+        assert_eq!(dictionary.transform("teh end"), "the end");
+        assert_eq!(dictionary.transform("up 5 percent"), "up 5%");
+        assert_eq!(dictionary.transform("a github repo"), "a github repo");
+        assert!(Dictionary::new([("(unclosed", "x")]).is_err());
+        assert_incremental(&dictionary, "teh code is 5 percent teh best");
+
+        // Patterns apply in the order given, each to the output of the one before
+        let forward = Dictionary::new([("teh", "the"), (r"\bthe\b", "a")]).unwrap();
+        assert_eq!(forward.transform("teh cat"), "a cat");
+        let backward = Dictionary::new([(r"\bthe\b", "a"), ("teh", "the")]).unwrap();
+        assert_eq!(backward.transform("teh cat"), "the cat");
+    }
+
+    #[test]
+    fn test_case_fixes() {
+        let mut case = CaseFixes::default();
+        case.set("GitHub");
+
+        assert_eq!(case.transform("i think i'm right"), "I think I'm right");
+        assert_eq!(case.transform("\"i\" said github."), "\"I\" said GitHub.");
+        assert_eq!(case.transform("it is in italic"), "it is in italic");
+        assert_incremental(&case, "so i said i'd push to github");
+    }
+
+    #[test]
+    fn test_whitespace() {
+        let test_cases = vec![
+            ("  hello   world  ", "hello world"),
+            ("hello , world .", "hello, world."),
+            ("(see below ) ok", "(see below) ok"),
+            ("tabs\tand\nnewlines", "tabs and newlines"),
+            ("", ""),
+        ];
+
+        for (input, expected) in test_cases {
+            assert_eq!(Whitespace.transform(input), expected, "For: '{}'", input);
+        }
+        assert_incremental(&Whitespace, "one ,  two   three .");
+    }
+
+    #[test]
+    fn test_filler_words() {
+        let fillers = FillerWords::default();
+        let test_cases = vec![
+            ("so um we should uh go", "so we should go"),
+            ("Um, I think so", "I think so"),
+            ("we are uh, done", "we are done"),
+            ("that's it um.", "that's it."),
+            ("the drummer hummed", "the drummer hummed"),
+            ("um", ""),
+        ];
+
+        for (input, expected) in test_cases {
+            assert_eq!(fillers.transform(input), expected, "For: '{}'", input);
+        }
+        assert_incremental(&fillers, "well um, I uh think hmm we are done um");
+    }
+
+    #[test]
+    fn test_pipeline_order() {
+        let mut pipeline = TextPipeline::default();
+        assert_eq!(pipeline.apply(" as is "), " as is ");
+
+        pipeline.push(FillerWords::default());
+        pipeline.push(Dictionary::new([(r"\bso\b", "i")]).unwrap());
+        pipeline.push(CaseFixes::default());
+        pipeline.push(Whitespace);
+        assert_eq!(pipeline.apply("um  so   uh , yes"), "I, yes");
+    }
+
+    #[test]
+    fn test_pipeline_code_mode() {
+        let mut pipeline = TextPipeline::default();
+        pipeline.push(CaseFixes::default());
+        let mode = CodeMode::default();
+        pipeline.set_code_mode(mode.clone(), CodeFormatter::default());
+
+        assert_eq!(pipeline.apply("for i in snake case all items"), "for I in snake case all items");
+        mode.set_enabled(true);
+        assert_eq!(pipeline.apply("for i in snake case all items"), "for i in all_items");
+    }
+}
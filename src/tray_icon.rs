@@ -5,10 +5,14 @@ use tray_icon::menu::{Menu, MenuEvent, MenuItem};
 use tray_icon::{Icon, TrayIcon, TrayIconBuilder};
 use tracing::{debug, info};
 
+use crate::stt_client::ConnectionStatus;
+
 pub struct TrayManager {
     tray_icon: TrayIcon,
     toggle_item: MenuItem,
     is_active: Arc<Mutex<bool>>,
+    connection_status: ConnectionStatus,
+    typing_error: Option<String>,
 }
 
 impl TrayManager {
@@ -22,7 +26,7 @@ impl TrayManager {
         menu.append(&quit_item)?;
 
         // Create initial icon (inactive state)
-        let icon = Self::create_icon(false)?;
+        let icon = Self::create_icon(false, false)?;
 
         let tray_icon = TrayIconBuilder::new()
             .with_menu(Box::new(menu))
@@ -36,10 +40,12 @@ impl TrayManager {
             tray_icon,
             toggle_item: toggle_item,
             is_active,
+            connection_status: ConnectionStatus::Disconnected,
+            typing_error: None,
         })
     }
 
-    fn create_icon(active: bool) -> Result<Icon> {
+    fn create_icon(active: bool, warning: bool) -> Result<Icon> {
         // Create a simple colored icon
         // 32x32 RGBA icon
         let size = 32;
@@ -56,7 +62,13 @@ impl TrayManager {
                 let radius = size as f32 / 2.0 - 2.0;
 
                 if distance <= radius {
-                    if active {
+                    if warning {
+                        // Amber while the connection is being restored or typing failed
+                        rgba[idx] = 230;     // R
+                        rgba[idx + 1] = 160; // G
+                        rgba[idx + 2] = 30;  // B
+                        rgba[idx + 3] = 255; // A
+                    } else if active {
                         // Green for active
                         rgba[idx] = 50;      // R
                         rgba[idx + 1] = 200; // G
@@ -81,11 +93,16 @@ impl TrayManager {
     }
 
     pub fn update_icon(&mut self, active: bool) -> Result<()> {
-        let icon = Self::create_icon(active)?;
-        let tooltip = if active {
-            "Voice Keyboard - Active"
-        } else {
-            "Voice Keyboard - Inactive"
+        // Turns are still typed after listening stops, so a typing error shows either way
+        let reconnecting = active && matches!(self.connection_status, ConnectionStatus::Reconnecting { .. });
+        let icon = Self::create_icon(active, reconnecting || self.typing_error.is_some())?;
+        let tooltip = match (&self.connection_status, &self.typing_error) {
+            (ConnectionStatus::Reconnecting { attempt }, _) if active => {
+                format!("Voice Keyboard - Reconnecting (attempt {})", attempt)
+            }
+            (_, Some(error)) => format!("Voice Keyboard - Typing failed: {}", error),
+            _ if active => "Voice Keyboard - Active".to_string(),
+            _ => "Voice Keyboard - Inactive".to_string(),
         };
 
         self.tray_icon.set_icon(Some(icon))?;
@@ -95,6 +112,20 @@ impl TrayManager {
         Ok(())
     }
 
+    /// Show the state of the speech-to-text connection
+    pub fn set_connection_status(&mut self, status: ConnectionStatus) -> Result<()> {
+        self.connection_status = status;
+        let active = *self.is_active.lock();
+        self.update_icon(active)
+    }
+
+    /// Show that typing a turn failed, until listening starts again
+    pub fn set_typing_error(&mut self, error: Option<String>) -> Result<()> {
+        self.typing_error = error;
+        let active = *self.is_active.lock();
+        self.update_icon(active)
+    }
+
     pub fn handle_events(&mut self) -> Result<bool> {
         if let Ok(event) = MenuEvent::receiver().try_recv() {
             if event.id == self.toggle_item.id() {
@@ -0,0 +1,179 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::input_event::*;
+
+/// Strategy used to type characters that have no direct keycode
+#[derive(Debug, Clone, Default)]
+pub enum UnicodeInput {
+    /// Skip characters without a keycode
+    Disabled,
+    /// GTK/IBus hex entry: Ctrl+Shift+U, hex digits, Space
+    #[default]
+    CtrlShiftU,
+    /// Compose key followed by a key sequence from the table
+    Compose(ComposeTable),
+}
+
+impl UnicodeInput {
+    /// Parse the `--unicode-input` value
+    pub fn from_name(name: &str, compose_table: ComposeTable) -> Result<Self> {
+        match name {
+            "none" => Ok(UnicodeInput::Disabled),
+            "ctrl-shift-u" | "ibus" => Ok(UnicodeInput::CtrlShiftU),
+            "compose" => Ok(UnicodeInput::Compose(compose_table)),
+            other => Err(anyhow!(
+                "invalid unicode input method '{}': must be 'ctrl-shift-u', 'compose' or 'none'",
+                other
+            )),
+        }
+    }
+
+    /// Whether a character without a direct keycode can still be typed
+    pub fn supports(&self, c: char) -> bool {
+        match self {
+            UnicodeInput::Disabled => false,
+            UnicodeInput::CtrlShiftU => true,
+            UnicodeInput::Compose(table) => table.sequence(c).is_some(),
+        }
+    }
+}
+
+/// Compose key plus the key sequences that follow it for each character
+#[derive(Debug, Clone)]
+pub struct ComposeTable {
+    compose_key: u16,
+    sequences: HashMap<char, String>,
+}
+
+// Default sequences from the standard X11 Compose file (en_US.UTF-8)
+const DEFAULT_SEQUENCES: &[(char, &str)] = &[
+    ('á', "'a"), ('é', "'e"), ('í', "'i"), ('ó', "'o"), ('ú', "'u"), ('ý', "'y"),
+    ('Á', "'A"), ('É', "'E"), ('Í', "'I"), ('Ó', "'O"), ('Ú', "'U"), ('Ý', "'Y"),
+    ('à', "`a"), ('è', "`e"), ('ì', "`i"), ('ò', "`o"), ('ù', "`u"),
+    ('À', "`A"), ('È', "`E"), ('Ì', "`I"), ('Ò', "`O"), ('Ù', "`U"),
+    ('â', "^a"), ('ê', "^e"), ('î', "^i"), ('ô', "^o"), ('û', "^u"),
+    ('Â', "^A"), ('Ê', "^E"), ('Î', "^I"), ('Ô', "^O"), ('Û', "^U"),
+    ('ä', "\"a"), ('ë', "\"e"), ('ï', "\"i"), ('ö', "\"o"), ('ü', "\"u"), ('ÿ', "\"y"),
+    ('Ä', "\"A"), ('Ë', "\"E"), ('Ï', "\"I"), ('Ö', "\"O"), ('Ü', "\"U"),
+    ('ã', "~a"), ('ñ', "~n"), ('õ', "~o"), ('Ã', "~A"), ('Ñ', "~N"), ('Õ', "~O"),
+    ('ç', ",c"), ('Ç', ",C"), ('å', "oa"), ('Å', "OA"), ('ø', "/o"), ('Ø', "/O"),
+    ('æ', "ae"), ('Æ', "AE"), ('œ', "oe"), ('Œ', "OE"), ('ß', "ss"),
+    ('—', "---"), ('–', "--."), ('…', ".."),
+    ('‘', "<'"), ('’', ">'"), ('“', "<\""), ('”', ">\""), ('«', "<<"), ('»', ">>"),
+    ('€', "=e"), ('£', "-L"), ('¥', "=Y"), ('¢', "|c"),
+    ('°', "oo"), ('±', "+-"), ('×', "xx"), ('÷', ":-"),
+    ('©', "oc"), ('®', "or"), ('™', "tm"), ('¿', "??"), ('¡', "!!"),
+];
+
+impl Default for ComposeTable {
+    fn default() -> Self {
+        Self {
+            compose_key: KEY_RIGHTALT,
+            sequences: DEFAULT_SEQUENCES
+                .iter()
+                .map(|&(c, seq)| (c, seq.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl ComposeTable {
+    /// Parse a compose key name such as "ralt" or "menu"
+    pub fn key_from_name(name: &str) -> Result<u16> {
+        match name {
+            "ralt" => Ok(KEY_RIGHTALT),
+            "rctrl" => Ok(KEY_RIGHTCTRL),
+            "menu" => Ok(KEY_COMPOSE),
+            "caps" => Ok(KEY_CAPSLOCK),
+            "lwin" => Ok(KEY_LEFTMETA),
+            "rwin" => Ok(KEY_RIGHTMETA),
+            "scrolllock" => Ok(KEY_SCROLLLOCK),
+            other => Err(anyhow!(
+                "invalid compose key '{}': must be one of ralt, rctrl, menu, caps, lwin, rwin, scrolllock",
+                other
+            )),
+        }
+    }
+
+    pub fn set_compose_key(&mut self, keycode: u16) {
+        self.compose_key = keycode;
+    }
+
+    pub fn compose_key(&self) -> u16 {
+        self.compose_key
+    }
+
+    pub fn sequence(&self, c: char) -> Option<&str> {
+        self.sequences.get(&c).map(|s| s.as_str())
+    }
+
+    /// Merge sequences from a file, overriding the defaults
+    /// Each non-empty line is `<char> <keys>`, e.g. `é 'e`; lines starting with '#' are comments
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .context(format!("Failed to read compose table {:?}", path))?;
+        self.parse(&contents)
+            .context(format!("Invalid compose table {:?}", path))
+    }
+
+    fn parse(&mut self, contents: &str) -> Result<()> {
+        for (line_no, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, char::is_whitespace);
+            let target = parts.next().unwrap_or_default();
+            let keys = parts.next().unwrap_or_default().trim();
+
+            let mut target_chars = target.chars();
+            let (Some(c), None) = (target_chars.next(), target_chars.next()) else {
+                return Err(anyhow!("line {}: expected a single character, got '{}'", line_no + 1, target));
+            };
+            if keys.is_empty() {
+                return Err(anyhow!("line {}: missing key sequence for '{}'", line_no + 1, c));
+            }
+
+            self.sequences.insert(c, keys.to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compose_table_parse_overrides_defaults() {
+        let mut table = ComposeTable::default();
+        assert_eq!(table.sequence('é'), Some("'e"));
+
+        table
+            .parse("# custom entries\n\né e'\nł /l\n")
+            .unwrap();
+        assert_eq!(table.sequence('é'), Some("e'"));
+        assert_eq!(table.sequence('ł'), Some("/l"));
+        assert_eq!(table.sequence('—'), Some("---"));
+    }
+
+    #[test]
+    fn test_compose_table_parse_errors() {
+        let mut table = ComposeTable::default();
+        assert!(table.parse("ab 'a").is_err());
+        assert!(table.parse("é").is_err());
+    }
+
+    #[test]
+    fn test_unicode_input_supports() {
+        assert!(!UnicodeInput::Disabled.supports('é'));
+        assert!(UnicodeInput::CtrlShiftU.supports('🙂'));
+        let compose = UnicodeInput::Compose(ComposeTable::default());
+        assert!(compose.supports('é'));
+        assert!(!compose.supports('🙂'));
+    }
+}
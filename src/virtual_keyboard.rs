@@ -4,12 +4,19 @@ use anyhow::{Context, Result};
 use nix::fcntl::{open, OFlag};
 use nix::sys::stat::Mode;
 use nix::unistd::close;
-use regex::Regex;
+use std::collections::{BTreeMap, VecDeque};
 use std::io::Write;
 use std::os::unix::io::FromRawFd;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::time::{Duration, Instant};
 use tracing::{debug, error, info, warn};
 
+use crate::clipboard::{Clipboard, ClipboardContents, PasteShortcut};
+use crate::code_mode::CodeMode;
 use crate::input_event::*;
+use crate::keyboard_layout::{KeyStroke, KeyboardLayout};
+use crate::unicode_input::UnicodeInput;
+use crate::voice_commands::{CommandAction, CommandMatch, KeyChord, VoiceCommands};
 
 // Define ioctl macros for uinput
 // The nix ioctl_write_int! macro requires the ioctl type and number
@@ -18,18 +25,133 @@ nix::ioctl_write_int!(ui_set_keybit, b'U', 101);
 nix::ioctl_none!(ui_dev_create, b'U', 1);
 nix::ioctl_none!(ui_dev_destroy, b'U', 2);
 
+/// Keys a uinput device holds down, and its fd, so they can be released from a signal
+/// handler or panic hook where the device itself is out of reach
+struct HeldKeys {
+    fd: AtomicI32,
+    down: [AtomicBool; 256],
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const KEY_UP: AtomicBool = AtomicBool::new(false);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_DEVICE: HeldKeys = HeldKeys {
+    fd: AtomicI32::new(-1),
+    down: [KEY_UP; 256],
+};
+
+// Devices open in this process: the typing keyboard, and the passthrough device of
+// grabbed hotkey keyboards
+const MAX_DEVICES: usize = 4;
+
+static DEVICES: [HeldKeys; MAX_DEVICES] = [NO_DEVICE; MAX_DEVICES];
+
+impl HeldKeys {
+    /// Take a free slot of `DEVICES` for the device with this fd
+    fn register(fd: i32) -> Option<&'static HeldKeys> {
+        let held = DEVICES
+            .iter()
+            .find(|held| held.fd.compare_exchange(-1, fd, Ordering::SeqCst, Ordering::SeqCst).is_ok())?;
+        for key in &held.down {
+            key.store(false, Ordering::SeqCst);
+        }
+        Some(held)
+    }
+
+    /// Release the keys still down and free the slot
+    fn unregister(&self) {
+        self.release_all();
+        self.fd.store(-1, Ordering::SeqCst);
+    }
+
+    fn set(&self, keycode: u16, down: bool) {
+        if let Some(key) = self.down.get(keycode as usize) {
+            key.store(down, Ordering::SeqCst);
+        }
+    }
+
+    /// Send a release for every held key; only uses async-signal-safe calls
+    fn release_all(&self) {
+        let fd = self.fd.load(Ordering::SeqCst);
+        if fd < 0 {
+            return;
+        }
+        for (keycode, key) in self.down.iter().enumerate() {
+            if !key.load(Ordering::SeqCst) {
+                continue;
+            }
+            // No timestamp: gettimeofday is not safe in a signal handler, and uinput
+            // stamps events itself
+            let zero = libc::timeval { tv_sec: 0, tv_usec: 0 };
+            let release = InputEvent { time: zero, type_: EV_KEY, code: keycode as u16, value: 0 };
+            let sync = InputEvent { time: zero, type_: EV_SYN, code: SYN_REPORT, value: 0 };
+            if write_event(fd, &release) && write_event(fd, &sync) {
+                key.store(false, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+/// Write one event to a uinput fd, returning whether all of it was written
+fn write_event(fd: i32, event: &InputEvent) -> bool {
+    let size = std::mem::size_of::<InputEvent>();
+    let written = unsafe { libc::write(fd, event as *const InputEvent as *const libc::c_void, size) };
+    written == size as isize
+}
+
+/// Release every key the virtual keyboards hold down; safe to call from a signal handler
+pub fn release_held_keys() {
+    for held in &DEVICES {
+        held.release_all();
+    }
+}
+
+/// Release held keys when the process panics, so that no modifier stays stuck; `Drop`
+/// does not run then, as the release profile aborts on panic
+pub fn release_keys_on_exit() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        release_held_keys();
+        previous(info);
+    }));
+}
+
 /// Hardware abstraction trait for keyboard operations
 pub trait KeyboardHardware {
     fn type_text(&mut self, text: &str) -> Result<()>;
     fn press_backspace(&mut self) -> Result<()>;
     fn press_enter(&mut self) -> Result<()>;
     fn press_key(&mut self, keycode: u16) -> Result<()>;
+    /// Press a key while holding the given modifiers, keys given by US position
+    fn press_chord(&mut self, modifiers: &[u16], keycode: u16) -> Result<()>;
+
+    /// Insert text in one go (e.g. through the clipboard); falls back to typing it
+    fn paste_text(&mut self, text: &str) -> Result<()> {
+        self.type_text(text)
+    }
+
+    /// Whether `type_text` produces this character on screen (unsupported ones are skipped)
+    fn supports_char(&self, _c: char) -> bool {
+        true
+    }
+
+    /// Key presses and releases needed to type this character, for choosing the cheaper
+    /// of two ways to edit text
+    fn key_events(&self, _c: char) -> usize {
+        2
+    }
 }
 
 /// Real hardware implementation using Linux uinput
 pub struct RealKeyboardHardware {
     fd: i32,
+    held: &'static HeldKeys,
     name: String,
+    layout: KeyboardLayout,
+    unicode_input: UnicodeInput,
+    clipboard: Option<Clipboard>,
+    paste_shortcut: PasteShortcut,
 }
 
 impl RealKeyboardHardware {
@@ -96,13 +218,36 @@ impl RealKeyboardHardware {
         }
 
         info!("Virtual keyboard '{}' created successfully", device_name);
+        let held = HeldKeys::register(fd).context("Too many virtual keyboards")?;
 
         Ok(Self {
             fd,
+            held,
             name: device_name.to_string(),
+            layout: KeyboardLayout::default(),
+            unicode_input: UnicodeInput::default(),
+            clipboard: None,
+            paste_shortcut: PasteShortcut::default(),
         })
     }
 
+    /// Enable clipboard pasting for `paste_text`
+    pub fn set_clipboard(&mut self, clipboard: Clipboard, paste_shortcut: PasteShortcut) {
+        self.clipboard = Some(clipboard);
+        self.paste_shortcut = paste_shortcut;
+    }
+
+    /// Set the keyboard layout used to map characters to keycodes
+    pub fn set_layout(&mut self, layout: KeyboardLayout) {
+        info!("Using keyboard layout '{}'", layout.name());
+        self.layout = layout;
+    }
+
+    /// Set how characters without a direct keycode are typed
+    pub fn set_unicode_input(&mut self, unicode_input: UnicodeInput) {
+        self.unicode_input = unicode_input;
+    }
+
     fn send_event(&self, event: InputEvent) -> Result<()> {
         let event_bytes = unsafe {
             std::slice::from_raw_parts(
@@ -130,17 +275,111 @@ impl RealKeyboardHardware {
         Ok(())
     }
 
+    /// Re-emit an event read from another device, e.g. from a grabbed keyboard
+    pub fn forward_event(&self, event: InputEvent) -> Result<()> {
+        self.send_event(event)?;
+        // Keys the user holds are held on this device too (value 2 is autorepeat)
+        if event.type_ == EV_KEY {
+            self.held.set(event.code, event.value != 0);
+        }
+        Ok(())
+    }
+
     fn send_key(&self, keycode: u16, pressed: bool) -> Result<()> {
         debug!("Sending key: {} (pressed: {})", keycode, pressed);
 
         // Send key event
         let key_event = InputEvent::key_event(keycode, pressed);
-        self.send_event(key_event)?;
+        let result = self
+            .send_event(key_event)
+            .and_then(|_| self.send_event(InputEvent::syn_event()));
+
+        match result {
+            Ok(()) => {
+                self.held.set(keycode, pressed);
+                Ok(())
+            }
+            Err(e) => {
+                // Don't leave the modifiers of a half-typed character held down
+                self.held.release_all();
+                Err(e)
+            }
+        }
+    }
+
+    /// Type a character that has a direct keycode, holding shift/AltGr if needed
+    fn type_keystroke(&mut self, stroke: KeyStroke) -> Result<()> {
+        if !stroke.shift && !stroke.altgr {
+            // Just press the key
+            return self.press_key(stroke.keycode);
+        }
+
+        // Press modifiers
+        if stroke.altgr {
+            self.send_key(KEY_RIGHTALT, true)?;
+        }
+        if stroke.shift {
+            self.send_key(KEY_LEFTSHIFT, true)?;
+        }
+
+        // Press the key
+        self.send_key(stroke.keycode, true)?;
+        self.send_key(stroke.keycode, false)?;
+
+        // Release modifiers
+        if stroke.shift {
+            self.send_key(KEY_LEFTSHIFT, false)?;
+        }
+        if stroke.altgr {
+            self.send_key(KEY_RIGHTALT, false)?;
+        }
+        Ok(())
+    }
 
-        // Send synchronization event
-        let syn_event = InputEvent::syn_event();
-        self.send_event(syn_event)?;
+    /// Type a character without a direct keycode using the configured unicode input method
+    fn type_unicode_char(&mut self, c: char) -> Result<()> {
+        match &self.unicode_input {
+            UnicodeInput::Disabled => {
+                warn!("Unsupported character: '{}'", c);
+            }
+            UnicodeInput::CtrlShiftU => {
+                debug!("Typing '{}' as U+{:04X} via Ctrl+Shift+U", c, c as u32);
+
+                // Start hex entry with Ctrl+Shift+U (the U key moves on e.g. Dvorak)
+                let u_key = self.layout.lookup('u').map_or(KEY_U, |k| k.keycode);
+                self.send_key(KEY_LEFTCTRL, true)?;
+                self.send_key(KEY_LEFTSHIFT, true)?;
+                self.send_key(u_key, true)?;
+                self.send_key(u_key, false)?;
+                self.send_key(KEY_LEFTSHIFT, false)?;
+                self.send_key(KEY_LEFTCTRL, false)?;
+                std::thread::sleep(std::time::Duration::from_millis(10));
 
+                // Type the code point, then commit it with space
+                for digit in format!("{:x}", c as u32).chars() {
+                    if let Some(stroke) = self.layout.lookup(digit) {
+                        self.type_keystroke(stroke)?;
+                    }
+                }
+                self.press_key(KEY_SPACE)?;
+            }
+            UnicodeInput::Compose(table) => {
+                let Some(sequence) = table.sequence(c).map(|s| s.to_string()) else {
+                    warn!("Unsupported character (no compose sequence): '{}'", c);
+                    return Ok(());
+                };
+                debug!("Typing '{}' via compose sequence '{}'", c, sequence);
+
+                self.press_key(table.compose_key())?;
+                for key in sequence.chars() {
+                    if let Some(stroke) = self.layout.lookup(key) {
+                        self.type_keystroke(stroke)?;
+                    } else {
+                        warn!("Compose sequence for '{}' contains untypeable key '{}'", c, key);
+                    }
+                }
+            }
+        }
         Ok(())
     }
 }
@@ -150,27 +389,14 @@ impl KeyboardHardware for RealKeyboardHardware {
         debug!("Typing text: '{}'", text);
 
         for c in text.chars() {
-            if let Some((keycode, needs_shift)) = char_to_keycode(c) {
-                if needs_shift {
-                    // Press shift
-                    self.send_key(KEY_LEFTSHIFT, true)?;
-
-                    // Press the key
-                    self.send_key(keycode, true)?;
-                    self.send_key(keycode, false)?;
-
-                    // Release shift
-                    self.send_key(KEY_LEFTSHIFT, false)?;
-                } else {
-                    // Just press the key
-                    self.press_key(keycode)?;
-                }
-
-                // Small delay between characters for more natural typing
-                std::thread::sleep(std::time::Duration::from_millis(10));
+            if let Some(stroke) = self.layout.lookup(c) {
+                self.type_keystroke(stroke)?;
             } else {
-                warn!("Unsupported character: '{}'", c);
+                self.type_unicode_char(c)?;
             }
+
+            // Small delay between characters for more natural typing
+            std::thread::sleep(std::time::Duration::from_millis(10));
         }
 
         Ok(())
@@ -189,12 +415,86 @@ impl KeyboardHardware for RealKeyboardHardware {
         self.send_key(keycode, false)?;
         Ok(())
     }
+
+    fn press_chord(&mut self, modifiers: &[u16], keycode: u16) -> Result<()> {
+        let keycode = self.layout.shortcut_keycode(keycode);
+        for &modifier in modifiers {
+            self.send_key(modifier, true)?;
+        }
+        self.press_key(keycode)?;
+        for &modifier in modifiers.iter().rev() {
+            self.send_key(modifier, false)?;
+        }
+        Ok(())
+    }
+
+    fn paste_text(&mut self, text: &str) -> Result<()> {
+        let Some(clipboard) = self.clipboard.clone() else {
+            return self.type_text(text);
+        };
+        debug!("Pasting text: '{}'", text);
+
+        // Keep the user's clipboard so it can be restored afterwards
+        let previous = match clipboard.read() {
+            Ok(ClipboardContents::Other) => {
+                warn!("Clipboard holds an image or files that can't be restored after pasting, typing instead");
+                return self.type_text(text);
+            }
+            Ok(previous) => previous,
+            Err(e) => {
+                warn!("Failed to read clipboard, typing instead: {}", e);
+                return self.type_text(text);
+            }
+        };
+        if let Err(e) = clipboard.write(text.as_bytes()) {
+            warn!("Failed to set clipboard, typing instead: {}", e);
+            return self.type_text(text);
+        }
+        // Give the clipboard owner a moment to take over the selection
+        std::thread::sleep(std::time::Duration::from_millis(50));
+
+        let (modifiers, key) = self.paste_shortcut.keys();
+        self.press_chord(modifiers, key)?;
+
+        // The target application reads the clipboard asynchronously after the shortcut
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        let restored = match previous {
+            ClipboardContents::Text(previous) => clipboard.write(&previous),
+            _ => clipboard.clear(),
+        };
+        if let Err(e) = restored {
+            warn!("Failed to restore previous clipboard contents: {}", e);
+        }
+
+        Ok(())
+    }
+
+    fn supports_char(&self, c: char) -> bool {
+        self.layout.lookup(c).is_some() || self.unicode_input.supports(c)
+    }
+
+    fn key_events(&self, c: char) -> usize {
+        let stroke_events = |stroke: KeyStroke| 2 + 2 * (stroke.shift as usize + stroke.altgr as usize);
+        if let Some(stroke) = self.layout.lookup(c) {
+            return stroke_events(stroke);
+        }
+        let keys = |text: &str| -> usize { text.chars().filter_map(|k| self.layout.lookup(k)).map(stroke_events).sum() };
+        match &self.unicode_input {
+            UnicodeInput::Disabled => 0,
+            // Ctrl+Shift+U, the hex digits, Space
+            UnicodeInput::CtrlShiftU => 6 + keys(&format!("{:x}", c as u32)) + 2,
+            UnicodeInput::Compose(table) => table.sequence(c).map_or(0, |sequence| 2 + keys(sequence)),
+        }
+    }
 }
 
 impl Drop for RealKeyboardHardware {
     fn drop(&mut self) {
         info!("Destroying virtual keyboard '{}'", self.name);
 
+        // Keys still down when the device goes away may stay stuck in the compositor
+        self.held.unregister();
+
         // Destroy the device
         unsafe {
             if let Err(e) = ui_dev_destroy(self.fd) {
@@ -216,12 +516,221 @@ impl Drop for RealKeyboardHardware {
 unsafe impl Send for RealKeyboardHardware {}
 unsafe impl Sync for RealKeyboardHardware {}
 
-/// Business logic layer that handles transcript processing and enter command detection
+/// How finalized turns are emitted in delay mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+    /// Type each character
+    #[default]
+    Type,
+    /// Paste the whole turn through the clipboard
+    Paste,
+}
+
+impl OutputMode {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "type" => Ok(OutputMode::Type),
+            "paste" => Ok(OutputMode::Paste),
+            other => Err(anyhow::anyhow!(
+                "invalid output mode '{}': must be 'type' or 'paste'",
+                other
+            )),
+        }
+    }
+}
+
+/// What is typed after a turn that doesn't end with a voice command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TurnSeparator {
+    /// A space, unless the turn already ended with whitespace such as a newline
+    #[default]
+    Auto,
+    /// Always a space
+    Space,
+    /// Turns are typed back to back
+    None,
+}
+
+impl TurnSeparator {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "auto" => Ok(TurnSeparator::Auto),
+            "space" => Ok(TurnSeparator::Space),
+            "none" => Ok(TurnSeparator::None),
+            other => Err(anyhow::anyhow!(
+                "invalid turn separator '{}': must be 'auto', 'space' or 'none'",
+                other
+            )),
+        }
+    }
+}
+
+/// When live mode types a word of a partial transcript
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StabilityPolicy {
+    /// Consecutive updates a word must stay the same before it is typed; 1 types it at once
+    pub min_updates: usize,
+    /// Words the provider is at least this confident about are typed at once
+    pub min_confidence: Option<f64>,
+}
+
+impl Default for StabilityPolicy {
+    fn default() -> Self {
+        Self {
+            min_updates: 1,
+            min_confidence: None,
+        }
+    }
+}
+
+// Number of finalized turns kept for "scratch that"
+const MAX_HISTORY: usize = 20;
+
+// Characters of emitted text remembered to decide how the next turn starts
+const TAIL_LEN: usize = 16;
+
+/// The end of the text emitted so far, as far as we know it
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Tail {
+    /// Nothing emitted yet, or keys moved the cursor somewhere we can't follow
+    Unknown,
+    Text(String),
+}
+
+impl Tail {
+    fn push(&mut self, text: &str) {
+        let mut tail = match std::mem::replace(self, Tail::Unknown) {
+            Tail::Unknown => String::new(),
+            Tail::Text(tail) => tail,
+        };
+        tail.push_str(text);
+        let excess = tail.chars().count().saturating_sub(TAIL_LEN);
+        if let Some((i, _)) = tail.char_indices().nth(excess) {
+            tail.drain(..i);
+        }
+        if !tail.is_empty() {
+            *self = Tail::Text(tail);
+        }
+    }
+
+    /// A backspace; once the remembered text is used up the tail is unknown again
+    fn pop(&mut self) {
+        if let Tail::Text(tail) = self {
+            tail.pop();
+            if tail.is_empty() {
+                *self = Tail::Unknown;
+            }
+        }
+    }
+}
+
+/// What a turn put on screen, so that it can be taken back
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TurnRecord {
+    /// Characters the turn left on screen, including newlines, tabs and the separator
+    pub chars: usize,
+    /// Keys pressed by voice commands, plus backspaces that reached into earlier text
+    pub keys: Vec<KeyChord>,
+}
+
+impl TurnRecord {
+    fn is_empty(&self) -> bool {
+        self.chars == 0 && self.keys.is_empty()
+    }
+
+    /// Backspacing only reverts text, newlines and tabs; shortcuts, cursor
+    /// movement and deleted earlier text cannot be taken back
+    fn can_undo(&self) -> bool {
+        self.keys
+            .iter()
+            .all(|k| k.modifiers.is_empty() && matches!(k.key, KEY_ENTER | KEY_TAB))
+    }
+}
+
+/// A turn finalized early on an eager end of turn; what it typed is provisional until the
+/// turn really ends, since the speaker may carry on or the final transcript may differ
+#[derive(Debug)]
+struct ProvisionalTurn {
+    /// Transcript the turn was finalized with
+    transcript: String,
+    /// The text it left on screen before the separator; None if a command ran, in which
+    /// case its output stands and only later words are typed
+    typed: Option<String>,
+    /// Tail before the turn started
+    start: Tail,
+    separator: bool,
+    record: TurnRecord,
+}
+
+/// An event of one of the provider's turns
+#[derive(Debug)]
+pub enum TurnEvent {
+    /// The post-processed transcript of the turn so far, and the part of it the provider
+    /// is confident about (empty unless a confidence level is configured)
+    Update { transcript: String, confident: String },
+    EagerEndOfTurn,
+    TurnResumed,
+    EndOfTurn,
+}
+
+// How long a turn may go without events before a later turn is typed anyway
+const TURN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Keeps the provider's turns in order: events for finished turns are dropped, and a later
+/// turn is held back until the one being typed ends or times out
+struct TurnOrder {
+    open: Option<u32>,
+    finished: Option<u32>,  // Last turn that ended
+    held: BTreeMap<u32, Vec<TurnEvent>>,  // Events of later turns, by turn index
+    last_event: Instant,  // Last event of the open turn
+    timeout: Duration,
+}
+
+impl Default for TurnOrder {
+    fn default() -> Self {
+        Self {
+            open: None,
+            finished: None,
+            held: BTreeMap::new(),
+            last_event: Instant::now(),
+            timeout: TURN_TIMEOUT,
+        }
+    }
+}
+
+impl TurnOrder {
+    /// Keep an event of a later turn; updates replace the one before them
+    fn hold(&mut self, turn_index: u32, event: TurnEvent) {
+        let events = self.held.entry(turn_index).or_default();
+        if matches!(event, TurnEvent::Update { .. }) && matches!(events.last(), Some(TurnEvent::Update { .. })) {
+            events.pop();
+        }
+        events.push(event);
+    }
+}
+
+/// Business logic layer that handles transcript processing and voice command detection
 pub struct VirtualKeyboard<H: KeyboardHardware> {
     hardware: H,
     current_text: String,
     delay_input: bool,
-    eager_eot_finalized: bool,  // Track if we've finalized due to eager EOT
+    output_mode: OutputMode,
+    commands: VoiceCommands,
+    consumed_words: usize,  // Words of this turn already handled by mid-turn commands
+    turn: TurnRecord,  // Output of the turn in progress
+    history: VecDeque<TurnRecord>,  // Finalized turns, oldest first
+    code_mode: CodeMode,  // Switched by the code mode voice commands
+    separator: TurnSeparator,
+    sentence_case: bool,  // Fit the first letter of a turn to the text before it
+    tail: Tail,  // End of the emitted text, across turns
+    segment_start: Option<Tail>,  // Tail when the live text of this turn started
+    stability: StabilityPolicy,
+    word_counts: Vec<(String, usize)>,  // Words of the last update and for how many updates each stayed the same
+    stable_words: usize,  // Leading words of the last update that passed the stability policy
+    pending_transcript: String,  // Latest transcript of the turn if some of its words are held back
+    transcript: String,  // Latest transcript of the turn in live mode
+    provisional: Option<ProvisionalTurn>,  // Turn finalized on an eager end of turn
+    turns: TurnOrder,
 }
 
 impl<H: KeyboardHardware> VirtualKeyboard<H> {
@@ -230,14 +739,72 @@ impl<H: KeyboardHardware> VirtualKeyboard<H> {
             hardware,
             current_text: String::new(),
             delay_input,
-            eager_eot_finalized: false,
+            output_mode: OutputMode::default(),
+            commands: VoiceCommands::default(),
+            consumed_words: 0,
+            turn: TurnRecord::default(),
+            history: VecDeque::new(),
+            code_mode: CodeMode::default(),
+            separator: TurnSeparator::default(),
+            sentence_case: true,
+            tail: Tail::Unknown,
+            segment_start: None,
+            stability: StabilityPolicy::default(),
+            word_counts: Vec::new(),
+            stable_words: 0,
+            pending_transcript: String::new(),
+            transcript: String::new(),
+            provisional: None,
+            turns: TurnOrder::default(),
         }
     }
 
+    /// Set the spoken command table
+    pub fn set_commands(&mut self, commands: VoiceCommands) {
+        self.commands = commands;
+    }
+
+    /// Share the code mode switch with the text pipeline, so voice commands can flip it
+    pub fn set_code_mode(&mut self, code_mode: CodeMode) {
+        self.code_mode = code_mode;
+    }
+
+    /// Set which words of a partial transcript live mode types right away
+    pub fn set_stability(&mut self, stability: StabilityPolicy) {
+        self.stability = stability;
+    }
+
+    pub fn stability(&self) -> StabilityPolicy {
+        self.stability
+    }
+
+    /// Set what is typed after each turn
+    pub fn set_turn_separator(&mut self, separator: TurnSeparator) {
+        self.separator = separator;
+    }
+
+    /// Whether a turn starts with a capital after a sentence end, and without one
+    /// mid-sentence if its first word is a common word; otherwise the provider's
+    /// capitalization is kept
+    pub fn set_sentence_case(&mut self, sentence_case: bool) {
+        self.sentence_case = sentence_case;
+    }
+
+    /// Set how finalized turns are emitted in delay mode (live mode always types)
+    pub fn set_output_mode(&mut self, output_mode: OutputMode) {
+        self.output_mode = output_mode;
+    }
+
     /// Update the transcript incrementally, handling smart backspacing
     /// 1. Type new characters if the new transcript extends the current one
     /// 2. Only backspace the characters that actually changed, then type the new ending
     pub fn update_transcript(&mut self, new_transcript: &str) -> Result<()> {
+        self.update_transcript_with_confidence(new_transcript, "")
+    }
+
+    /// Like `update_transcript`, but in live mode only the words that pass the stability
+    /// policy are typed; `confident` is the start of the transcript the provider is sure of
+    pub fn update_transcript_with_confidence(&mut self, new_transcript: &str, confident: &str) -> Result<()> {
         // Trim whitespace from the new transcript
         let new_transcript = new_transcript.trim();
         
@@ -252,6 +819,115 @@ impl<H: KeyboardHardware> VirtualKeyboard<H> {
             return Ok(());
         }
 
+        // After an eager end of turn, a different transcript means the turn goes on
+        if let Some(provisional) = self.provisional.take() {
+            if provisional.transcript == new_transcript {
+                self.provisional = Some(provisional);
+                return Ok(());
+            }
+            self.reopen_live(provisional)?;
+        }
+        self.transcript = new_transcript.to_string();
+
+        // Hold back words that may still change; they are typed at the end of the turn
+        let stable = self.stable_prefix(new_transcript, confident);
+        self.pending_transcript.clear();
+        if stable.len() < new_transcript.len() {
+            debug!("Holding back '{}' until it is stable", &new_transcript[stable.len()..]);
+            self.pending_transcript = new_transcript.to_string();
+        }
+        self.type_transcript(stable)
+    }
+
+    /// The leading words of `transcript` that have stayed the same for enough updates,
+    /// or that are part of the `confident` text. Words that were stable before stay
+    /// stable while the transcript keeps them, so typed words are only retracted when
+    /// the transcript changes them
+    fn stable_prefix<'t>(&mut self, transcript: &'t str, confident: &str) -> &'t str {
+        let spans = word_spans(transcript);
+        let confident: Vec<&str> = confident.split_whitespace().collect();
+
+        let mut unchanged = true;
+        let counts: Vec<(String, usize, bool)> = spans
+            .iter()
+            .enumerate()
+            .map(|(i, &(start, end))| {
+                let word = &transcript[start..end];
+                unchanged = unchanged && self.word_counts.get(i).is_some_and(|(w, _)| w == word);
+                let count = if unchanged { self.word_counts[i].1 + 1 } else { 1 };
+                (word.to_string(), count, unchanged && i < self.stable_words)
+            })
+            .collect();
+
+        let stable = counts
+            .iter()
+            .enumerate()
+            .take_while(|&(i, (word, count, was_stable))| {
+                *was_stable || *count >= self.stability.min_updates || confident.get(i) == Some(&word.as_str())
+            })
+            .count();
+        self.word_counts = counts.into_iter().map(|(word, count, _)| (word, count)).collect();
+        self.stable_words = stable;
+        match stable {
+            0 => "",
+            n => &transcript[..spans[n - 1].1],
+        }
+    }
+
+    /// Type the transcript, running mid-turn commands
+    fn type_transcript(&mut self, new_transcript: &str) -> Result<()> {
+        // Words before an already executed mid-turn command are committed
+        let mut remaining = skip_words(new_transcript, self.consumed_words).to_string();
+
+        // Run commands that are followed by more speech, typing the text before each
+        if self.commands.mid_turn() {
+            while let Some(m) = self.commands.match_inner(&remaining) {
+                debug!("Found mid-turn command: '{}'", &remaining[m.start..m.end]);
+                let before = self.start_segment(&remaining[..m.start]);
+                self.type_update(&before)?;
+                self.run_command(&m)?;
+
+                self.consumed_words += remaining[..m.end].split_whitespace().count();
+                self.current_text.clear();
+                self.segment_start = None;
+                remaining = remaining[m.end..].to_string();
+            }
+        }
+
+        let text = self.start_segment(&remaining);
+        self.type_update(&text)
+    }
+
+    /// Live text with the case it gets at the position where it started typing
+    fn start_segment(&mut self, text: &str) -> String {
+        let tail = self.segment_start.get_or_insert_with(|| self.tail.clone()).clone();
+        self.fit_to(&tail, text)
+    }
+
+    /// `text` with its first letter upper- or lowercased to continue the text in `tail`
+    fn fit_to(&self, tail: &Tail, text: &str) -> String {
+        let text = text.trim_start();
+        match tail {
+            Tail::Text(before) if self.sentence_case && !self.code_mode.is_enabled() => fit_case(before, text),
+            _ => text.to_string(),
+        }
+    }
+
+    /// Type the separator after a turn that ended with text
+    fn type_separator(&mut self) -> Result<()> {
+        let needed = match self.separator {
+            TurnSeparator::Auto => matches!(&self.tail, Tail::Text(t) if !t.ends_with(char::is_whitespace)),
+            TurnSeparator::Space => true,
+            TurnSeparator::None => false,
+        };
+        if needed {
+            self.type_text(" ")?;
+        }
+        Ok(())
+    }
+
+    /// Bring the typed text of the current turn in line with `new_transcript`
+    fn type_update(&mut self, new_transcript: &str) -> Result<()> {
         // If the new transcript is empty, clear everything
         if new_transcript.is_empty() {
             self.clear_current_text()?;
@@ -264,9 +940,11 @@ impl<H: KeyboardHardware> VirtualKeyboard<H> {
             let new_chars = &new_transcript[self.current_text.len()..];
             if !new_chars.is_empty() {
                 debug!("Typing new characters: '{}'", new_chars);
-                self.hardware.type_text(new_chars)?;
+                self.type_text(new_chars)?;
                 self.current_text = new_transcript.to_string();
             }
+        } else if let Some(edit) = self.cheaper_cursor_edit(new_transcript) {
+            self.edit_in_place(&edit, new_transcript)?;
         } else {
             // Find the common prefix between current and new transcript
             let common_prefix_len = self
@@ -277,7 +955,8 @@ impl<H: KeyboardHardware> VirtualKeyboard<H> {
                 .count();
 
             let current_chars: Vec<char> = self.current_text.chars().collect();
-            let chars_to_backspace = current_chars.len() - common_prefix_len;
+            let differing: String = current_chars[common_prefix_len..].iter().collect();
+            let chars_to_backspace = self.emitted_len(&differing);
 
             debug!(
                 "Common prefix length: {}, need to backspace {} characters",
@@ -288,7 +967,7 @@ impl<H: KeyboardHardware> VirtualKeyboard<H> {
             if chars_to_backspace > 0 {
                 debug!("Backspacing {} characters", chars_to_backspace);
                 for _ in 0..chars_to_backspace {
-                    self.hardware.press_backspace()?;
+                    self.backspace()?;
                 }
             }
 
@@ -297,7 +976,7 @@ impl<H: KeyboardHardware> VirtualKeyboard<H> {
             if common_prefix_len < new_chars.len() {
                 let new_ending: String = new_chars[common_prefix_len..].iter().collect();
                 debug!("Typing new ending: '{}'", new_ending);
-                self.hardware.type_text(&new_ending)?;
+                self.type_text(&new_ending)?;
             }
 
             self.current_text = new_transcript.to_string();
@@ -306,104 +985,638 @@ impl<H: KeyboardHardware> VirtualKeyboard<H> {
         Ok(())
     }
 
-    /// Finalize the current transcript
-    /// If the transcript ends with "enter" (with optional punctuation/whitespace),
-    /// backspace that portion and press the ENTER key
-    /// Otherwise, just finalize without pressing enter
-    pub fn finalize_transcript(&mut self) -> Result<()> {
-        // Trim whitespace from the current text before finalizing
-        self.current_text = self.current_text.trim().to_string();
-        
-        debug!("Finalizing transcript: '{}'", self.current_text);
+    /// A word-level edit that patches the changed span of the current text with the cursor,
+    /// if that takes fewer key events than backspacing to the first change and retyping
+    fn cheaper_cursor_edit(&self, new_transcript: &str) -> Option<WordEdit> {
+        let edit = WordEdit::between(&self.current_text, new_transcript)?;
+        let old_chars: Vec<char> = self.current_text.chars().collect();
+        let common_prefix_len = old_chars
+            .iter()
+            .zip(new_transcript.chars())
+            .take_while(|&(&a, b)| a == b)
+            .count();
+        let old_tail: String = old_chars[common_prefix_len..].iter().collect();
+        let new_tail: String = new_transcript.chars().skip(common_prefix_len).collect();
+        let retype_cost = 2 * self.emitted_len(&old_tail) + self.typing_cost(&new_tail);
+
+        // Left over the unchanged end, delete and type the span, then right back
+        let old_span = &self.current_text[edit.old_span.clone()];
+        let new_span = &new_transcript[edit.new_span.clone()];
+        let moves = self.emitted_len(&self.current_text[edit.old_span.end..]);
+        let edit_cost = 4 * moves + 2 * self.emitted_len(old_span) + self.typing_cost(new_span);
+
+        debug!("Retyping costs {} key events, editing in place {}", retype_cost, edit_cost);
+        (edit_cost < retype_cost).then_some(edit)
+    }
 
-        // If delay_input is enabled, type the complete text and clear
-        if self.delay_input {
-            // If we already finalized due to eager EOT, skip typing again
-            if self.eager_eot_finalized {
-                debug!("Skipping finalize - already finalized on eager EOT");
-                self.eager_eot_finalized = false;  // Reset for next turn
-                self.current_text.clear();
-                return Ok(());
-            }
-            
-            // Only type if there's actual text (non-empty after trimming)
-            if !self.current_text.is_empty() {
-                self.hardware.type_text(&self.current_text)?;
-                self.current_text.clear();
-                self.hardware.type_text(" ")?;
-            } else {
-                self.current_text.clear();
-            }
-            return Ok(());
+    /// Replace the changed span in place and move the cursor back to the end
+    fn edit_in_place(&mut self, edit: &WordEdit, new_transcript: &str) -> Result<()> {
+        let old_text = std::mem::take(&mut self.current_text);
+        let moves = self.emitted_len(&old_text[edit.old_span.end..]);
+        let old_span = &old_text[edit.old_span.clone()];
+        let new_span = &new_transcript[edit.new_span.clone()];
+        debug!("Editing in place: '{}' -> '{}', {} characters from the end", old_span, new_span, moves);
+
+        for _ in 0..moves {
+            self.hardware.press_key(KEY_LEFT)?;
+        }
+        for _ in 0..self.emitted_len(old_span) {
+            self.backspace()?;
+        }
+        self.type_text(new_span)?;
+        for _ in 0..moves {
+            self.hardware.press_key(KEY_RIGHT)?;
         }
 
-        // Regex to match "enter" (case-insensitive) at the end, optionally followed by
-        // punctuation and/or whitespace: (?i)\s*\benter\b[[:punct:]\s]*$
-        // (?i) = case insensitive
-        // \s* = optional leading whitespace
-        // \benter\b = the word "enter" with word boundaries
-        // [[:punct:]\s]* = optional trailing punctuation or whitespace
-        // $ = end of string
-        let enter_regex = Regex::new(r"(?i)\s*\benter\b[[:punct:]\s]*$").unwrap();
-
-        // Find the match and extract the information we need before mutating self
-        let match_info = enter_regex.find(&self.current_text).map(|m| {
-            (
-                m.start(),
-                m.as_str().chars().count(),
-                m.as_str().to_string(),
-            )
-        });
+        // The text still ends the same way; rebuild the tail from where the turn started
+        self.tail = self.segment_start.clone().unwrap_or(Tail::Unknown);
+        self.push_tail(new_transcript);
+        self.current_text = new_transcript.to_string();
+        Ok(())
+    }
 
-        if let Some((start_pos, chars_to_backspace, matched_str)) = match_info {
-            debug!(
-                "Found 'enter' command at end of transcript: '{}'",
-                matched_str
-            );
-            debug!(
-                "Backspacing {} characters for 'enter' command",
-                chars_to_backspace
-            );
+    /// Key events needed to type `text`
+    fn typing_cost(&self, text: &str) -> usize {
+        text.chars().map(|c| self.hardware.key_events(c)).sum()
+    }
 
-            // Backspace the matched portion
-            for _ in 0..chars_to_backspace {
-                self.hardware.press_backspace()?;
-                // Small delay between backspaces for reliability
-                std::thread::sleep(std::time::Duration::from_millis(5));
+    /// Handle an event of the provider's turn `turn_index`
+    /// Late events for turns that already ended are dropped, and events of a later turn
+    /// wait until the open turn ends, or for up to `TURN_TIMEOUT` of silence from it
+    pub fn handle_turn_event(&mut self, turn_index: u32, event: TurnEvent) -> Result<()> {
+        if self.turns.finished.is_some_and(|finished| turn_index <= finished) {
+            debug!("Dropping {:?} for turn {}, which already ended", event, turn_index);
+            return Ok(());
+        }
+        match self.turns.open {
+            Some(open) if turn_index < open => {
+                debug!("Dropping {:?} for turn {}, turn {} has started", event, turn_index, open);
+                return Ok(());
             }
+            Some(open) if turn_index > open => {
+                self.turns.hold(turn_index, event);
+                if self.turns.last_event.elapsed() < self.turns.timeout {
+                    debug!("Holding back turn {} until turn {} ends", turn_index, open);
+                    return Ok(());
+                }
+                return self.expire_turns();
+            }
+            _ => {}
+        }
 
-            // Update our internal tracking to remove the backspaced characters
-            self.current_text = self.current_text[..start_pos].to_string();
-
-            // Press the actual ENTER key
-            debug!("Pressing ENTER key");
-            self.hardware.press_enter()?;
+        self.turns.open = Some(turn_index);
+        self.turns.last_event = Instant::now();
+        let ends_turn = matches!(event, TurnEvent::EndOfTurn);
+        self.apply_turn_event(event)?;
+        if ends_turn {
+            self.end_turn_index(turn_index)?;
         }
+        Ok(())
+    }
 
-        // Clear the current text tracking
-        self.current_text.clear();
+    #[cfg(test)]
+    pub fn set_turn_timeout(&mut self, timeout: Duration) {
+        self.turns.timeout = timeout;
+    }
 
-        // Add a space after the end of each turn
-        self.hardware.type_text(" ")?;
+    /// How long until the open turn times out, if later turns are waiting for it; the
+    /// caller runs `expire_turns` then, in case no other event comes first
+    pub fn turn_timeout(&self) -> Option<Duration> {
+        if self.turns.held.is_empty() {
+            return None;
+        }
+        Some(self.turns.timeout.saturating_sub(self.turns.last_event.elapsed()))
+    }
 
+    /// Finalize the open turn if it went `TURN_TIMEOUT` without events while later turns
+    /// wait, and type the turns that were held back
+    pub fn expire_turns(&mut self) -> Result<()> {
+        while let (Some(open), Some(Duration::ZERO)) = (self.turns.open, self.turn_timeout()) {
+            warn!("Turn {} timed out without an end of turn, finalizing it", open);
+            self.finalize_transcript()?;
+            self.end_turn_index(open)?;
+        }
         Ok(())
     }
 
-    /// Clear the current text by backspacing
-    fn clear_current_text(&mut self) -> Result<()> {
-        if !self.current_text.is_empty() {
-            self.backspace_current_text()?;
+    /// A new connection numbers its turns from 0 again; turns the last one left open
+    /// are final now
+    pub fn start_session(&mut self) -> Result<()> {
+        self.end_session()
+    }
+
+    /// Finalize the open turn and any turns held back behind it, e.g. when listening
+    /// stops or before shutting down; the next session numbers its turns from 0 again
+    pub fn end_session(&mut self) -> Result<()> {
+        while let Some(open) = self.turns.open {
+            debug!("Finalizing turn {} of the session", open);
+            self.finalize_transcript()?;
+            self.end_turn_index(open)?;
         }
+        self.turns = TurnOrder::default();
         Ok(())
     }
 
-    /// Backspace all characters in the current text
-    fn backspace_current_text(&mut self) -> Result<()> {
-        let char_count = self.current_text.chars().count();
-        debug!("Backspacing {} characters", char_count);
-
-        for _ in 0..char_count {
+    /// Forget what the session has not typed yet, e.g. when the recording is cancelled:
+    /// the open turn and turns held back behind it are dropped, text already on screen
+    /// stays and can still be undone
+    pub fn discard_session(&mut self) {
+        if self.turns.open.is_some() || !self.turns.held.is_empty() {
+            debug!("Discarding the open turns of the cancelled session");
+        }
+        self.current_text.clear();
+        self.pending_transcript.clear();
+        self.transcript.clear();
+        self.word_counts.clear();
+        self.stable_words = 0;
+        self.consumed_words = 0;
+        self.segment_start = None;
+        self.provisional = None;
+        self.record_turn();
+        self.turns = TurnOrder::default();
+    }
+
+    /// Mark a turn as ended and go on with the next turn that was held back
+    fn end_turn_index(&mut self, turn_index: u32) -> Result<()> {
+        self.turns.open = None;
+        self.turns.finished = Some(turn_index);
+        if let Some((next, events)) = self.turns.held.pop_first() {
+            debug!("Typing turn {}, which was held back", next);
+            for event in events {
+                self.handle_turn_event(next, event)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_turn_event(&mut self, event: TurnEvent) -> Result<()> {
+        match event {
+            TurnEvent::Update { transcript, confident } => self
+                .update_transcript_with_confidence(&transcript, &confident)
+                .context("Failed to update transcript"),
+            TurnEvent::EagerEndOfTurn => {
+                // The text stays open to corrections until the turn really ends
+                info!("Eager end of turn detected, finalizing transcript");
+                self.finalize_eager()
+                    .context("Failed to finalize transcript on eager EOT")
+            }
+            TurnEvent::TurnResumed => {
+                info!("Turn resumed, continuing transcription");
+                self.resume_turn().context("Failed to resume turn")
+            }
+            TurnEvent::EndOfTurn => self
+                .finalize_transcript()
+                .context("Failed to finalize transcript"),
+        }
+    }
+
+    /// Finalize the current transcript
+    /// If the transcript ends with a voice command such as "enter" or "new line" (with
+    /// optional punctuation/whitespace), backspace that portion and run the command
+    /// Otherwise, just finalize and add a space before the next turn
+    pub fn finalize_transcript(&mut self) -> Result<()> {
+        self.finalize().map(|_| ())
+    }
+
+    /// Finalize the turn on an eager end of turn
+    /// The turn is typed as usual, but stays open to corrections: if it resumes or the
+    /// final transcript differs, the text is patched instead of typed again
+    pub fn finalize_eager(&mut self) -> Result<()> {
+        let transcript = if self.delay_input { self.current_text.trim() } else { self.transcript.as_str() }.to_string();
+        let start = match &self.provisional {
+            // Nothing new since the last eager end of turn
+            Some(p) if transcript.is_empty() || transcript == p.transcript => return Ok(()),
+            Some(p) => p.start.clone(),
+            None => self.segment_start.clone().unwrap_or_else(|| self.tail.clone()),
+        };
+        let live_text = self.current_text.trim().to_string();
+        let consumed_words = self.consumed_words;
+
+        let record = self.finalize()?;
+
+        // Only plain text and a separator can be edited later
+        let typed = if self.delay_input { self.fit_to(&start, &transcript) } else { live_text };
+        let emitted = self.emitted_len(&typed);
+        let editable = consumed_words == 0
+            && record.keys.is_empty()
+            && (record.chars == emitted || record.chars == emitted + 1);
+        debug!("Eager end of turn typed '{}' (editable: {})", typed, editable);
+        self.provisional = Some(ProvisionalTurn {
+            transcript,
+            separator: record.chars == emitted + 1,
+            typed: editable.then_some(typed),
+            start,
+            record,
+        });
+        Ok(())
+    }
+
+    /// The turn goes on after an eager end of turn
+    /// In live mode the eager text becomes the live text again, so the resumed turn only
+    /// types what changed; delay mode reconciles when the turn ends
+    pub fn resume_turn(&mut self) -> Result<()> {
+        if self.delay_input {
+            return Ok(());
+        }
+        match self.provisional.take() {
+            Some(provisional) => self.reopen_live(provisional),
+            None => Ok(()),
+        }
+    }
+
+    /// Make the text of a provisional turn the live text of the turn in progress
+    fn reopen_live(&mut self, provisional: ProvisionalTurn) -> Result<()> {
+        let transcript = provisional.transcript.clone();
+        let start = provisional.start.clone();
+        if let Some(typed) = self.reopen(provisional)? {
+            // Its words were typed, so they count as stable
+            let min_updates = self.stability.min_updates;
+            self.word_counts = transcript
+                .split_whitespace()
+                .map(|w| (w.to_string(), min_updates))
+                .collect();
+            self.stable_words = self.word_counts.len();
+            self.current_text = typed;
+            self.segment_start = Some(start);
+            self.transcript = transcript;
+        }
+        Ok(())
+    }
+
+    /// Take a provisional turn back from the history and remove its separator, returning
+    /// the text it typed; if that text can't be edited, its words are skipped instead
+    fn reopen(&mut self, provisional: ProvisionalTurn) -> Result<Option<String>> {
+        let on_screen = provisional.record.is_empty() || self.history.back() == Some(&provisional.record);
+        let typed = match provisional.typed {
+            Some(typed) if on_screen => typed,
+            _ => {
+                debug!("Keeping the output of the eager end of turn, typing only what follows");
+                self.consumed_words = provisional.transcript.split_whitespace().count();
+                return Ok(None);
+            }
+        };
+
+        debug!("Reopening turn '{}'", typed);
+        if !provisional.record.is_empty() {
+            self.history.pop_back();
+        }
+        self.turn = provisional.record;
+        if provisional.separator {
+            self.backspace()?;
+        }
+        Ok(Some(typed))
+    }
+
+    /// Finalize the turn, returning what it left on screen
+    fn finalize(&mut self) -> Result<TurnRecord> {
+        if let Some(provisional) = self.provisional.take() {
+            // Live mode reopens the turn on any change, so the eager text stands
+            if !self.delay_input {
+                return Ok(provisional.record);
+            }
+            let text = self.current_text.trim().to_string();
+            if text.is_empty() || text == provisional.transcript {
+                self.current_text.clear();
+                return Ok(provisional.record);
+            }
+            self.correct_delayed(provisional, &text)?;
+        }
+
+        // Type the words the stability policy held back
+        let pending = std::mem::take(&mut self.pending_transcript);
+        self.word_counts.clear();
+        self.stable_words = 0;
+        if !pending.is_empty() {
+            self.type_transcript(&pending)?;
+        }
+
+        let result = self.finish_turn();
+        self.segment_start = None;
+        self.transcript.clear();
+        let record = self.record_turn();
+        result.map(|_| record)
+    }
+
+    /// In delay mode, bring the text typed on an eager end of turn in line with the final
+    /// transcript; turns with commands are backspaced and run again from the start
+    fn correct_delayed(&mut self, provisional: ProvisionalTurn, text: &str) -> Result<()> {
+        debug!("Final transcript '{}' differs from the eager one '{}'", text, provisional.transcript);
+        let start = provisional.start.clone();
+        let Some(typed) = self.reopen(provisional)? else {
+            return Ok(());
+        };
+
+        if self.has_delayed_commands(text) {
+            for _ in 0..self.emitted_len(&typed) {
+                self.backspace()?;
+            }
+            self.tail = start;
+            return Ok(());
+        }
+
+        let target = self.fit_to(&start, text);
+        let common_prefix_len = typed
+            .chars()
+            .zip(target.chars())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let old_ending: String = typed.chars().skip(common_prefix_len).collect();
+        let new_ending: String = target.chars().skip(common_prefix_len).collect();
+        for _ in 0..self.emitted_len(&old_ending) {
+            self.backspace()?;
+        }
+        if !new_ending.is_empty() {
+            self.emit(&new_ending)?;
+        }
+
+        self.tail = start;
+        self.push_tail(&target);
+        self.current_text.clear();
+        self.type_separator()
+    }
+
+    /// Whether finalizing `text` in delay mode runs a voice command
+    fn has_delayed_commands(&self, text: &str) -> bool {
+        self.standalone_command(text).is_some()
+            || (self.commands.delay_mode()
+                && ((self.commands.mid_turn() && self.commands.match_inner(text).is_some())
+                    || self.commands.match_trailing(text).is_some()))
+    }
+
+    /// Command that makes up all of `text` and runs even when delay mode types turns
+    /// verbatim: switching code mode or "scratch that" are never dictated on their own
+    fn standalone_command(&self, text: &str) -> Option<CommandMatch> {
+        self.commands.match_trailing(text).filter(|m| {
+            m.start == 0 && matches!(m.action, CommandAction::CodeMode(_) | CommandAction::UndoTurn)
+        })
+    }
+
+    fn finish_turn(&mut self) -> Result<()> {
+        // Trim whitespace from the current text before finalizing
+        self.current_text = self.current_text.trim().to_string();
+        let consumed_words = std::mem::take(&mut self.consumed_words);
+
+        debug!("Finalizing transcript: '{}'", self.current_text);
+
+        // If delay_input is enabled, type the complete text and clear
+        if self.delay_input {
+            // Words already typed on an eager end of turn that ran a command are skipped
+            let transcript = std::mem::take(&mut self.current_text);
+            let text = skip_words(&transcript, consumed_words);
+
+            // Only type if there's actual text (non-empty after trimming)
+            if !text.is_empty() {
+                let mut remaining = text;
+                let mut ends_with_command = false;
+
+                // Commands are opt-in here; by default the turn is typed verbatim
+                if let Some(m) = self.standalone_command(remaining) {
+                    self.run_command(&m)?;
+                    return Ok(());
+                }
+
+                if self.commands.delay_mode() {
+                    while self.commands.mid_turn() {
+                        let Some(m) = self.commands.match_inner(remaining) else {
+                            break;
+                        };
+                        self.emit_delayed(&remaining[..m.start], &m)?;
+                        remaining = &remaining[m.end..];
+                    }
+                    if let Some(m) = self.commands.match_trailing(remaining) {
+                        self.emit_delayed(&remaining[..m.start], &m)?;
+                        remaining = "";
+                        ends_with_command = true;
+                    }
+                }
+
+                if !remaining.is_empty() {
+                    self.emit_text(remaining)?;
+                }
+                if !ends_with_command {
+                    self.type_separator()?;
+                }
+            }
+            return Ok(());
+        }
+
+        // Find the command and the text it spans before mutating self
+        let match_info = self.commands.match_trailing(&self.current_text).map(|m| {
+            let chars_to_backspace = self.emitted_len(&self.current_text[m.start..]);
+            (m, chars_to_backspace)
+        });
+
+        if let Some((m, chars_to_backspace)) = match_info {
+            debug!(
+                "Found command at end of transcript: '{}'",
+                &self.current_text[m.start..]
+            );
+            debug!("Backspacing {} characters for command", chars_to_backspace);
+
+            // Backspace the matched portion
+            for _ in 0..chars_to_backspace {
+                self.backspace()?;
+                // Small delay between backspaces for reliability
+                std::thread::sleep(std::time::Duration::from_millis(5));
+            }
+
+            // Update our internal tracking to remove the backspaced characters
+            self.current_text.truncate(m.start);
+
+            self.run_command(&m)?;
+            self.current_text.clear();
+
+            // The command already moved the cursor; no separator needed
+            return Ok(());
+        }
+
+        // Clear the current text tracking
+        self.current_text.clear();
+
+        // Separate this turn from the next
+        self.type_separator()?;
+
+        Ok(())
+    }
+
+    /// Run a matched voice command
+    fn run_command(&mut self, m: &CommandMatch) -> Result<()> {
+        match &m.action {
+            CommandAction::Keys(chords) => {
+                for chord in chords {
+                    debug!("Pressing key {} with modifiers {:?}", chord.key, chord.modifiers);
+                    self.press(chord)?;
+                    std::thread::sleep(std::time::Duration::from_millis(5));
+                }
+            }
+            CommandAction::DeleteThat => {
+                debug!("Deleting the current turn");
+                self.backspace_current_text()?;
+            }
+            CommandAction::UndoTurn => {
+                // Take back what this turn typed so far, or else the previous turn
+                if self.turn.is_empty() {
+                    self.undo_last_turn()?;
+                } else if self.turn.can_undo() {
+                    debug!("Scratching the current turn ({} characters)", self.turn.chars);
+                    for _ in 0..self.turn.chars {
+                        self.hardware.press_backspace()?;
+                        self.tail.pop();
+                        std::thread::sleep(std::time::Duration::from_millis(5));
+                    }
+                    self.turn = TurnRecord::default();
+                    self.current_text.clear();
+                } else {
+                    warn!("Cannot scratch this turn: it pressed keys that backspace can't revert");
+                }
+            }
+            CommandAction::CodeMode(enabled) => {
+                info!("Code mode {}", if *enabled { "on" } else { "off" });
+                self.code_mode.set_enabled(*enabled);
+            }
+            CommandAction::Backspace => {
+                debug!("Backspacing {} characters on command", m.count);
+                for _ in 0..m.count {
+                    self.backspace()?;
+                    std::thread::sleep(std::time::Duration::from_millis(5));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// In delay mode, emit the text spoken before a command and then run it
+    fn emit_delayed(&mut self, text: &str, m: &CommandMatch) -> Result<()> {
+        // Nothing was typed yet, so "delete that" just drops the text
+        if m.action != CommandAction::DeleteThat && !text.is_empty() {
+            self.emit_text(text)?;
+        }
+        self.run_command(m)
+    }
+
+    /// Type or paste finalized text, depending on the output mode
+    fn emit_text(&mut self, text: &str) -> Result<()> {
+        let text = self.fit_to(&self.tail, text);
+        self.emit(&text)
+    }
+
+    fn emit(&mut self, text: &str) -> Result<()> {
+        match self.output_mode {
+            OutputMode::Type => self.type_text(text),
+            OutputMode::Paste => {
+                self.hardware.paste_text(text)?;
+                self.turn.chars += self.emitted_len(text);
+                self.push_tail(text);
+                Ok(())
+            }
+        }
+    }
+
+    /// Type text and count it towards the current turn
+    fn type_text(&mut self, text: &str) -> Result<()> {
+        self.hardware.type_text(text)?;
+        self.turn.chars += self.emitted_len(text);
+        self.push_tail(text);
+        Ok(())
+    }
+
+    /// Remember the characters of `text` that made it to the screen
+    fn push_tail(&mut self, text: &str) {
+        let emitted: String = text.chars().filter(|&c| self.hardware.supports_char(c)).collect();
+        self.tail.push(&emitted);
+    }
+
+    /// Press backspace; once the turn's own output is gone it deletes earlier text
+    fn backspace(&mut self) -> Result<()> {
+        self.hardware.press_backspace()?;
+        self.tail.pop();
+        if self.turn.chars > 0 {
+            self.turn.chars -= 1;
+        } else {
+            self.turn.keys.push(KeyChord {
+                modifiers: Vec::new(),
+                key: KEY_BACKSPACE,
+            });
+        }
+        Ok(())
+    }
+
+    /// Press a key or chord from a voice command and record it
+    fn press(&mut self, chord: &KeyChord) -> Result<()> {
+        match (chord.modifiers.is_empty(), chord.key) {
+            (true, KEY_BACKSPACE) => return self.backspace(),
+            (true, KEY_ENTER) => self.hardware.press_enter()?,
+            (true, key) => self.hardware.press_key(key)?,
+            (false, key) => self.hardware.press_chord(&chord.modifiers, key)?,
+        }
+        // Enter and Tab leave a character that a backspace removes; other keys may
+        // move the cursor anywhere
+        match (chord.modifiers.is_empty(), chord.key) {
+            (true, KEY_ENTER) => self.tail.push("\n"),
+            (true, KEY_TAB) => self.tail.push("\t"),
+            _ => self.tail = Tail::Unknown,
+        }
+        if chord.modifiers.is_empty() && matches!(chord.key, KEY_ENTER | KEY_TAB) {
+            self.turn.chars += 1;
+        }
+        self.turn.keys.push(chord.clone());
+        Ok(())
+    }
+
+    /// Remove the most recent finalized turn by backspacing exactly what it typed
+    /// Returns false if there is nothing to undo or the turn can't be reverted
+    pub fn undo_last_turn(&mut self) -> Result<bool> {
+        if !self.turn.is_empty() {
+            warn!("Not undoing the last turn while a turn is being typed");
+            return Ok(false);
+        }
+        let Some(record) = self.history.pop_back() else {
+            info!("Nothing to undo");
+            return Ok(false);
+        };
+        if !record.can_undo() {
+            warn!("Cannot undo the last turn: it pressed keys that backspace can't revert");
+            return Ok(false);
+        }
+
+        info!("Undoing last turn ({} characters)", record.chars);
+        for _ in 0..record.chars {
             self.hardware.press_backspace()?;
+            self.tail.pop();
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        Ok(true)
+    }
+
+    /// Move the output of the finished turn into the history
+    fn record_turn(&mut self) -> TurnRecord {
+        let record = std::mem::take(&mut self.turn);
+        if record.is_empty() {
+            return record;
+        }
+        debug!("Recording turn: {:?}", record);
+        if self.history.len() == MAX_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(record.clone());
+        record
+    }
+
+    /// Clear the current text by backspacing
+    fn clear_current_text(&mut self) -> Result<()> {
+        if !self.current_text.is_empty() {
+            self.backspace_current_text()?;
+        }
+        Ok(())
+    }
+
+    /// Backspace all characters in the current text
+    fn backspace_current_text(&mut self) -> Result<()> {
+        let char_count = self.emitted_len(&self.current_text);
+        debug!("Backspacing {} characters", char_count);
+
+        for _ in 0..char_count {
+            self.backspace()?;
             // Small delay between backspaces for reliability
             std::thread::sleep(std::time::Duration::from_millis(5));
         }
@@ -412,20 +1625,144 @@ impl<H: KeyboardHardware> VirtualKeyboard<H> {
         Ok(())
     }
 
+    /// Number of characters `text` actually occupies on screen once typed
+    /// One character may take many key events (e.g. Ctrl+Shift+U entry) but is still
+    /// removed by a single backspace, while unsupported characters are never typed at all
+    fn emitted_len(&self, text: &str) -> usize {
+        text.chars()
+            .filter(|&c| self.hardware.supports_char(c))
+            .count()
+    }
+
     /// Get the current text that has been typed
     pub fn get_current_text(&self) -> &str {
         &self.current_text
     }
+}
+
+/// The one span of words that differs between two versions of a transcript, as byte
+/// ranges; the words before and after it are the same in both
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct WordEdit {
+    old_span: std::ops::Range<usize>,
+    new_span: std::ops::Range<usize>,
+}
+
+impl WordEdit {
+    /// None if nothing after the change is unchanged, so there is nothing to keep
+    fn between(old: &str, new: &str) -> Option<Self> {
+        let (old_words, new_words) = (word_spans(old), word_spans(new));
+        let same = |o: (usize, usize), n: (usize, usize)| old[o.0..o.1] == new[n.0..n.1];
+
+        let prefix = old_words
+            .iter()
+            .zip(&new_words)
+            .take_while(|&(&o, &n)| same(o, n))
+            .count();
+        let max_suffix = old_words.len().min(new_words.len()) - prefix;
+        let suffix = old_words
+            .iter()
+            .rev()
+            .zip(new_words.iter().rev())
+            .take(max_suffix)
+            .take_while(|&(&o, &n)| same(o, n))
+            .count();
+        if suffix == 0 {
+            return None;
+        }
+
+        // The span runs from the end of the shared first words to the start of the shared last ones
+        let span = |words: &[(usize, usize)]| {
+            let start = if prefix == 0 { 0 } else { words[prefix - 1].1 };
+            start..words[words.len() - suffix].0
+        };
+        let (mut old_span, mut new_span) = (span(&old_words), span(&new_words));
+        if old[..old_span.start] != new[..new_span.start] || old[old_span.end..] != new[new_span.end..] {
+            return None;
+        }
 
-    /// Mark that we've finalized due to eager end of turn
-    pub fn mark_eager_eot_finalized(&mut self) {
-        self.eager_eot_finalized = true;
+        // Keep the spaces around the changed words
+        while old_span.start < old_span.end
+            && new_span.start < new_span.end
+            && old[old_span.start..].starts_with(' ')
+            && new[new_span.start..].starts_with(' ')
+        {
+            old_span.start += 1;
+            new_span.start += 1;
+        }
+        while old_span.start < old_span.end
+            && new_span.start < new_span.end
+            && old[..old_span.end].ends_with(' ')
+            && new[..new_span.end].ends_with(' ')
+        {
+            old_span.end -= 1;
+            new_span.end -= 1;
+        }
+        Some(Self { old_span, new_span })
+    }
+}
+
+// Common words the provider capitalizes only because they start a turn; anything else
+// may be a name ("John", "Paris") and keeps its capital mid-sentence
+const LOWERCASE_WORDS: &[&str] = &[
+    "a", "about", "after", "again", "all", "also", "an", "and", "any", "are", "as", "at",
+    "because", "before", "but", "by", "can", "could", "do", "does", "don't", "for", "from",
+    "had", "has", "have", "he", "her", "here", "him", "his", "how", "if", "in", "is", "it",
+    "it's", "its", "just", "let's", "like", "maybe", "me", "my", "no", "not", "now", "of",
+    "oh", "ok", "okay", "on", "or", "our", "please", "really", "she", "should", "so",
+    "some", "that", "that's", "the", "their", "them", "then", "there", "there's", "these",
+    "they", "this", "those", "to", "too", "uh", "um", "us", "very", "was", "we", "well",
+    "were", "what", "what's", "when", "where", "which", "while", "who", "why", "will",
+    "with", "would", "yeah", "yes", "you", "your",
+];
+
+// `text` with its first letter capitalized after the end of a sentence or line, and
+// lowercased mid-sentence if it starts with a common word ("The end" but not "John")
+fn fit_case(before: &str, text: &str) -> String {
+    let before = before.trim_end_matches([' ', '\t']);
+    let sentence_start = before.is_empty()
+        || before.ends_with('\n')
+        || before
+            .trim_end_matches(['"', '\'', ')', ']', '\u{201d}', '\u{2019}'])
+            .ends_with(['.', '!', '?', '\u{2026}']);
+
+    let mut chars = text.chars();
+    let Some(first) = chars.next() else {
+        return String::new();
+    };
+    let rest = chars.as_str();
+
+    if sentence_start {
+        return first.to_uppercase().chain(rest.chars()).collect();
+    }
+
+    let word = text.split(|c: char| !c.is_alphanumeric() && c != '\'').next().unwrap_or("");
+    let rest_of_word = &word[first.len_utf8().min(word.len())..];
+    let common = LOWERCASE_WORDS.contains(&word.to_lowercase().as_str());
+    if first.is_uppercase() && common && !rest_of_word.chars().any(|c| c.is_uppercase()) {
+        return first.to_lowercase().chain(rest.chars()).collect();
     }
+    text.to_string()
+}
 
-    /// Reset eager EOT flag (called when turn is resumed)
-    pub fn reset_eager_eot_flag(&mut self) {
-        self.eager_eot_finalized = false;
+// Byte ranges of the whitespace separated words of `text`
+fn word_spans(text: &str) -> Vec<(usize, usize)> {
+    text.split_whitespace()
+        .map(|w| {
+            let start = w.as_ptr() as usize - text.as_ptr() as usize;
+            (start, start + w.len())
+        })
+        .collect()
+}
+
+// `text` without its first `count` words
+fn skip_words(text: &str, count: usize) -> &str {
+    let mut rest = text;
+    for _ in 0..count {
+        rest = rest.trim_start();
+        rest = &rest[rest.find(char::is_whitespace).unwrap_or(rest.len())..];
     }
+    rest.trim_start()
 }
 
 /// Mock hardware implementation for testing
@@ -433,6 +1770,12 @@ pub struct MockKeyboardHardware {
     pub typed_chars: Vec<char>,
     pub backspace_count: usize,
     pub enter_pressed: bool,
+    pub unsupported_chars: Vec<char>,
+    pub pasted: Vec<String>,
+    pub pressed_keys: Vec<u16>,
+    pub chords: Vec<(Vec<u16>, u16)>,
+    /// Position in `typed_chars` where typing inserts, moved by the arrow keys
+    pub cursor: usize,
 }
 
 impl MockKeyboardHardware {
@@ -441,35 +1784,77 @@ impl MockKeyboardHardware {
             typed_chars: Vec::new(),
             backspace_count: 0,
             enter_pressed: false,
+            unsupported_chars: Vec::new(),
+            pasted: Vec::new(),
+            pressed_keys: Vec::new(),
+            chords: Vec::new(),
+            cursor: 0,
         }
     }
+
+    fn insert(&mut self, c: char) {
+        self.typed_chars.insert(self.cursor, c);
+        self.cursor += 1;
+    }
 }
 
 impl KeyboardHardware for MockKeyboardHardware {
     fn type_text(&mut self, text: &str) -> Result<()> {
         for c in text.chars() {
-            self.typed_chars.push(c);
+            if self.supports_char(c) {
+                self.insert(c);
+            }
         }
         Ok(())
     }
 
     fn press_backspace(&mut self) -> Result<()> {
         self.backspace_count += 1;
-        if !self.typed_chars.is_empty() {
-            self.typed_chars.pop();
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.typed_chars.remove(self.cursor);
         }
         Ok(())
     }
 
     fn press_enter(&mut self) -> Result<()> {
         self.enter_pressed = true;
+        self.insert('\n');
+        Ok(())
+    }
+
+    fn press_key(&mut self, keycode: u16) -> Result<()> {
+        self.pressed_keys.push(keycode);
+        match keycode {
+            KEY_TAB => self.insert('\t'),
+            KEY_LEFT => self.cursor = self.cursor.saturating_sub(1),
+            KEY_RIGHT => self.cursor = (self.cursor + 1).min(self.typed_chars.len()),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn press_chord(&mut self, modifiers: &[u16], keycode: u16) -> Result<()> {
+        self.chords.push((modifiers.to_vec(), keycode));
         Ok(())
     }
 
-    fn press_key(&mut self, _keycode: u16) -> Result<()> {
-        // Mock implementation - could log the keycode if needed
+    fn paste_text(&mut self, text: &str) -> Result<()> {
+        self.pasted.push(text.to_string());
+        for c in text.chars() {
+            self.insert(c);
+        }
         Ok(())
     }
+
+    fn supports_char(&self, c: char) -> bool {
+        !self.unsupported_chars.contains(&c)
+    }
+
+    // Capitals need shift
+    fn key_events(&self, c: char) -> usize {
+        if c.is_uppercase() { 4 } else { 2 }
+    }
 }
 
 #[cfg(test)]
@@ -602,64 +1987,234 @@ mod tests {
     }
 
     #[test]
-    fn test_finalize_with_enter_command() {
+    fn test_smart_backspacing_unicode_characters() {
         let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
 
-        // Type text ending with "enter"
-        kb.update_transcript("Write a unit test enter").unwrap();
-        assert_eq!(kb.current_text, "Write a unit test enter");
-        assert!(!kb.hardware.enter_pressed);
+        // Multi-key characters still occupy one character on screen
+        kb.update_transcript("café — ok").unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "café — ok");
 
-        // Finalize - should backspace "enter" and press ENTER key
-        kb.finalize_transcript().unwrap();
-        assert_eq!(kb.current_text, "");
-        assert!(kb.hardware.enter_pressed);
-        // Should have backspaced 6 characters (" enter")
-        assert_eq!(kb.hardware.backspace_count, 6);
+        kb.update_transcript("café — no").unwrap();
+        assert_eq!(kb.hardware.backspace_count, 2);
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "café — no");
+
+        kb.update_transcript("cafe").unwrap();
+        assert_eq!(kb.hardware.backspace_count, 2 + 6);
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "cafe");
     }
 
     #[test]
-    fn test_finalize_with_enter_only() {
-        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+    fn test_smart_backspacing_skips_unsupported_characters() {
+        let mut hardware = MockKeyboardHardware::new();
+        hardware.unsupported_chars.push('🙂');
+        let mut kb = VirtualKeyboard::new(hardware, false);
 
-        // Type only "enter"
-        kb.update_transcript("enter").unwrap();
-        assert_eq!(kb.current_text, "enter");
-        assert!(!kb.hardware.enter_pressed);
+        // The emoji is never typed, so it must not be backspaced either
+        kb.update_transcript("hi 🙂 there").unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "hi  there");
 
-        // Finalize - should backspace all and press ENTER key
-        kb.finalize_transcript().unwrap();
-        assert_eq!(kb.current_text, "");
-        assert!(kb.hardware.enter_pressed);
-        // Should have backspaced 5 characters ("enter")
-        assert_eq!(kb.hardware.backspace_count, 5);
+        kb.update_transcript("hi").unwrap();
+        assert_eq!(kb.hardware.backspace_count, 7);
+        assert_eq!(kb.hardware.typed_chars, ['h', 'i']);
+
+        // Clearing counts only what is on screen
+        kb.update_transcript("🙂 ok").unwrap();
+        kb.update_transcript("").unwrap();
+        assert_eq!(kb.hardware.backspace_count, 7 + 2 + 3);
+        assert!(kb.hardware.typed_chars.is_empty());
     }
 
     #[test]
-    fn test_finalize_with_enter_case_insensitive() {
+    fn test_cursor_edit_replaces_one_word() {
         let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
 
-        // Type text ending with "ENTER" (uppercase)
-        kb.update_transcript("hello ENTER").unwrap();
-        assert_eq!(kb.current_text, "hello ENTER");
-        assert!(!kb.hardware.enter_pressed);
+        kb.update_transcript("the cat Sat On The Mat").unwrap();
+        kb.update_transcript("the hat Sat On The Mat").unwrap();
 
-        // Finalize - should still recognize and handle it
-        kb.finalize_transcript().unwrap();
-        assert_eq!(kb.current_text, "");
-        assert!(kb.hardware.enter_pressed);
-        // Should have backspaced 6 characters (" ENTER")
-        assert_eq!(kb.hardware.backspace_count, 6);
+        // Only "cat" is deleted; the rest is skipped over with the arrow keys
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "the hat Sat On The Mat");
+        assert_eq!(kb.hardware.backspace_count, 3);
+        assert_eq!(kb.hardware.pressed_keys.iter().filter(|&&k| k == KEY_LEFT).count(), 15);
+        assert_eq!(kb.hardware.pressed_keys.iter().filter(|&&k| k == KEY_RIGHT).count(), 15);
+        assert_eq!(kb.hardware.cursor, kb.hardware.typed_chars.len());
+        assert_eq!(kb.current_text, "the hat Sat On The Mat");
+
+        // Typing continues at the end
+        kb.update_transcript("the hat Sat On The Mat today").unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "the hat Sat On The Mat today");
     }
 
     #[test]
-    fn test_finalize_without_enter_command() {
+    fn test_cursor_edit_inserts_and_deletes_words() {
         let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
 
-        // Type text not ending with "enter"
-        kb.update_transcript("hello world").unwrap();
-        assert_eq!(kb.current_text, "hello world");
-        assert!(!kb.hardware.enter_pressed);
+        kb.update_transcript("call Me At Noon Today").unwrap();
+        kb.update_transcript("please call Me At Noon Today").unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "please call Me At Noon Today");
+        assert_eq!(kb.hardware.backspace_count, 0);
+
+        kb.update_transcript("please Me At Noon Today").unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "please Me At Noon Today");
+        assert_eq!(kb.hardware.backspace_count, 5);
+        assert_eq!(kb.hardware.cursor, kb.hardware.typed_chars.len());
+
+        // Scratching the turn still removes exactly what it typed
+        kb.update_transcript("please Me At Noon Today scratch that").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert!(kb.hardware.typed_chars.is_empty());
+    }
+
+    #[test]
+    fn test_cursor_edit_only_when_cheaper() {
+        // Moving over plain letters costs as much as retyping them, so backspacing wins
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        kb.update_transcript("the cat sat on the mat").unwrap();
+        kb.update_transcript("the hat sat on the mat").unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "the hat sat on the mat");
+        assert!(kb.hardware.pressed_keys.is_empty());
+        assert_eq!(kb.hardware.backspace_count, 18);
+
+        // A change in the last word is handled by backspacing too
+        kb.update_transcript("the hat sat on The Rug").unwrap();
+        assert!(kb.hardware.pressed_keys.is_empty());
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "the hat sat on The Rug");
+    }
+
+    #[test]
+    fn test_word_edit_spans() {
+        let test_cases = vec![
+            ("the cat sat", "the hat sat", Some((4..7, 4..7))),
+            ("the sat", "the big cat sat", Some((4..4, 4..12))),
+            ("the big cat", "the cat", Some((4..8, 4..4))),
+            ("cat", "a cat", Some((0..0, 0..2))),
+            ("a b c", "a b d", None),
+            ("same", "same", None),
+        ];
+
+        for (old, new, expected) in test_cases {
+            let edit = WordEdit::between(old, new).map(|e| (e.old_span, e.new_span));
+            assert_eq!(edit, expected, "For: '{}' -> '{}'", old, new);
+        }
+    }
+
+    #[test]
+    fn test_stability_holds_words_back() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        kb.set_stability(StabilityPolicy {
+            min_updates: 2,
+            min_confidence: None,
+        });
+        let typed = |kb: &VirtualKeyboard<MockKeyboardHardware>| kb.hardware.typed_chars.iter().collect::<String>();
+
+        kb.update_transcript("the cat").unwrap();
+        assert_eq!(typed(&kb), "");
+        kb.update_transcript("the hat").unwrap();
+        assert_eq!(typed(&kb), "the");
+        kb.update_transcript("the hat sat").unwrap();
+        assert_eq!(typed(&kb), "the hat");
+
+        // The word that changed was never typed, so nothing was backspaced
+        assert_eq!(kb.hardware.backspace_count, 0);
+
+        // Words still held back are typed at the end of the turn
+        kb.finalize_transcript().unwrap();
+        assert_eq!(typed(&kb), "the hat sat ");
+        assert_eq!(kb.hardware.backspace_count, 0);
+    }
+
+    #[test]
+    fn test_stability_confident_words() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        kb.set_stability(StabilityPolicy {
+            min_updates: 3,
+            min_confidence: Some(0.9),
+        });
+
+        kb.update_transcript_with_confidence("hello big world", "hello big").unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "hello big");
+
+        // Typed words stay while the transcript keeps them, even if confidence drops
+        kb.update_transcript_with_confidence("hello big world", "hello").unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "hello big");
+        assert_eq!(kb.hardware.backspace_count, 0);
+
+        // Confidence only counts for the words it was given for
+        kb.update_transcript_with_confidence("hello big earth", "hello big bearth").unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "hello big");
+
+        // A word the transcript changes is retracted
+        kb.update_transcript_with_confidence("hello bag earth", "").unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "hello");
+    }
+
+    #[test]
+    fn test_stability_default_types_everything() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        kb.update_transcript("hello").unwrap();
+        kb.update_transcript("hello world").unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "hello world");
+        assert!(kb.pending_transcript.is_empty());
+    }
+
+    #[test]
+    fn test_finalize_with_enter_command() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+
+        // Type text ending with "enter"
+        kb.update_transcript("Write a unit test enter").unwrap();
+        assert_eq!(kb.current_text, "Write a unit test enter");
+        assert!(!kb.hardware.enter_pressed);
+
+        // Finalize - should backspace "enter" and press ENTER key
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.current_text, "");
+        assert!(kb.hardware.enter_pressed);
+        // Should have backspaced 6 characters (" enter")
+        assert_eq!(kb.hardware.backspace_count, 6);
+    }
+
+    #[test]
+    fn test_finalize_with_enter_only() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+
+        // Type only "enter"
+        kb.update_transcript("enter").unwrap();
+        assert_eq!(kb.current_text, "enter");
+        assert!(!kb.hardware.enter_pressed);
+
+        // Finalize - should backspace all and press ENTER key
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.current_text, "");
+        assert!(kb.hardware.enter_pressed);
+        // Should have backspaced 5 characters ("enter")
+        assert_eq!(kb.hardware.backspace_count, 5);
+    }
+
+    #[test]
+    fn test_finalize_with_enter_case_insensitive() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+
+        // Type text ending with "ENTER" (uppercase)
+        kb.update_transcript("hello ENTER").unwrap();
+        assert_eq!(kb.current_text, "hello ENTER");
+        assert!(!kb.hardware.enter_pressed);
+
+        // Finalize - should still recognize and handle it
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.current_text, "");
+        assert!(kb.hardware.enter_pressed);
+        // Should have backspaced 6 characters (" ENTER")
+        assert_eq!(kb.hardware.backspace_count, 6);
+    }
+
+    #[test]
+    fn test_finalize_without_enter_command() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+
+        // Type text not ending with "enter"
+        kb.update_transcript("hello world").unwrap();
+        assert_eq!(kb.current_text, "hello world");
+        assert!(!kb.hardware.enter_pressed);
 
         // Finalize - should not press ENTER key but should add a space
         kb.finalize_transcript().unwrap();
@@ -855,6 +2410,283 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_finalize_with_voice_commands() {
+        let test_cases = vec![
+            ("hello new line", "hello\n", vec![], true),
+            ("Dear Sam, new paragraph.", "Dear Sam,\n\n", vec![], true),
+            ("name tab", "name tab ", vec![], false),
+            ("Tab.", "\t", vec![KEY_TAB], false),
+            ("select all", "", vec![], false),
+            ("go to end of line", "", vec![KEY_END], false),
+        ];
+
+        for (input, expected_text, expected_keys, expect_enter) in test_cases {
+            let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+            kb.update_transcript(input).unwrap();
+            kb.finalize_transcript().unwrap();
+
+            assert_eq!(
+                kb.hardware.typed_chars.iter().collect::<String>(),
+                expected_text,
+                "Wrong text for: '{}'",
+                input
+            );
+            assert_eq!(kb.hardware.pressed_keys, expected_keys, "Wrong keys for: '{}'", input);
+            assert_eq!(kb.hardware.enter_pressed, expect_enter, "Wrong ENTER for: '{}'", input);
+        }
+
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        kb.update_transcript("undo").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.hardware.chords, [(vec![KEY_LEFTCTRL], KEY_Z)]);
+
+        // Everyday words at the end of a sentence are dictation
+        for input in ["there is nothing to undo", "I hit backspace", "open a new tab"] {
+            let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+            kb.update_transcript(input).unwrap();
+            kb.finalize_transcript().unwrap();
+            assert!(kb.hardware.chords.is_empty(), "Chord fired for: '{}'", input);
+            assert!(kb.hardware.pressed_keys.is_empty(), "Key pressed for: '{}'", input);
+            assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), format!("{} ", input));
+        }
+    }
+
+    #[test]
+    fn test_press_chord_commands() {
+        let test_cases = vec![
+            ("press control shift T", vec![(vec![KEY_LEFTCTRL, KEY_LEFTSHIFT], KEY_T)], vec![]),
+            ("Press alt F4.", vec![(vec![KEY_LEFTALT], KEY_F4)], vec![]),
+            ("press escape", vec![], vec![KEY_ESC]),
+            ("search press control-f", vec![(vec![KEY_LEFTCTRL], KEY_F)], vec![]),
+        ];
+
+        for (input, expected_chords, expected_keys) in test_cases {
+            let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+            kb.update_transcript(input).unwrap();
+            kb.finalize_transcript().unwrap();
+
+            assert_eq!(kb.hardware.chords, expected_chords, "Wrong chords for: '{}'", input);
+            assert_eq!(kb.hardware.pressed_keys, expected_keys, "Wrong keys for: '{}'", input);
+        }
+
+        // Not a chord: typed as dictated
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        kb.update_transcript("press the button").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert!(kb.hardware.chords.is_empty());
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "press the button ");
+
+        // Bare letters and digits are dictation unless they end the turn, mid-turn too
+        for input in ["press a button to start", "press one more time"] {
+            let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+            let mut commands = VoiceCommands::default();
+            commands.set_mid_turn(true);
+            kb.set_commands(commands);
+            kb.update_transcript(input).unwrap();
+            kb.finalize_transcript().unwrap();
+            assert!(kb.hardware.chords.is_empty(), "Chord fired for: '{}'", input);
+            assert!(kb.hardware.pressed_keys.is_empty(), "Key pressed for: '{}'", input);
+            assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), format!("{} ", input));
+        }
+    }
+
+    #[test]
+    fn test_finalize_with_delete_and_backspace_commands() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        kb.update_transcript("first").unwrap();
+        kb.finalize_transcript().unwrap();
+
+        // "delete that" removes the text of this turn only
+        kb.update_transcript("never mind delete that").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "first ");
+
+        // "backspace three" removes three characters before the turn
+        kb.update_transcript("backspace three").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "fir");
+    }
+
+    #[test]
+    fn test_mid_turn_commands() {
+        let mut commands = VoiceCommands::default();
+        commands.set_mid_turn(true);
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        kb.set_commands(commands);
+
+        kb.update_transcript("hello new line").unwrap();
+        assert!(!kb.hardware.enter_pressed);
+
+        // The command runs once speech continues after it
+        kb.update_transcript("hello new line world").unwrap();
+        assert!(kb.hardware.enter_pressed);
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "hello\nWorld");
+        assert_eq!(kb.current_text, "World");
+
+        // Later updates only touch the text after the command
+        kb.update_transcript("Hello, new line. World peace").unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "hello\nWorld peace");
+
+        kb.update_transcript("Hello, new line. World peace press tab").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.hardware.pressed_keys, [KEY_TAB]);
+        assert_eq!(kb.current_text, "");
+
+        // The next turn starts from scratch
+        kb.update_transcript("again").unwrap();
+        assert_eq!(kb.current_text, "again");
+    }
+
+    #[test]
+    fn test_delayed_input_commands_opt_in() {
+        let mut commands = VoiceCommands::default();
+        commands.set_delay_mode(true);
+        commands.set_mid_turn(true);
+        commands.set("save file", "ctrl+s").unwrap();
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), true);
+        kb.set_commands(commands);
+
+        kb.update_transcript("one new line two delete that three save file").unwrap();
+        assert!(kb.hardware.typed_chars.is_empty());
+
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "one\nThree");
+        assert!(kb.hardware.enter_pressed);
+        assert_eq!(kb.hardware.chords, [(vec![KEY_LEFTCTRL], KEY_S)]);
+    }
+
+    #[test]
+    fn test_code_mode_commands() {
+        let code_mode = CodeMode::default();
+
+        // Delay mode switches even though other commands are typed verbatim
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), true);
+        kb.set_code_mode(code_mode.clone());
+        kb.update_transcript("Code mode on.").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert!(code_mode.is_enabled());
+        assert!(kb.hardware.typed_chars.is_empty());
+
+        kb.update_transcript("no code mode on").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "no code mode on ");
+
+        // Live mode backspaces the phrase
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        kb.set_code_mode(code_mode.clone());
+        kb.update_transcript("user id prose mode").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert!(!code_mode.is_enabled());
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "user id");
+    }
+
+    #[test]
+    fn test_scratch_that_undoes_previous_turns() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        for turn in ["one", "two new line", "three"] {
+            kb.update_transcript(turn).unwrap();
+            kb.finalize_transcript().unwrap();
+        }
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "one two\nThree ");
+
+        // Each "scratch that" removes one more turn, including newlines and separators
+        kb.update_transcript("scratch that").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "one two\n");
+
+        let backspaces = kb.hardware.backspace_count;
+        kb.update_transcript("Undo that.").unwrap();
+        kb.finalize_transcript().unwrap();
+        // "Undo that." itself (10) plus "two" and the newline (4)
+        assert_eq!(kb.hardware.backspace_count - backspaces, 10 + 4);
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "one ");
+
+        kb.update_transcript("scratch that").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert!(kb.hardware.typed_chars.is_empty());
+
+        // Nothing left to undo
+        assert!(!kb.undo_last_turn().unwrap());
+    }
+
+    #[test]
+    fn test_scratch_that_within_turn() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        kb.update_transcript("keep").unwrap();
+        kb.finalize_transcript().unwrap();
+
+        // Text before the command in the same turn is what gets scratched
+        kb.update_transcript("wrong words scratch that").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "keep ");
+
+        assert!(kb.undo_last_turn().unwrap());
+        assert!(kb.hardware.typed_chars.is_empty());
+    }
+
+    #[test]
+    fn test_scratch_that_in_delay_mode() {
+        // Runs without --delay-mode-commands when it is the whole turn
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), true);
+        for turn in ["keep this", "not this"] {
+            kb.update_transcript(turn).unwrap();
+            kb.finalize_transcript().unwrap();
+        }
+        kb.update_transcript("Scratch that.").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "keep this ");
+
+        // Inside dictation it stays text
+        kb.update_transcript("never scratch that").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "keep this never scratch that ");
+    }
+
+    #[test]
+    fn test_undo_last_turn_delayed_and_pasted() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), true);
+        kb.set_output_mode(OutputMode::Paste);
+        kb.update_transcript("first").unwrap();
+        kb.finalize_transcript().unwrap();
+        kb.update_transcript("café 🙂").unwrap();
+        kb.finalize_transcript().unwrap();
+
+        assert!(kb.undo_last_turn().unwrap());
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "first ");
+        assert!(kb.undo_last_turn().unwrap());
+        assert!(kb.hardware.typed_chars.is_empty());
+    }
+
+    #[test]
+    fn test_undo_last_turn_refuses_irreversible_turns() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        kb.update_transcript("text").unwrap();
+        kb.finalize_transcript().unwrap();
+        kb.update_transcript("select all").unwrap();
+        kb.finalize_transcript().unwrap();
+
+        // Backspacing after Ctrl+A would delete far more than the turn
+        let backspaces = kb.hardware.backspace_count;
+        assert!(!kb.undo_last_turn().unwrap());
+        assert_eq!(kb.hardware.backspace_count, backspaces);
+
+        // Undo is refused while a turn is on screen
+        kb.update_transcript("pending").unwrap();
+        assert!(!kb.undo_last_turn().unwrap());
+    }
+
+    #[test]
+    fn test_undo_history_is_bounded() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), true);
+        for _ in 0..MAX_HISTORY + 5 {
+            kb.update_transcript("x").unwrap();
+            kb.finalize_transcript().unwrap();
+        }
+        assert_eq!(kb.history.len(), MAX_HISTORY);
+        assert_eq!(kb.history[0], TurnRecord { chars: 2, keys: Vec::new() });
+    }
+
     #[test]
     fn test_delayed_input_no_incremental_typing() {
         let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), true);
@@ -929,6 +2761,126 @@ mod tests {
         assert_eq!(kb.current_text, "");
     }
 
+    #[test]
+    fn test_delayed_input_paste_mode() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), true);
+        kb.set_output_mode(OutputMode::Paste);
+
+        kb.update_transcript("hello").unwrap();
+        kb.update_transcript("hello world").unwrap();
+        assert!(kb.hardware.pasted.is_empty());
+
+        // The whole turn is pasted at once, followed by the separator
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.hardware.pasted, ["hello world"]);
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "hello world ");
+
+        // Empty turns paste nothing
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.hardware.pasted.len(), 1);
+    }
+
+    #[test]
+    fn test_turn_case_follows_previous_text() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), true);
+        for turn in ["The meeting is", "On Tuesday.", "it was fine, but", "I left early", "NASA called"] {
+            kb.update_transcript(turn).unwrap();
+            kb.finalize_transcript().unwrap();
+        }
+        assert_eq!(
+            kb.hardware.typed_chars.iter().collect::<String>(),
+            "The meeting is on Tuesday. It was fine, but I left early NASA called "
+        );
+
+        // Live updates keep the case decided when the turn started typing
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        kb.update_transcript("Hello").unwrap();
+        kb.finalize_transcript().unwrap();
+        kb.update_transcript("So").unwrap();
+        kb.update_transcript("So long.").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "Hello so long. ");
+
+        // Case is left alone in code mode, after shortcuts and when turned off
+        let code_mode = CodeMode::default();
+        code_mode.set_enabled(true);
+        kb.set_code_mode(code_mode.clone());
+        kb.update_transcript("x").unwrap();
+        kb.finalize_transcript().unwrap();
+        code_mode.set_enabled(false);
+        kb.update_transcript("select all").unwrap();
+        kb.finalize_transcript().unwrap();
+        kb.update_transcript("gone").unwrap();
+        kb.finalize_transcript().unwrap();
+        kb.set_sentence_case(false);
+        kb.update_transcript("Now").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "Hello so long. x gone Now ");
+    }
+
+    #[test]
+    fn test_turn_separator() {
+        assert_eq!(TurnSeparator::from_name("auto").unwrap(), TurnSeparator::Auto);
+        assert!(TurnSeparator::from_name("tab").is_err());
+
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), true);
+        kb.set_turn_separator(TurnSeparator::None);
+        for turn in ["one", "two"] {
+            kb.update_transcript(turn).unwrap();
+            kb.finalize_transcript().unwrap();
+        }
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "onetwo");
+
+        // Auto skips the space when nothing was typed
+        let mut hardware = MockKeyboardHardware::new();
+        hardware.unsupported_chars.push('🙂');
+        let mut kb = VirtualKeyboard::new(hardware, true);
+        kb.update_transcript("🙂").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert!(kb.hardware.typed_chars.is_empty());
+
+        kb.set_turn_separator(TurnSeparator::Space);
+        kb.update_transcript("🙂").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.hardware.typed_chars, [' ']);
+    }
+
+    #[test]
+    fn test_fit_case() {
+        let test_cases = vec![
+            ("", "hello", "Hello"),
+            ("one.\n", "two", "Two"),
+            ("so he said \"stop.\" ", "then", "Then"),
+            ("and then ", "The end", "the end"),
+            ("and then ", "I said", "I said"),
+            ("and then ", "I'm done", "I'm done"),
+            ("on my ", "iPhone", "iPhone"),
+            ("at ", "NASA", "NASA"),
+            ("costs ", "23 dollars", "23 dollars"),
+            ("and then ", "It's late", "it's late"),
+            // Names keep their capital mid-sentence
+            ("and then ", "John said", "John said"),
+            ("we flew to ", "Paris today", "Paris today"),
+            ("thanks, ", "Sam.", "Sam."),
+            ("and ", "IT support", "IT support"),
+        ];
+
+        for (before, text, expected) in test_cases {
+            assert_eq!(fit_case(before, text), expected, "For: '{}' after '{}'", text, before);
+        }
+    }
+
+    #[test]
+    fn test_live_mode_ignores_paste_mode() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        kb.set_output_mode(OutputMode::Paste);
+
+        kb.update_transcript("hello").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert!(kb.hardware.pasted.is_empty());
+        assert_eq!(kb.hardware.typed_chars, ['h', 'e', 'l', 'l', 'o', ' ']);
+    }
+
     #[test]
     fn test_eager_eot_prevents_double_typing() {
         let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), true);
@@ -938,22 +2890,20 @@ mod tests {
         assert_eq!(kb.hardware.typed_chars.len(), 0); // Nothing typed yet in delayed mode
 
         // First finalization (eager EOT)
-        kb.finalize_transcript().unwrap();
+        kb.finalize_eager().unwrap();
         assert_eq!(
             kb.hardware.typed_chars,
             ['h', 'e', 'l', 'l', 'o', ' ', 'w', 'o', 'r', 'l', 'd', ' ']
         );
-        
-        // Mark that we've done eager EOT finalization
-        kb.mark_eager_eot_finalized();
 
-        // Second finalization (regular EndOfTurn) should not type anything
+        // Regular EndOfTurn with the same transcript should not type anything
+        kb.update_transcript("hello world").unwrap();
         kb.finalize_transcript().unwrap();
-        // Should still be the same - no additional typing
         assert_eq!(
             kb.hardware.typed_chars,
             ['h', 'e', 'l', 'l', 'o', ' ', 'w', 'o', 'r', 'l', 'd', ' ']
         );
+        assert_eq!(kb.hardware.backspace_count, 0);
     }
 
     #[test]
@@ -964,22 +2914,271 @@ mod tests {
         kb.update_transcript("hello").unwrap();
         assert_eq!(kb.hardware.typed_chars.len(), 0);
 
-        // Eager EOT - finalize and mark
-        kb.finalize_transcript().unwrap();
-        kb.mark_eager_eot_finalized();
+        kb.finalize_eager().unwrap();
         assert_eq!(kb.hardware.typed_chars, ['h', 'e', 'l', 'l', 'o', ' ']);
 
-        // Turn resumed - reset flag
-        kb.reset_eager_eot_flag();
+        // The turn goes on, and its transcript now covers both parts
+        kb.resume_turn().unwrap();
+        kb.update_transcript("hello world").unwrap();
+        assert_eq!(kb.hardware.typed_chars, ['h', 'e', 'l', 'l', 'o', ' ']);
 
-        // Continue speaking
-        kb.update_transcript("world").unwrap();
-        
-        // Final EndOfTurn should type the new content
+        // Final EndOfTurn types only the new content
         kb.finalize_transcript().unwrap();
         assert_eq!(
             kb.hardware.typed_chars,
             ['h', 'e', 'l', 'l', 'o', ' ', 'w', 'o', 'r', 'l', 'd', ' ']
         );
+        assert_eq!(kb.hardware.backspace_count, 1);
+        assert_eq!(kb.history.len(), 1);
+    }
+
+    #[test]
+    fn test_eager_eot_corrected_in_delay_mode() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), true);
+        kb.update_transcript("see you at ten").unwrap();
+        kb.finalize_eager().unwrap();
+
+        // The final transcript differs without the turn resuming
+        kb.update_transcript("see you at 10.").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "see you at 10. ");
+        assert_eq!(kb.hardware.backspace_count, 4);
+
+        // Undo takes back the corrected turn as a whole
+        assert!(kb.undo_last_turn().unwrap());
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "");
+    }
+
+    #[test]
+    fn test_eager_eot_resumed_in_live_mode() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        kb.update_transcript("hello").unwrap();
+        kb.finalize_eager().unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "hello ");
+
+        // Resuming takes the separator back, and the turn continues from its text
+        kb.resume_turn().unwrap();
+        kb.update_transcript("hello there").unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "hello there");
+        kb.update_transcript("hello there world").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "hello there world ");
+        assert_eq!(kb.hardware.backspace_count, 1);
+        assert_eq!(kb.history.len(), 1);
+    }
+
+    #[test]
+    fn test_eager_eot_corrected_in_live_mode() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        kb.update_transcript("the cat sat").unwrap();
+        kb.finalize_eager().unwrap();
+
+        // The same transcript at the end of the turn changes nothing
+        kb.update_transcript("the cat sat").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "the cat sat ");
+        assert_eq!(kb.hardware.backspace_count, 0);
+
+        // A different one only replaces what changed
+        kb.update_transcript("it ran").unwrap();
+        kb.finalize_eager().unwrap();
+        kb.update_transcript("it ran off").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "the cat sat it ran off ");
+        assert_eq!(kb.hardware.backspace_count, 1);
+    }
+
+    #[test]
+    fn test_eager_eot_command_is_kept() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        kb.update_transcript("hello new line").unwrap();
+        kb.finalize_eager().unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "hello\n");
+
+        // The command can't be taken back, so only the words after it are typed
+        kb.resume_turn().unwrap();
+        kb.update_transcript("hello new line and more").unwrap();
+        kb.finalize_transcript().unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "hello\nAnd more ");
+    }
+
+    fn turn_update(kb: &mut VirtualKeyboard<MockKeyboardHardware>, turn: u32, text: &str) {
+        let event = TurnEvent::Update {
+            transcript: text.to_string(),
+            confident: String::new(),
+        };
+        kb.handle_turn_event(turn, event).unwrap();
+    }
+
+    #[test]
+    fn test_turn_order_drops_late_updates() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        turn_update(&mut kb, 0, "hello");
+        kb.handle_turn_event(0, TurnEvent::EndOfTurn).unwrap();
+
+        // A stale update for the finished turn would otherwise be typed as a new turn
+        turn_update(&mut kb, 0, "hello there");
+        kb.handle_turn_event(0, TurnEvent::EndOfTurn).unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "hello ");
+
+        // So is one for a turn that was skipped over
+        turn_update(&mut kb, 2, "again");
+        turn_update(&mut kb, 1, "late");
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "hello again");
+    }
+
+    #[test]
+    fn test_turn_order_holds_back_next_turn() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        turn_update(&mut kb, 0, "one");
+        turn_update(&mut kb, 1, "two");
+        turn_update(&mut kb, 0, "one more");
+        turn_update(&mut kb, 1, "two three");
+        turn_update(&mut kb, 2, "four");
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "one more");
+
+        // Ending turn 0 types turn 1 up to its latest update; turn 2 waits for turn 1
+        kb.handle_turn_event(0, TurnEvent::EndOfTurn).unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "one more two three");
+        kb.handle_turn_event(1, TurnEvent::EndOfTurn).unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "one more two three four");
+        assert_eq!(kb.hardware.backspace_count, 0);
+    }
+
+    #[test]
+    fn test_turn_order_replays_ended_turns() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), true);
+        turn_update(&mut kb, 0, "first");
+        turn_update(&mut kb, 1, "second");
+        kb.handle_turn_event(1, TurnEvent::EndOfTurn).unwrap();
+        turn_update(&mut kb, 2, "third");
+        kb.handle_turn_event(0, TurnEvent::EndOfTurn).unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "first second ");
+
+        kb.handle_turn_event(2, TurnEvent::EndOfTurn).unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "first second third ");
+    }
+
+    #[test]
+    fn test_turn_order_timeout() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        kb.turns.timeout = Duration::ZERO;
+        turn_update(&mut kb, 0, "no end");
+
+        // Turn 0 never ends, so turn 1 finalizes it instead of waiting forever
+        turn_update(&mut kb, 1, "next");
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "no end next");
+        turn_update(&mut kb, 0, "no end at all");
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "no end next");
+    }
+
+    #[test]
+    fn test_turn_order_timeout_without_further_events() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        turn_update(&mut kb, 0, "no end");
+        assert_eq!(kb.turn_timeout(), None);
+
+        // Turn 1 arrives in full while turn 0 is still within its timeout
+        turn_update(&mut kb, 1, "next");
+        kb.handle_turn_event(1, TurnEvent::EndOfTurn).unwrap();
+        assert!(kb.turn_timeout().is_some_and(|left| left > Duration::ZERO));
+        kb.expire_turns().unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "no end");
+
+        // Once it times out, turn 0 is finalized and turn 1 typed with no other event
+        kb.turns.timeout = Duration::ZERO;
+        assert_eq!(kb.turn_timeout(), Some(Duration::ZERO));
+        kb.expire_turns().unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "no end next ");
+        assert_eq!(kb.turn_timeout(), None);
+    }
+
+    #[test]
+    fn test_turn_order_new_session() {
+        let mut kb = VirtualKeyboard::new(MockKeyboardHardware::new(), false);
+        turn_update(&mut kb, 0, "one");
+        kb.handle_turn_event(0, TurnEvent::EndOfTurn).unwrap();
+        turn_update(&mut kb, 1, "cut off");
+        turn_update(&mut kb, 2, "held");
+
+        // The new connection counts from 0 again; what the old one left is final
+        kb.start_session().unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "one cut off held ");
+        turn_update(&mut kb, 0, "two");
+        kb.handle_turn_event(0, TurnEvent::EndOfTurn).unwrap();
+        assert_eq!(kb.hardware.typed_chars.iter().collect::<String>(), "one cut off held two ");
+    }
+
+    // Events written to a pipe, read once its write end is closed
+    fn read_events(fd: i32) -> Vec<InputEvent> {
+        let mut file = unsafe { std::fs::File::from_raw_fd(fd) };
+        let mut bytes = Vec::new();
+        std::io::Read::read_to_end(&mut file, &mut bytes).unwrap();
+        let size = std::mem::size_of::<InputEvent>();
+        bytes
+            .chunks(size)
+            .map(|chunk| unsafe { std::ptr::read_unaligned(chunk.as_ptr() as *const InputEvent) })
+            .collect()
+    }
+
+    fn pipe() -> [i32; 2] {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        fds
+    }
+
+    #[test]
+    fn test_release_held_keys() {
+        let fds = pipe();
+        let held = HeldKeys {
+            fd: AtomicI32::new(fds[1]),
+            down: [KEY_UP; 256],
+        };
+        held.set(KEY_LEFTSHIFT, true);
+        held.set(KEY_A, true);
+        held.set(KEY_A, false);
+        held.release_all();
+        held.fd.store(-1, Ordering::SeqCst);
+        unsafe { libc::close(fds[1]) };
+
+        // Only the key still down is released, followed by a sync
+        let events = read_events(fds[0]);
+        assert_eq!(events.len(), 2);
+        assert_eq!((events[0].type_, events[0].code, events[0].value), (EV_KEY, KEY_LEFTSHIFT, 0));
+        assert_eq!((events[1].type_, events[1].code), (EV_SYN, SYN_REPORT));
+        assert!(!held.down[KEY_LEFTSHIFT as usize].load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_release_held_keys_per_device() {
+        let (typing_fds, passthrough_fds) = (pipe(), pipe());
+        let typing = HeldKeys::register(typing_fds[1]).unwrap();
+        let passthrough = HeldKeys::register(passthrough_fds[1]).unwrap();
+
+        // A failed character releases the typing keyboard's shift, not the key the user
+        // holds on the passthrough device
+        typing.set(KEY_LEFTSHIFT, true);
+        passthrough.set(KEY_LEFTCTRL, true);
+        typing.release_all();
+        assert!(passthrough.down[KEY_LEFTCTRL as usize].load(Ordering::SeqCst));
+
+        // Dropping one device leaves the other registered for the signal handler
+        typing.unregister();
+        passthrough.set(KEY_LEFTSHIFT, true);
+        release_held_keys();
+        passthrough.unregister();
+        unsafe {
+            libc::close(typing_fds[1]);
+            libc::close(passthrough_fds[1]);
+        }
+
+        let key = |events: Vec<InputEvent>| -> Vec<(u16, u16, i32)> {
+            events.iter().filter(|e| e.type_ == EV_KEY).map(|e| (e.type_, e.code, e.value)).collect()
+        };
+        assert_eq!(key(read_events(typing_fds[0])), [(EV_KEY, KEY_LEFTSHIFT, 0)]);
+        assert_eq!(
+            key(read_events(passthrough_fds[0])),
+            [(EV_KEY, KEY_LEFTCTRL, 0), (EV_KEY, KEY_LEFTSHIFT, 0)]
+        );
     }
 }
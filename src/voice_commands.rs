@@ -0,0 +1,547 @@
+use anyhow::{anyhow, bail, Result};
+use std::collections::HashMap;
+
+use crate::input_event::*;
+
+/// A key press while holding zero or more modifiers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyChord {
+    pub modifiers: Vec<u16>,
+    pub key: u16,
+}
+
+impl KeyChord {
+    /// Parse a chord such as "enter", "ctrl+a" or "ctrl+shift+t"
+    pub fn parse(chord: &str) -> Result<Self> {
+        let mut keys = Vec::new();
+        for name in chord.split('+') {
+            let keycode =
+                key_from_name(name.trim()).ok_or_else(|| anyhow!("unknown key '{}'", name.trim()))?;
+            keys.push(keycode);
+        }
+
+        let key = keys.pop().ok_or_else(|| anyhow!("empty key chord"))?;
+        if !keys.iter().all(|&k| is_modifier(k)) {
+            bail!("'{}': only the last key of a chord may be a non-modifier", chord);
+        }
+        Ok(Self {
+            modifiers: keys,
+            key,
+        })
+    }
+
+    /// A letter or digit without modifiers, which "press" only fires at the end of a turn
+    /// since ordinary dictation says "press a button" or "press one more time"
+    fn is_bare_character(&self) -> bool {
+        self.modifiers.is_empty()
+            && ('a'..='z')
+                .chain('0'..='9')
+                .any(|c| key_from_name(&c.to_string()) == Some(self.key))
+    }
+}
+
+// Spoken key names that differ from the names `key_from_name` knows
+const SPOKEN_KEYS: &[(&str, &str)] = &[
+    ("page up", "pageup"),
+    ("page down", "pagedown"),
+    ("caps lock", "capslock"),
+    ("back space", "backspace"),
+    ("alt gr", "altgr"),
+    ("up arrow", "up"),
+    ("down arrow", "down"),
+    ("left arrow", "left"),
+    ("right arrow", "right"),
+    ("period", "dot"),
+    ("zero", "0"),
+    ("one", "1"),
+    ("two", "2"),
+    ("three", "3"),
+    ("four", "4"),
+    ("five", "5"),
+    ("six", "6"),
+    ("seven", "7"),
+    ("eight", "8"),
+    ("nine", "9"),
+];
+
+// Number words for function keys ("f four")
+const FUNCTION_KEY_NUMBERS: &[&str] = &[
+    "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten", "eleven",
+    "twelve",
+];
+
+/// Parse a spoken chord such as "control shift t", "alt f four" or "control-c":
+/// any number of modifiers followed by one key
+/// Returns the chord and how many of `words` it spans
+pub fn parse_spoken_chord(words: &[&str]) -> Option<(KeyChord, usize)> {
+    // Split "control-shift-t" into its parts, remembering the word each came from
+    let parts: Vec<(usize, &str)> = words
+        .iter()
+        .enumerate()
+        .flat_map(|(i, w)| w.split(['-', '+']).filter(|p| !p.is_empty()).map(move |p| (i, p)))
+        .collect();
+    let names = |from: usize| -> Vec<&str> { parts[from..].iter().map(|&(_, p)| p).collect() };
+
+    let mut modifiers = Vec::new();
+    let mut pos = 0;
+    while pos < parts.len() {
+        let (keycode, len) = spoken_key(&names(pos))?;
+        pos += len;
+
+        // A modifier followed by another key is held; otherwise it is the key itself
+        if is_modifier(keycode) && pos < parts.len() && spoken_key(&names(pos)).is_some() {
+            modifiers.push(keycode);
+            continue;
+        }
+
+        // The chord must end on a word boundary ("control-c" but not "control-cat")
+        let word = parts[pos - 1].0;
+        if parts.get(pos).is_some_and(|&(w, _)| w == word) {
+            return None;
+        }
+        return Some((KeyChord { modifiers, key: keycode }, word + 1));
+    }
+    None
+}
+
+// Key named at the start of `names`, and how many names it spans
+fn spoken_key(names: &[&str]) -> Option<(u16, usize)> {
+    if let [first, second, ..] = names {
+        let pair = format!("{} {}", first, second);
+        if let Some(&(_, name)) = SPOKEN_KEYS.iter().find(|&&(spoken, _)| spoken == pair) {
+            return key_from_name(name).map(|k| (k, 2));
+        }
+        if *first == "f" {
+            let number = FUNCTION_KEY_NUMBERS
+                .iter()
+                .position(|n| n == second)
+                .map(|i| i + 1)
+                .or_else(|| second.parse().ok());
+            if let Some(number) = number {
+                return key_from_name(&format!("f{}", number)).map(|k| (k, 2));
+            }
+        }
+    }
+
+    let name = *names.first()?;
+    let name = SPOKEN_KEYS
+        .iter()
+        .find(|&&(spoken, _)| spoken == name)
+        .map_or(name, |&(_, name)| name);
+    key_from_name(name).map(|k| (k, 1))
+}
+
+/// What a spoken command does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandAction {
+    /// Press keys/chords in order
+    Keys(Vec<KeyChord>),
+    /// Remove the text dictated in the current turn
+    DeleteThat,
+    /// Press backspace, repeated by an optional spoken count ("backspace three")
+    Backspace,
+    /// Take back the current turn, or the previous one if nothing was typed yet
+    UndoTurn,
+    /// Switch programmer dictation on or off
+    CodeMode(bool),
+}
+
+impl CommandAction {
+    /// Parse a config value: "delete-that", "undo-turn", "backspace", "code-mode-on",
+    /// "code-mode-off", or space separated chords such as "enter enter" or "ctrl+shift+z"
+    pub fn parse(action: &str) -> Result<Self> {
+        match action.trim() {
+            "delete-that" => Ok(CommandAction::DeleteThat),
+            "undo-turn" => Ok(CommandAction::UndoTurn),
+            "backspace" => Ok(CommandAction::Backspace),
+            "code-mode-on" => Ok(CommandAction::CodeMode(true)),
+            "code-mode-off" => Ok(CommandAction::CodeMode(false)),
+            "" => Err(anyhow!("empty command action")),
+            chords => Ok(CommandAction::Keys(
+                chords
+                    .split_whitespace()
+                    .map(KeyChord::parse)
+                    .collect::<Result<_>>()?,
+            )),
+        }
+    }
+}
+
+/// A command found in a transcript
+/// `start..end` is the byte range to drop from the text, including the whitespace
+/// before the command and any punctuation after it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandMatch {
+    pub start: usize,
+    pub end: usize,
+    pub action: CommandAction,
+    /// Repeat count spoken after the command (1 if none)
+    pub count: usize,
+}
+
+// Built-in phrases; config entries are merged on top
+const DEFAULT_COMMANDS: &[(&str, &str)] = &[
+    ("enter", "enter"),
+    ("press enter", "enter"),
+    ("new line", "enter"),
+    ("new paragraph", "enter enter"),
+    ("tab", "tab"),
+    ("delete that", "delete-that"),
+    ("scratch that", "undo-turn"),
+    ("undo that", "undo-turn"),
+    ("backspace", "backspace"),
+    ("select all", "ctrl+a"),
+    ("undo", "ctrl+z"),
+    ("redo", "ctrl+shift+z"),
+    ("go to end of line", "end"),
+    ("go to start of line", "home"),
+    ("go to beginning of line", "home"),
+    ("code mode on", "code-mode-on"),
+    ("code mode off", "code-mode-off"),
+    ("prose mode", "code-mode-off"),
+];
+
+// Built-in phrases that are everyday words, run only when they are all that was said
+const WHOLE_TURN_PHRASES: &[&str] = &["backspace", "redo", "tab", "undo"];
+
+// Largest spoken count accepted after a repeatable command
+const MAX_COUNT: usize = 50;
+
+/// Spoken phrase to action table used by the virtual keyboard
+#[derive(Debug, Clone)]
+pub struct VoiceCommands {
+    commands: HashMap<Vec<String>, CommandAction>,
+    mid_turn: bool,
+    delay_mode: bool,
+}
+
+impl Default for VoiceCommands {
+    fn default() -> Self {
+        let commands = DEFAULT_COMMANDS
+            .iter()
+            .map(|&(phrase, action)| {
+                let action = CommandAction::parse(action).expect("invalid built-in command");
+                (phrase_words(phrase), action)
+            })
+            .collect();
+        Self {
+            commands,
+            mid_turn: false,
+            delay_mode: false,
+        }
+    }
+}
+
+impl VoiceCommands {
+    /// Add or replace a command; an empty action removes the phrase
+    pub fn set(&mut self, phrase: &str, action: &str) -> Result<()> {
+        let words = phrase_words(phrase);
+        if words.is_empty() {
+            bail!("empty command phrase");
+        }
+        if action.trim().is_empty() {
+            self.commands.remove(&words);
+            return Ok(());
+        }
+        let action = CommandAction::parse(action)
+            .map_err(|e| anyhow!("command '{}': {}", phrase, e))?;
+        self.commands.insert(words, action);
+        Ok(())
+    }
+
+    /// Also run commands spoken in the middle of a turn, as soon as speech continues after them
+    pub fn set_mid_turn(&mut self, mid_turn: bool) {
+        self.mid_turn = mid_turn;
+    }
+
+    pub fn mid_turn(&self) -> bool {
+        self.mid_turn
+    }
+
+    /// Also run commands when input is delayed until the end of the turn
+    pub fn set_delay_mode(&mut self, delay_mode: bool) {
+        self.delay_mode = delay_mode;
+    }
+
+    pub fn delay_mode(&self) -> bool {
+        self.delay_mode
+    }
+
+    /// Whether a command starts at `words[0]`; words must be lowercase without punctuation
+    pub fn starts_command(&self, words: &[&str]) -> bool {
+        words.first() == Some(&"press")
+            || self.commands.keys().any(|phrase| {
+                phrase.len() <= words.len() && phrase.iter().zip(words).all(|(p, w)| p == w)
+            })
+    }
+
+    /// Command at the very end of `text`, followed by nothing but punctuation/whitespace
+    pub fn match_trailing(&self, text: &str) -> Option<CommandMatch> {
+        let tokens = tokenize(text);
+        let words: Vec<usize> = (0..tokens.len()).filter(|&i| !tokens[i].2.is_empty()).collect();
+
+        (0..words.len())
+            .filter_map(|first| {
+                let (action, len, count) = self.match_at(&tokens, &words[first..], first == 0)?;
+                (first + len == words.len()).then(|| CommandMatch {
+                    start: word_boundary(&tokens, words[first]),
+                    end: text.len(),
+                    action,
+                    count,
+                })
+            })
+            .next()
+    }
+
+    /// First command in `text` that has at least one more word after it
+    pub fn match_inner(&self, text: &str) -> Option<CommandMatch> {
+        let tokens = tokenize(text);
+        let words: Vec<usize> = (0..tokens.len()).filter(|&i| !tokens[i].2.is_empty()).collect();
+
+        (0..words.len()).find_map(|first| {
+            let (action, len, count) = self.match_at(&tokens, &words[first..], false)?;
+            let next = *words.get(first + len)?;
+            Some(CommandMatch {
+                start: word_boundary(&tokens, words[first]),
+                end: tokens[next].0,
+                action,
+                count,
+            })
+        })
+    }
+
+    /// Longest command starting at `words[0]`: (action, words used, count); phrases in
+    /// `WHOLE_TURN_PHRASES` only when `whole` says the command would be all of the text
+    fn match_at(
+        &self,
+        tokens: &[(usize, usize, String)],
+        words: &[usize],
+        whole: bool,
+    ) -> Option<(CommandAction, usize, usize)> {
+        let word = |i: usize| words.get(i).map(|&t| tokens[t].2.as_str());
+
+        // "press <chord>" fires any shortcut, e.g. "press control shift t"; a bare letter
+        // or digit only when nothing follows it
+        let press = (word(0) == Some("press"))
+            .then(|| {
+                let rest: Vec<&str> = (1..words.len()).filter_map(word).collect();
+                parse_spoken_chord(&rest)
+            })
+            .flatten()
+            .filter(|(chord, len)| !chord.is_bare_character() || len + 1 == words.len())
+            .map(|(chord, len)| (CommandAction::Keys(vec![chord]), len + 1, 1));
+
+        self.commands
+            .iter()
+            .filter(|(phrase, _)| whole || !matches!(&phrase[..], [w] if WHOLE_TURN_PHRASES.contains(&w.as_str())))
+            .filter(|(phrase, _)| phrase.iter().enumerate().all(|(i, w)| word(i) == Some(w.as_str())))
+            .map(|(phrase, action)| {
+                let count = match action {
+                    CommandAction::Backspace => word(phrase.len()).and_then(parse_count),
+                    _ => None,
+                };
+                match count {
+                    Some(count) => (action.clone(), phrase.len() + 1, count),
+                    None => (action.clone(), phrase.len(), 1),
+                }
+            })
+            .chain(press)
+            .max_by_key(|&(_, len, _)| len)
+    }
+}
+
+fn phrase_words(phrase: &str) -> Vec<String> {
+    phrase.split_whitespace().map(normalize_word).collect()
+}
+
+// Lowercase a word and strip surrounding punctuation ("Enter." -> "enter")
+fn normalize_word(word: &str) -> String {
+    word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase()
+}
+
+// Whitespace separated tokens as (start, end, normalized word); punctuation-only
+// tokens get an empty word
+fn tokenize(text: &str) -> Vec<(usize, usize, String)> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+        match (start, c.is_whitespace()) {
+            (None, false) => start = Some(i),
+            (Some(s), true) => {
+                tokens.push((s, i, normalize_word(&text[s..i])));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+// Where text removed for a command starting at `token` begins: right after the
+// previous token, so the whitespace in between goes too
+fn word_boundary(tokens: &[(usize, usize, String)], token: usize) -> usize {
+    if token == 0 {
+        0
+    } else {
+        tokens[token - 1].1
+    }
+}
+
+fn parse_count(word: &str) -> Option<usize> {
+    const NUMBERS: &[&str] = &[
+        "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+        "eleven", "twelve", "thirteen", "fourteen", "fifteen", "sixteen", "seventeen",
+        "eighteen", "nineteen", "twenty",
+    ];
+    let count = match word {
+        "once" => 1,
+        "twice" => 2,
+        _ => NUMBERS
+            .iter()
+            .position(|&n| n == word)
+            .or_else(|| word.parse().ok())?,
+    };
+    (1..=MAX_COUNT).contains(&count).then_some(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_actions() {
+        assert_eq!(
+            CommandAction::parse("ctrl+shift+t").unwrap(),
+            CommandAction::Keys(vec![KeyChord {
+                modifiers: vec![KEY_LEFTCTRL, KEY_LEFTSHIFT],
+                key: KEY_T,
+            }])
+        );
+        assert_eq!(
+            CommandAction::parse("Enter enter").unwrap(),
+            CommandAction::Keys(vec![
+                KeyChord { modifiers: vec![], key: KEY_ENTER },
+                KeyChord { modifiers: vec![], key: KEY_ENTER },
+            ])
+        );
+        assert_eq!(CommandAction::parse("delete-that").unwrap(), CommandAction::DeleteThat);
+        assert_eq!(CommandAction::parse("code-mode-off").unwrap(), CommandAction::CodeMode(false));
+        assert!(CommandAction::parse("ctrl+nope").is_err());
+        assert!(CommandAction::parse("a+b").is_err());
+    }
+
+    #[test]
+    fn test_match_trailing() {
+        let commands = VoiceCommands::default();
+
+        let m = commands.match_trailing("hello, New line.").unwrap();
+        assert_eq!((m.start, m.end), (6, 16));
+        assert_eq!(m.action, CommandAction::parse("enter").unwrap());
+
+        let m = commands.match_trailing("Backspace three.").unwrap();
+        assert_eq!((m.start, m.action, m.count), (0, CommandAction::Backspace, 3));
+        let m = commands.match_trailing("backspace").unwrap();
+        assert_eq!((m.start, m.count), (0, 1));
+
+        // Everyday words only run as commands when they are all that was said
+        assert!(commands.match_trailing("oops backspace three").is_none());
+        assert!(commands.match_trailing("I hit backspace").is_none());
+        assert!(commands.match_trailing("there is nothing to undo").is_none());
+        assert!(commands.match_trailing("open a new tab.").is_none());
+        let m = commands.match_trailing("Undo!").unwrap();
+        assert_eq!(m.action, CommandAction::parse("ctrl+z").unwrap());
+
+        // The longest phrase wins
+        let m = commands.match_trailing("press enter").unwrap();
+        assert_eq!(m.start, 0);
+
+        assert!(commands.match_trailing("a new line of code").is_none());
+        assert!(commands.match_trailing("the undone work").is_none());
+    }
+
+    #[test]
+    fn test_match_inner() {
+        let commands = VoiceCommands::default();
+
+        let m = commands.match_inner("first new line second").unwrap();
+        assert_eq!((m.start, m.end), (5, 15));
+        assert!(commands.match_inner("first tab second").is_none());
+        assert!(commands.match_inner("undo the last change").is_none());
+        let m = commands.match_inner("dear Sam, new paragraph. Thanks").unwrap();
+        assert_eq!((m.start, m.end), (9, 25));
+
+        // A command at the end is left for the end of the turn
+        assert!(commands.match_inner("hello new line").is_none());
+    }
+
+    #[test]
+    fn test_parse_spoken_chord() {
+        let chord = |modifiers: &[u16], key| KeyChord { modifiers: modifiers.to_vec(), key };
+        let test_cases = vec![
+            ("control shift t", Some((chord(&[KEY_LEFTCTRL, KEY_LEFTSHIFT], KEY_T), 3))),
+            ("alt f4", Some((chord(&[KEY_LEFTALT], KEY_F4), 2))),
+            ("alt f four", Some((chord(&[KEY_LEFTALT], KEY_F4), 3))),
+            ("escape", Some((chord(&[], KEY_ESC), 1))),
+            ("control-c please", Some((chord(&[KEY_LEFTCTRL], KEY_C), 1))),
+            ("super page down", Some((chord(&[KEY_LEFTMETA], KEY_PAGEDOWN), 3))),
+            ("shift", Some((chord(&[], KEY_LEFTSHIFT), 1))),
+            ("control the door", Some((chord(&[], KEY_LEFTCTRL), 1))),
+            ("control-cat", None),
+            ("the button", None),
+        ];
+
+        for (input, expected) in test_cases {
+            let words: Vec<&str> = input.split(' ').collect();
+            assert_eq!(parse_spoken_chord(&words), expected, "For: '{}'", input);
+        }
+    }
+
+    #[test]
+    fn test_match_press_commands() {
+        let commands = VoiceCommands::default();
+
+        let m = commands.match_trailing("Press Control Shift T.").unwrap();
+        assert_eq!(
+            m.action,
+            CommandAction::Keys(vec![KeyChord { modifiers: vec![KEY_LEFTCTRL, KEY_LEFTSHIFT], key: KEY_T }])
+        );
+        let m = commands.match_inner("press alt tab and then").unwrap();
+        assert_eq!(m.end, "press alt tab ".len());
+
+        assert!(commands.match_trailing("press the button").is_none());
+        assert!(commands.match_trailing("press control the door").is_none());
+
+        // Bare letters and digits only at the end of a turn
+        assert!(commands.match_inner("press a button and wait").is_none());
+        assert!(commands.match_trailing("press a button").is_none());
+        assert!(commands.match_inner("press one more time").is_none());
+        assert!(commands.match_trailing("press one more time").is_none());
+        let m = commands.match_trailing("then press one").unwrap();
+        assert_eq!(m.action, CommandAction::Keys(vec![KeyChord { modifiers: vec![], key: KEY_1 }]));
+        let m = commands.match_inner("press control a and delete").unwrap();
+        assert_eq!(m.action, CommandAction::parse("ctrl+a").unwrap());
+    }
+
+    #[test]
+    fn test_config_overrides() {
+        let mut commands = VoiceCommands::default();
+        commands.set("save file", "ctrl+s").unwrap();
+        commands.set("tab", "").unwrap();
+
+        let m = commands.match_trailing("Save file!").unwrap();
+        assert_eq!(m.action, CommandAction::parse("ctrl+s").unwrap());
+        assert!(commands.match_trailing("tab").is_none());
+        assert!(commands.set("close", "ctrl+w+q").is_err());
+    }
+
+    #[test]
+    fn test_starts_command() {
+        let commands = VoiceCommands::default();
+        assert!(commands.starts_command(&["new", "line"]));
+        assert!(commands.starts_command(&["press", "control", "s"]));
+        assert!(commands.starts_command(&["code", "mode", "off", "please"]));
+        assert!(!commands.starts_command(&["new"]));
+        assert!(!commands.starts_command(&["user", "id"]));
+        assert!(!commands.starts_command(&[]));
+    }
+}
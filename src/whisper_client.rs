@@ -2,7 +2,13 @@ use anyhow::{Context, Result};
 use reqwest::multipart;
 use serde::{Deserialize, Serialize};
 use std::env;
-use tracing::{debug, info};
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tracing::{debug, error, info};
+
+use crate::audio_input;
+use crate::speech_recognizer::{EventSink, RecognizerSession, SpeechRecognizer, StatusSink};
+use crate::stt_client::{SttEvent, TranscriptionResult};
 
 pub const WHISPER_API_URL: &str = "https://api.openai.com/v1/audio/transcriptions";
 
@@ -11,15 +17,17 @@ pub struct WhisperResponse {
     pub text: String,
 }
 
+#[derive(Clone)]
 pub struct WhisperClient {
     api_url: String,
     api_key: Option<String>,
     language: String,
     model: String,
+    sample_rate: u32,
 }
 
 impl WhisperClient {
-    pub fn new(api_url: Option<&str>, language: &str, model: &str) -> Self {
+    pub fn new(api_url: Option<&str>, language: &str, model: &str, sample_rate: u32) -> Self {
         let api_key = env::var("OPENAI_API_KEY").ok();
 
         if api_key.is_none() {
@@ -31,6 +39,7 @@ impl WhisperClient {
             api_key,
             language: language.to_string(),
             model: model.to_string(),
+            sample_rate,
         }
     }
 
@@ -129,6 +138,89 @@ impl WhisperClient {
     }
 }
 
+impl SpeechRecognizer for WhisperClient {
+    fn name(&self) -> &'static str {
+        "REST (OpenAI Whisper)"
+    }
+
+    fn streams(&self) -> bool {
+        false
+    }
+
+    fn start_session(
+        &mut self,
+        runtime: &Handle,
+        on_event: EventSink,
+        _on_status: StatusSink,
+    ) -> Result<Box<dyn RecognizerSession>> {
+        info!("Starting REST mode audio recording (buffering)...");
+        Ok(Box::new(RecordingSession {
+            client: self.clone(),
+            audio: Vec::new(),
+            runtime: runtime.clone(),
+            on_event,
+        }))
+    }
+}
+
+/// A session of `WhisperClient`, which buffers all audio and sends it once finished
+struct RecordingSession {
+    client: WhisperClient,
+    audio: Vec<u8>,
+    runtime: Handle,
+    on_event: EventSink,
+}
+
+impl RecordingSession {
+    fn transcribe(&self) {
+        info!("Sending {} bytes of audio to Whisper API...", self.audio.len());
+        if self.audio.is_empty() {
+            info!("No audio data recorded, skipping transcription");
+            return;
+        }
+
+        match self.runtime.block_on(self.client.transcribe(&self.audio, self.client.sample_rate)) {
+            Ok(text) if text.is_empty() => info!("Transcription is empty, skipping keyboard input"),
+            Ok(text) => {
+                // The recording is a single turn: its text, then its end. The session's
+                // Closed event starts the numbering over, so it is always turn 0
+                (self.on_event)(SttEvent::Update(TranscriptionResult {
+                    transcript: text,
+                    end_of_turn_confidence: 1.0,
+                    ..Default::default()
+                }));
+                (self.on_event)(SttEvent::EndOfTurn(TranscriptionResult {
+                    end_of_turn_confidence: 1.0,
+                    ..Default::default()
+                }));
+            }
+            Err(e) => {
+                error!("Failed to transcribe audio: {}", e);
+                (self.on_event)(SttEvent::Error {
+                    code: "transcription_failed".to_string(),
+                    description: format!("{:#}", e),
+                });
+            }
+        }
+    }
+}
+
+impl RecognizerSession for RecordingSession {
+    fn push_audio(&mut self, samples: &[f32]) {
+        self.audio.extend_from_slice(&audio_input::to_pcm16(samples));
+    }
+
+    // The request is the transcription itself, so the timeout does not cut it short
+    fn finish(self: Box<Self>, _timeout: Duration) {
+        self.transcribe();
+        (self.on_event)(SttEvent::Closed);
+    }
+
+    fn cancel(self: Box<Self>) {
+        info!("Dropping {} bytes of recorded audio", self.audio.len());
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;